@@ -1,12 +1,120 @@
+use std::{io::IsTerminal, process::ExitCode};
 
-use kgemu::{compile, definitions::language, prelude::*};
+use kgemu::{compile::expression, prelude::*};
 
-fn main() {
-	
+fn main() -> ExitCode {
+	// kgemu [--color auto|always|never] [--device device.json] [-I dir]... [-D name[=value]]... [--symbols] [--nm] [-c] [--listing out.lst] [-T script] [--map out.map] [-o out [--format name]] [file]...
+	// More than one file, a linker script, a map or an object links them into one program
+	let mut files = Vec::new();
+	let mut script_file = None;
+	let mut device_file = None;
+	let mut include_paths = Vec::new();
+	let mut defines = Vec::new();
+	let (mut dump_symbols, mut nm) = (false, false);
+	let (mut out_file, mut out_format) = (None, None);
+	let mut relocatable = false;
+	let mut listing_file = None;
+	let mut map_file = None;
+	let mut color = std::io::stderr().is_terminal() && std::env::var_os("NO_COLOR").is_none();
+	let mut args = std::env::args().skip(1);
+	while let Some(arg) = args.next() {
+		match arg.as_str() {
+			"--color" => match args.next().as_deref() {
+				Some("always") => color = true,
+				Some("never") => color = false,
+				Some("auto") => {}
+				_ => {
+					eprintln!("--color takes auto, always or never");
+					return ExitCode::FAILURE;
+				}
+			},
+			"--device" => device_file = args.next(),
+			"-I" => include_paths.extend(args.next()),
+			"-D" => {
+				let define = args.next().unwrap_or_default();
+				let (name, value) = define.split_once('=').unwrap_or((&define, "1"));
+				match expression::evaluate(value, &|_| None) {
+					Ok(value) if !name.is_empty() => defines.push((name.to_string(), value)),
+					_ => {
+						eprintln!("-D takes name=value with a number value, not \"{}\"", define);
+						return ExitCode::FAILURE;
+					}
+				}
+			}
+			"--symbols" => dump_symbols = true,
+			"--nm" => nm = true,
+			"-c" => relocatable = true,
+			"--listing" => listing_file = args.next(),
+			"--map" => map_file = args.next(),
+			"-T" => script_file = args.next(),
+			"-o" => out_file = args.next(),
+			"--format" => match args.next().unwrap_or_default().parse::<OutputFormat>() {
+				Ok(format) => out_format = Some(format),
+				Err(e) => {
+					eprintln!("{}", e);
+					return ExitCode::FAILURE;
+				}
+			},
+			_ => files.push(arg),
+		}
+	}
+	if files.len() > 1 || script_file.is_some() || map_file.is_some() || files.iter().any(|f| OutputFormat::from_file_name(f) == OutputFormat::Elf) {
+		let options = Options { device_file, include_paths, defines, color };
+		return link(&files, script_file, (out_file, out_format), map_file, &options);
+	}
+	let file_name = files.pop().unwrap_or("./sample_assembly_code/simple.thumb".to_string());
 
-	let thumb_def: language::LanguageDefinition = Default::default();
-	let mut parsed_simple: compile::parse_code::ParsedCode = Default::default();
-	parsed_simple.parse_from_file("./sample_assembly_code/command_tests.thumb", thumb_def);
+	let mut complier: Complier = Default::default();
+	complier.set_relocatable(relocatable);
+	for path in &include_paths {
+		complier.add_include_path(path);
+	}
+	for (name, value) in &defines {
+		complier.define(name, *value);
+	}
+	if let Some(device_file) = device_file {
+		if let Err(e) = complier.load_device_from_file(&device_file) {
+			eprint!("{}", complier.render_error(&e.into(), color));
+			return ExitCode::FAILURE;
+		}
+	}
+	let result = complier.compile_from_file(&file_name);
+	if let Err(e @ KgemuError::Io { .. }) = &result {
+		eprint!("{}", complier.render_error(e, color));
+		return ExitCode::FAILURE;
+	}
+	if !complier.get_warnings().is_empty() {
+		eprint!("{}", complier.render_warnings(color));
+	}
+	if let (Some(listing_file), Ok(())) = (&listing_file, &result) {
+		if let Err(e) = complier.write_listing(listing_file) {
+			eprint!("{}", complier.render_error(&e, color));
+			return ExitCode::FAILURE;
+		}
+	}
+	// Only the symbols, for other tools to read
+	if dump_symbols || nm {
+		match &result {
+			Ok(()) if nm => print!("{}", complier.get_symbols().to_nm(complier.get_device())),
+			Ok(()) => print!("{}", complier.get_symbols()),
+			Err(e) => {
+				eprint!("{}", complier.render_error(e, color));
+				return ExitCode::FAILURE;
+			}
+		}
+		return ExitCode::SUCCESS;
+	}
+	// Only the rom image, written to a file
+	if let Some(out_file) = out_file {
+		// An object is always ELF, whatever its extension
+		let out_format = out_format.or(relocatable.then_some(OutputFormat::Elf));
+		if let Err(e) = result.and_then(|_| complier.write_output(&out_file, out_format)) {
+			eprint!("{}", complier.render_error(&e, color));
+			return ExitCode::FAILURE;
+		}
+		return ExitCode::SUCCESS;
+	}
+	let parsed_simple = complier.get_parsed_code();
 
 	println!("File Name: {}", parsed_simple.file_name);
 	println!("File Length: {}", parsed_simple.file_size);
@@ -17,12 +125,107 @@ fn main() {
 		for section in &line.sections {
 			print!(" {:?} ", section)
 		}
-		println!("");
+		println!();
 	}
 
-	for c in parsed_simple.commands {
+	for c in &parsed_simple.commands {
 		println!("{:?}", c);
 	}
 
+	let status = match result {
+		Ok(()) => {
+			for section in complier.get_sections() {
+				let load = section.load_address.map_or("-".to_string(), |a| format!("{:08x}", a));
+				println!("{:<10} {:08x} load {} size {}", section.name, section.address, load, section.size);
+			}
+			for (i, byte) in complier.get_bin().iter().enumerate() {
+				print!("{:02x}{}", byte, if i % 2 == 1 { " " } else { "" });
+			}
+			println!();
+			ExitCode::SUCCESS
+		}
+		Err(e) => {
+			eprint!("{}", complier.render_error(&e, color));
+			ExitCode::FAILURE
+		}
+	};
+
 	println!("End of file");
-}
\ No newline at end of file
+	status
+}
+
+struct Options {
+	device_file: Option<String>,
+	include_paths: Vec<String>,
+	defines: Vec<(String, i64)>,
+	color: bool,
+}
+
+/// Compile every source to an object, link them with the objects given and write the program or print its map
+fn link(files: &[String], script_file: Option<String>, (out_file, out_format): (Option<String>, Option<OutputFormat>), map_file: Option<String>, options: &Options) -> ExitCode {
+	let mut linker: Linker = Default::default();
+	if let Some(device_file) = &options.device_file {
+		if let Err(e) = linker.load_device_from_file(device_file) {
+			eprint!("{}", linker.render_error(&e.into(), options.color));
+			return ExitCode::FAILURE;
+		}
+	}
+	if let Some(script_file) = script_file {
+		if let Err(e) = linker.load_script_from_file(&script_file) {
+			eprint!("{}", linker.render_error(&e, options.color));
+			return ExitCode::FAILURE;
+		}
+	}
+	for file_name in files {
+		if OutputFormat::from_file_name(file_name) == OutputFormat::Elf {
+			if let Err(e) = linker.add_object_file(file_name) {
+				eprint!("{}", linker.render_error(&e, options.color));
+				return ExitCode::FAILURE;
+			}
+			continue;
+		}
+		let mut complier: Complier = Default::default();
+		complier.set_relocatable(true);
+		for path in &options.include_paths {
+			complier.add_include_path(path);
+		}
+		for (name, value) in &options.defines {
+			complier.define(name, *value);
+		}
+		if let Some(device_file) = &options.device_file {
+			if let Err(e) = complier.load_device_from_file(device_file) {
+				eprint!("{}", complier.render_error(&e.into(), options.color));
+				return ExitCode::FAILURE;
+			}
+		}
+		let result = complier.compile_from_file(file_name);
+		if !complier.get_warnings().is_empty() {
+			eprint!("{}", complier.render_warnings(options.color));
+		}
+		if let Err(e) = result.and_then(|_| linker.add_object(file_name, complier.to_elf())) {
+			eprint!("{}", complier.render_error(&e, options.color));
+			return ExitCode::FAILURE;
+		}
+	}
+	if let Err(e) = linker.link() {
+		eprint!("{}", linker.render_error(&e, options.color));
+		return ExitCode::FAILURE;
+	}
+	if let Some(map_file) = &map_file {
+		if let Err(e) = linker.write_map(map_file) {
+			eprint!("{}", linker.render_error(&e, options.color));
+			return ExitCode::FAILURE;
+		}
+	}
+	match out_file {
+		Some(out_file) => {
+			if let Err(e) = linker.write_output(&out_file, out_format) {
+				eprint!("{}", linker.render_error(&e, options.color));
+				return ExitCode::FAILURE;
+			}
+		}
+		None if map_file.is_none() => print!("{}", linker.get_map()),
+		None => {}
+	}
+	ExitCode::SUCCESS
+}
@@ -0,0 +1,376 @@
+//! Turn machine code back into commands using the same definitions the complier encodes with
+
+use regex::Regex;
+
+use crate::definitions::{language::{CommandDefinition, LanguageDefinition}, processor::{Format, SegType}};
+use super::parse_code::ParsedCommand;
+
+/// An instruction matched back to the command definition that encodes it
+pub struct Decoded<'a> {
+	pub op_code: &'a str,
+	pub format: &'a Format,
+	pub command: &'a CommandDefinition,
+	/// Value of every segment of the format except Main, in segment order
+	pub fields: Vec<(SegType, u32)>,
+}
+
+impl Decoded<'_> {
+	/// Value of the nth field of a type
+	pub fn field(&self, seg_type: SegType, nth: usize) -> Option<u32> {
+		self.fields.iter().filter(|f| f.0 == seg_type).nth(nth).map(|f| f.1)
+	}
+
+	/// Build the command this instruction was assembled from, with its operands written out as source text.
+	/// A branch target is written as the address it goes to, PC-relative from address.
+	/// The fields of a command split over several instructions have to be joined already, see join_split
+	pub fn to_command(&self, address: i32, pc_offset: i32) -> ParsedCommand {
+		let parts = self.command.split.max(1) as u32;
+		let mut command = ParsedCommand {
+			op_code: self.op_code.to_string(),
+			address,
+			format: self.format.id,
+			scale: self.command.scale,
+			split: self.command.split,
+			..Default::default()
+		};
+		for (i, seg) in self.command.segments.iter().enumerate() {
+			let nth = self.command.segments[..i].iter().filter(|s| s.0 == seg.0).count();
+			let value = self.field(seg.0, nth).unwrap_or(0);
+			let text = match seg.0 {
+				SegType::Op | SegType::Flag | SegType::Condition => seg.1.clone(),
+				SegType::Offset if is_branch(&self.command.regex, &seg.1) => {
+					let width = self.format.segments.iter().filter(|s| s.seg_type == seg.0).nth(nth).map_or(32, |s| s.width() * parts).clamp(1, 32);
+					let distance = ((value << (32 - width)) as i32) >> (32 - width);
+					format!("0x{:x}", address.wrapping_add(pc_offset).wrapping_add(distance << self.command.scale) as u32)
+				}
+				SegType::Immediate | SegType::Offset => operand_text(&self.command.regex, &seg.1, seg.0, value << self.command.scale),
+				_ => operand_text(&self.command.regex, &seg.1, seg.0, value),
+			};
+			command.operands.push((seg.0, text));
+		}
+		command
+	}
+}
+
+/// Join the instructions a split command like BL was spread over back into one, each wide field
+/// taking the next lower slice from each instruction. None when the instructions that follow are not the rest of it
+fn join_split<'a>(def: &'a LanguageDefinition, bytes: &[u8], first: Decoded<'a>) -> Option<Decoded<'a>> {
+	let parts = first.command.split.max(1) as usize;
+	let size = first.format.size();
+	let mut fields = first.fields.clone();
+	for part in 0..parts {
+		let next = decode(def, bytes.get(part * size..)?)?;
+		if next.op_code != first.op_code || next.format.id != first.format.id || next.field(SegType::Flag, 0) != Some(part as u32) {
+			return None;
+		}
+		if part == 0 {
+			continue;
+		}
+		for (field, (seg_type, slice)) in fields.iter_mut().zip(&next.fields) {
+			let seg = first.format.segments.iter().find(|s| s.seg_type == *seg_type && s.values.is_none());
+			if let (Some(seg), SegType::Offset | SegType::Immediate) = (seg, seg_type) {
+				field.1 = field.1 << seg.width() | slice;
+			}
+		}
+	}
+	Some(Decoded { fields, ..first })
+}
+
+/// True when an offset operand of a command is a branch target, written as an address without a #
+fn is_branch(regex: &str, name: &str) -> bool {
+	let written_as_number = group_pattern(regex, name).and_then(|g| Regex::new(&format!("^(?:{})$", g)).ok()).is_some_and(|g| g.is_match("0x0"));
+	regex.find(&format!("(?P<{}>", name)).is_some_and(|at| !regex[..at].ends_with('#')) && written_as_number
+}
+
+/// Address of the literal pool entry a load from a pool reads, when the instruction is one
+fn literal_entry(def: &LanguageDefinition, decoded: &Decoded, address: i32) -> Option<i32> {
+	let scale = def.commands.iter().flat_map(|c| &c.1)
+		.find(|c| c.format_index == decoded.format.id && c.segments.iter().any(|s| s.0 == SegType::Literal))?
+		.scale;
+	let distance = decoded.field(SegType::Immediate, 0)? << scale;
+	Some((address.wrapping_add(def.processor_def.pc_offset) & !3).wrapping_add(distance as i32))
+}
+
+/// Match one instruction at the start of bytes to a format and command definition.
+/// bytes are in memory order.
+pub fn decode<'a>(def: &'a LanguageDefinition, bytes: &[u8]) -> Option<Decoded<'a>> {
+	let processor = &def.processor_def;
+	for format in &processor.formats {
+		let size = format.size();
+		if bytes.len() < size {
+			continue;
+		}
+		let mut word = bytes[..size].to_vec();
+		if processor.little_endian {
+			word.reverse();
+		}
+		let Some(fields) = format.decode(&word) else { continue };
+
+		for (op_code, versions) in &def.commands {
+			// A literal load can not be told from a plain one, its value is somewhere else
+			for command in versions.iter().filter(|c| c.format_index == format.id && c.segments.iter().all(|s| s.0 != SegType::Literal)) {
+				if literals_match(command, &fields) {
+					return Some(Decoded { op_code, format, command, fields });
+				}
+			}
+		}
+	}
+	None
+}
+
+/// Decode a block of machine code starting at base_address.
+/// The literal pool entries loads read are kept as a .word, anything else that is not an instruction as a .hword or .byte
+pub fn disassemble(def: &LanguageDefinition, bytes: &[u8], base_address: i32) -> Vec<ParsedCommand> {
+	let processor = &def.processor_def;
+	let unit = processor.formats.iter().map(|f| f.size()).min().unwrap_or(1).max(1);
+	let mut commands = Vec::new();
+	// A pool entry is always ahead of the load that reads it, so it is known before it is reached
+	let mut pool: Vec<i32> = Vec::new();
+	let mut pos = 0;
+
+	while pos < bytes.len() {
+		let address = base_address + pos as i32;
+		let entry = pool.contains(&address) && bytes.len() - pos >= 4;
+		let decoded = decode(def, &bytes[pos..]).filter(|_| !entry).and_then(|decoded| match decoded.command.split {
+			0 | 1 => Some(decoded),
+			_ => join_split(def, &bytes[pos..], decoded),
+		});
+		if let Some(decoded) = decoded {
+			pool.extend(literal_entry(def, &decoded, address));
+			commands.push(decoded.to_command(address, processor.pc_offset));
+			pos += decoded.format.size() * decoded.command.split.max(1) as usize;
+			continue;
+		}
+
+		let size = if entry { 4 } else if bytes.len() - pos >= unit { unit } else { 1 };
+		let mut value: u32 = 0;
+		for i in 0..size {
+			let byte = if processor.little_endian { bytes[pos + size - 1 - i] } else { bytes[pos + i] };
+			value = value << 8 | byte as u32;
+		}
+		commands.push(ParsedCommand {
+			op_code: match size { 1 => ".byte", 2 => ".hword", _ => ".word" }.to_string(),
+			address,
+			operands: vec![(SegType::Immediate, format!("0x{:0width$x}", value, width = size * 2))],
+			..Default::default()
+		});
+		pos += size;
+	}
+	commands
+}
+
+/// Write a command out as assembly text
+pub fn render_command(def: &LanguageDefinition, command: &ParsedCommand) -> String {
+	if command.op_code.starts_with('.') {
+		let operands: Vec<&str> = command.operands.iter().map(|o| o.1.as_str()).collect();
+		return format!("{} {}", command.op_code, operands.join(", "));
+	}
+
+	let definition = def.commands.iter()
+		.filter(|c| c.0 == command.op_code)
+		.flat_map(|c| &c.1)
+		.filter(|c| c.format_index == command.format && c.segments.len() == command.operands.len())
+		.find(|c| c.segments.iter().zip(&command.operands).all(|(s, o)| {
+			s.0 == o.0 && (!matches!(s.0, SegType::Op | SegType::Flag | SegType::Condition) || s.1 == o.1)
+		}));
+
+	let pieces = definition.and_then(|d| template(&d.regex));
+	match (definition, pieces) {
+		(Some(definition), Some(pieces)) => {
+			let mut text = String::new();
+			for piece in pieces {
+				match piece {
+					Piece::Text(t) => text.push_str(&t),
+					Piece::Capture(name) => {
+						if let Some(i) = definition.segments.iter().position(|s| s.1 == name) {
+							text.push_str(&command.operands[i].1);
+						}
+					}
+				}
+			}
+			text
+		}
+		_ => {
+			let operands: Vec<&str> = command.operands.iter()
+				.filter(|o| !matches!(o.0, SegType::Op | SegType::Flag | SegType::Condition))
+				.map(|o| o.1.as_str()).collect();
+			format!("{} {}", command.op_code.to_lowercase(), operands.join(", "))
+		}
+	}
+}
+
+/// Write out a listing of commands, one per line with its address
+pub fn render(def: &LanguageDefinition, commands: &[ParsedCommand]) -> String {
+	let mut text = String::new();
+	for command in commands {
+		text.push_str(&format!("{:08x}:\t{}\n", command.address, render_command(def, command)));
+	}
+	text
+}
+
+/// True when every literal Op/Flag/Condition segment of the command equals the decoded field
+fn literals_match(command: &CommandDefinition, fields: &[(SegType, u32)]) -> bool {
+	command.segments.iter().enumerate().all(|(i, seg)| {
+		let nth = command.segments[..i].iter().filter(|s| s.0 == seg.0).count();
+		let field = fields.iter().filter(|f| f.0 == seg.0).nth(nth);
+		match seg.0 {
+			SegType::Op | SegType::Flag | SegType::Condition => field.map(|f| f.1) == seg.1.parse::<u32>().ok(),
+			_ => field.is_some(),
+		}
+	})
+}
+
+/// Pick the way of writing a value that the named capture group of the regex accepts
+fn operand_text(regex: &str, name: &str, seg_type: SegType, value: u32) -> String {
+	let candidates: Vec<String> = if seg_type == SegType::RegisterList {
+		let regs: Vec<String> = (0..32).filter(|r| value & (1 << r) != 0).map(|r| format!("r{}", r)).collect();
+		let list = regs.join(", ");
+		vec![list.clone(), format!("{}, ", list)]
+	}
+	else if matches!(seg_type, SegType::Immediate | SegType::Offset) {
+		// Values can be written as expressions so only a register pattern rules out a number
+		vec![format!("{}", value), format!("r{}", value), format!("h{}", value), format!("0x{:x}", value)]
+	}
+	else {
+		vec![format!("r{}", value), format!("h{}", value), format!("{}", value), format!("0x{:x}", value)]
+	};
+
+	let fallback = if seg_type == SegType::RegisterList { candidates[0].clone() } else { value.to_string() };
+	let group = group_pattern(regex, name).and_then(|g| Regex::new(&format!("^(?:{})$", g)).ok());
+	match group {
+		Some(group) => candidates.iter().find(|c| group.is_match(c)).unwrap_or(&fallback).clone(),
+		None => fallback,
+	}
+}
+
+/// The pattern inside a named capture group
+fn group_pattern<'a>(regex: &'a str, name: &str) -> Option<&'a str> {
+	let start = regex.find(&format!("(?P<{}>", name))? + name.len() + 5;
+	let end = closing_paren(regex, start)?;
+	Some(&regex[start..end])
+}
+
+/// Index of the ')' that closes a group whose contents start at start
+fn closing_paren(regex: &str, start: usize) -> Option<usize> {
+	let bytes = regex.as_bytes();
+	let mut depth = 0;
+	let mut in_class = false;
+	let mut i = start;
+	while i < bytes.len() {
+		match bytes[i] {
+			b'\\' => i += 1,
+			b'[' => in_class = true,
+			b']' => in_class = false,
+			b'(' if !in_class => depth += 1,
+			b')' if !in_class => {
+				if depth == 0 {
+					return Some(i);
+				}
+				depth -= 1;
+			}
+			_ => {}
+		}
+		i += 1;
+	}
+	None
+}
+
+enum Piece {
+	Text(String),
+	Capture(String),
+}
+
+/// Turn a command regex into text with holes for its named captures.
+/// Only handles the simple regexes commands are written with, case pairs like [aA],
+/// whitespace runs, escaped and plain characters, and named groups.
+fn template(regex: &str) -> Option<Vec<Piece>> {
+	let chars: Vec<char> = regex.chars().collect();
+	let mut pieces = Vec::new();
+	let mut text = String::new();
+	let mut i = if chars.first() == Some(&'^') { 1 } else { 0 };
+
+	while i < chars.len() {
+		match chars[i] {
+			'$' => break,
+			'\\' => {
+				text.push(*chars.get(i + 1)?);
+				i += 2;
+			}
+			'[' => {
+				let end = i + chars[i..].iter().position(|c| *c == ']')?;
+				let class: String = chars[i + 1..end].iter().collect();
+				i = end + 1;
+				if class == " \\t" {
+					match chars.get(i) {
+						Some('*') => { i += 1; }
+						Some('+') => { i += 1; text.push(' '); }
+						_ => text.push(' '),
+					}
+				}
+				else {
+					let letters: Vec<char> = class.chars().collect();
+					if letters.len() != 2 || !letters[0].eq_ignore_ascii_case(&letters[1]) {
+						return None;
+					}
+					text.push(letters[0].to_ascii_lowercase());
+				}
+			}
+			'(' => {
+				let rest: String = chars[i..].iter().collect();
+				let name = rest.strip_prefix("(?P<")?.split('>').next()?.to_string();
+				let offset: usize = chars[..i].iter().map(|c| c.len_utf8()).sum();
+				let start = offset + name.len() + 5;
+				let end = closing_paren(regex, start)?;
+				i = regex[..=end].chars().count();
+				pieces.push(Piece::Text(std::mem::take(&mut text)));
+				pieces.push(Piece::Capture(name));
+			}
+			'+' | '*' | '?' | '{' | '|' | '.' => return None,
+			c => {
+				text.push(c);
+				i += 1;
+			}
+		}
+	}
+	pieces.push(Piece::Text(text));
+	Some(pieces)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::compile::Complier;
+
+	#[test]
+	fn round_trip() {
+		let source = concat!(
+			"start:\tlsl r2, r5, #27\nadd r3, r2, #5\nmov r4, h5\nldr r5, [r5, #44]\nstrh r2, [r5, #6]\npop {r4, r5, pc}\npush {r0, r3}\nadd sp, #-12\nswi 43\nldmia r3!, {r5, r6}\n",
+			"bcc start\nb end\nbl start\nldr r1, =0xdeadbeef\nend:\tbl end\n",
+		);
+		let mut complier: Complier = Default::default();
+		complier.compile_from_str(source).unwrap();
+		let bin = complier.get_bin().clone();
+
+		let def: LanguageDefinition = Default::default();
+		let commands = disassemble(&def, &bin, 0);
+		let text: Vec<String> = commands.iter().map(|c| render_command(&def, c)).collect();
+		assert_eq!(commands.len(), 17);
+		assert_eq!(text[0], "lsl r2, r5, #27");
+		assert_eq!(text[2], "mov r4, h5");
+		assert_eq!(text[5], "pop {r4, r5, pc}");
+		assert_eq!(text[7], "add sp, #-12");
+		// Branches go to the address they reach, a BL is one command and the literal pool is data
+		assert_eq!(text[10..], ["bcc 0x0", "b 0x1e", "bl 0x0", "ldr r1, [pc, #4]", "bl 0x1e", "lsl r0, r0, #0", ".word 0xdeadbeef"]);
+
+		complier.compile_from_str(&text.join("\n")).unwrap();
+		assert_eq!(complier.get_bin(), &bin);
+	}
+
+	#[test]
+	fn unknown_data() {
+		let def: LanguageDefinition = Default::default();
+		// add with two low registers is not a hi register operation
+		let commands = disassemble(&def, &[0x00, 0x44, 0x01], 0x100);
+		assert_eq!(render(&def, &commands), "00000100:\t.hword 0x4400\n00000102:\t.byte 0x01\n");
+	}
+}
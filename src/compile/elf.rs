@@ -0,0 +1,571 @@
+//! ELF32 files for ARM, executables for tools like objdump, readelf and GDB and relocatable objects for a linker
+
+use std::fmt;
+
+use super::Section;
+
+/// Machine number of ARM
+const EM_ARM: u16 = 40;
+/// Version 5 of the ARM embedded ABI
+const EF_ARM_EABI_VER5: u32 = 0x0500_0000;
+const ET_REL: u16 = 1;
+const ET_EXEC: u16 = 2;
+const PT_LOAD: u32 = 1;
+const SHT_PROGBITS: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+const SHT_STRTAB: u32 = 3;
+const SHT_RELA: u32 = 4;
+const SHT_NOBITS: u32 = 8;
+const SHT_REL: u32 = 9;
+pub const SHF_WRITE: u32 = 1;
+pub const SHF_ALLOC: u32 = 2;
+pub const SHF_EXECINSTR: u32 = 4;
+const SHF_INFO_LINK: u32 = 0x40;
+const SHN_ABS: u16 = 0xfff1;
+const STB_GLOBAL: u8 = 1;
+const STT_SECTION: u8 = 3;
+const STT_FILE: u8 = 4;
+const HEADER_SIZE: usize = 52;
+const PROGRAM_HEADER_SIZE: usize = 32;
+const SECTION_HEADER_SIZE: usize = 40;
+const SYMBOL_SIZE: usize = 16;
+const REL_SIZE: usize = 8;
+
+/// How the linker puts an address into the bytes at a relocation, numbered as in the ARM ELF ABI.
+/// The bytes already hold the addend, the linker adds the address of the target to it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RelocationKind {
+	/// A 32 bit address in data, like a .word or a literal pool entry
+	Abs32 = 2,
+	Abs16 = 5,
+	Abs8 = 8,
+	/// The distance a BL pair branches, 22 bits of halfwords split over two instructions
+	ThumbCall = 10,
+	/// The distance an unconditional B branches, 11 bits of halfwords
+	ThumbJump11 = 102,
+	/// The distance a conditional B branches, 8 bits of halfwords
+	ThumbJump8 = 103,
+}
+
+impl RelocationKind {
+	pub fn from_elf(kind: u32) -> Option<Self> {
+		match kind {
+			2 => Some(RelocationKind::Abs32),
+			5 => Some(RelocationKind::Abs16),
+			8 => Some(RelocationKind::Abs8),
+			10 => Some(RelocationKind::ThumbCall),
+			102 => Some(RelocationKind::ThumbJump11),
+			103 => Some(RelocationKind::ThumbJump8),
+			_ => None,
+		}
+	}
+
+	/// True when the bytes hold the distance from the place rather than an address
+	pub fn pc_relative(self) -> bool {
+		matches!(self, RelocationKind::ThumbCall | RelocationKind::ThumbJump11 | RelocationKind::ThumbJump8)
+	}
+}
+
+/// The name readelf gives a relocation kind, like R_ARM_ABS32
+impl fmt::Display for RelocationKind {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let name = match self {
+			RelocationKind::Abs32 => "R_ARM_ABS32",
+			RelocationKind::Abs16 => "R_ARM_ABS16",
+			RelocationKind::Abs8 => "R_ARM_ABS8",
+			RelocationKind::ThumbCall => "R_ARM_THM_CALL",
+			RelocationKind::ThumbJump11 => "R_ARM_THM_JUMP11",
+			RelocationKind::ThumbJump8 => "R_ARM_THM_JUMP8",
+		};
+		write!(f, "{}", name)
+	}
+}
+
+/// What a relocation adds the address of
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RelocationTarget {
+	/// The start of a section of the same object, for labels that are not global
+	Section(String),
+	/// A global symbol, defined in this object or another one
+	Symbol(String),
+}
+
+/// A place the linker has to fill in with an address
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Relocation {
+	/// Section holding the bytes to fill in
+	pub section: String,
+	/// Where the bytes are from the start of the section
+	pub offset: u32,
+	pub target: RelocationTarget,
+	pub kind: RelocationKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ElfSymbol {
+	pub name: String,
+	pub value: u32,
+	/// Section the symbol is in, empty for a constant or a symbol that is not defined
+	pub section: String,
+	/// False for a symbol this file uses but another one defines
+	pub defined: bool,
+	pub global: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ElfSection {
+	pub section: Section,
+	/// SHF_ALLOC, SHF_WRITE and SHF_EXECINSTR
+	pub flags: u32,
+}
+
+/// An ELF file for ARM, only what kgemu writes and reads of it
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub struct ElfFile {
+	/// An object for a linker, its sections start at 0, rather than an executable
+	pub relocatable: bool,
+	pub little_endian: bool,
+	/// Address the code starts running at
+	pub entry: u32,
+	pub sections: Vec<ElfSection>,
+	pub symbols: Vec<ElfSymbol>,
+	/// Only written for a relocatable file
+	pub relocations: Vec<Relocation>,
+}
+
+/// True when data starts like an ELF file
+pub fn is_elf(data: &[u8]) -> bool {
+	data.starts_with(b"\x7fELF")
+}
+
+/// Names packed one after another, each ending in a zero byte
+#[derive(Debug)]
+struct StringTable(Vec<u8>);
+
+impl StringTable {
+	fn new() -> Self {
+		StringTable(vec![0])
+	}
+
+	fn add(&mut self, name: &str) -> u32 {
+		if name.is_empty() {
+			return 0;
+		}
+		let offset = self.0.len() as u32;
+		self.0.extend(name.as_bytes());
+		self.0.push(0);
+		offset
+	}
+}
+
+/// Bytes written in the byte order of the file
+struct Writer {
+	bytes: Vec<u8>,
+	little_endian: bool,
+}
+
+impl Writer {
+	fn u8(&mut self, value: u8) {
+		self.bytes.push(value);
+	}
+
+	fn u16(&mut self, value: u16) {
+		self.bytes.extend(if self.little_endian { value.to_le_bytes() } else { value.to_be_bytes() });
+	}
+
+	fn u32(&mut self, value: u32) {
+		self.bytes.extend(if self.little_endian { value.to_le_bytes() } else { value.to_be_bytes() });
+	}
+
+	/// Pad with zeros until the length is a multiple of align plus remainder
+	fn pad(&mut self, align: u32, remainder: u32) {
+		let align = align.max(1) as usize;
+		while self.bytes.len() % align != remainder as usize % align {
+			self.bytes.push(0);
+		}
+	}
+}
+
+/// The fields of a section header in order
+struct SectionHeader {
+	name: u32,
+	kind: u32,
+	flags: u32,
+	address: u32,
+	offset: u32,
+	size: u32,
+	link: u32,
+	info: u32,
+	align: u32,
+	entry_size: u32,
+}
+
+impl ElfFile {
+	/// An executable holding one block of code that starts running at its first byte, for a rom image with no sections
+	pub fn from_image(data: &[u8], start: u32, little_endian: bool) -> Self {
+		let section = Section { name: ".text".to_string(), address: start, load_address: Some(start), size: data.len() as u32, data: data.to_vec(), align: 4 };
+		ElfFile {
+			little_endian,
+			entry: start,
+			sections: vec![ElfSection { section, flags: SHF_ALLOC | SHF_EXECINSTR }],
+			..Default::default()
+		}
+	}
+
+	/// The file as bytes.
+	/// An executable has a loadable program header for each section, stored at the section's load address,
+	/// a relocatable file has a .rel section for each section with relocations and a section symbol for each section
+	pub fn to_bytes(&self) -> Vec<u8> {
+		let mut names = StringTable::new();
+		let mut strings = StringTable::new();
+		let loaded = if self.relocatable { 0 } else { self.sections.iter().filter(|s| s.flags & SHF_ALLOC != 0).count() };
+		let start = HEADER_SIZE + loaded * PROGRAM_HEADER_SIZE;
+		let mut body = Writer { bytes: Vec::new(), little_endian: self.little_endian };
+		let mut program = Writer { bytes: Vec::new(), little_endian: self.little_endian };
+
+		let rel_sections: Vec<(usize, &ElfSection)> = self.sections.iter().enumerate()
+			.filter(|(_, s)| self.relocatable && self.relocations.iter().any(|r| r.section == s.section.name))
+			.collect();
+		let symtab_index = 1 + self.sections.len() + rel_sections.len();
+
+		let mut headers = vec![SectionHeader { name: 0, kind: 0, flags: 0, address: 0, offset: 0, size: 0, link: 0, info: 0, align: 0, entry_size: 0 }];
+		for elf_section in &self.sections {
+			let section = &elf_section.section;
+			let zeroed = section.load_address.is_none();
+			body.pad(section.align, section.address.wrapping_sub(start as u32));
+			let offset = (start + body.bytes.len()) as u32;
+			if !zeroed {
+				body.bytes.extend(&section.data);
+				body.bytes.resize(offset as usize - start + section.size as usize, 0);
+			}
+			headers.push(SectionHeader {
+				name: names.add(&section.name),
+				kind: if zeroed { SHT_NOBITS } else { SHT_PROGBITS },
+				flags: elf_section.flags,
+				address: section.address,
+				offset,
+				size: section.size,
+				link: 0,
+				info: 0,
+				align: section.align.max(1),
+				entry_size: 0,
+			});
+			if loaded > 0 && elf_section.flags & SHF_ALLOC != 0 {
+				let execute = if elf_section.flags & SHF_EXECINSTR != 0 { 1 } else { 0 };
+				let write = if elf_section.flags & SHF_WRITE != 0 { 2 } else { 0 };
+				for field in [PT_LOAD, offset, section.address, section.load_address.unwrap_or(section.address),
+					if zeroed { 0 } else { section.size }, section.size, 4 | write | execute, section.align.max(1)] {
+					program.u32(field);
+				}
+			}
+		}
+
+		// Section symbols first, then the other locals, then globals as ELF requires
+		let mut symbols: Vec<ElfSymbol> = self.symbols.iter().filter(|s| !s.global).cloned().collect();
+		symbols.extend(self.symbols.iter().filter(|s| s.global).cloned());
+		for relocation in &self.relocations {
+			if let RelocationTarget::Symbol(name) = &relocation.target {
+				if !symbols.iter().any(|s| &s.name == name) {
+					symbols.push(ElfSymbol { name: name.clone(), value: 0, section: String::new(), defined: false, global: true });
+				}
+			}
+		}
+		let section_symbols = if self.relocatable { self.sections.len() } else { 0 };
+		let first_global = 1 + section_symbols + symbols.iter().filter(|s| !s.global).count();
+		let symbol_index = |target: &RelocationTarget| match target {
+			RelocationTarget::Section(name) => self.sections.iter().position(|s| &s.section.name == name).map_or(0, |i| i + 1),
+			RelocationTarget::Symbol(name) => symbols.iter().position(|s| &s.name == name).map_or(0, |i| i + 1 + section_symbols),
+		};
+
+		for (index, elf_section) in &rel_sections {
+			body.pad(4, 0);
+			let offset = (start + body.bytes.len()) as u32;
+			let relocations: Vec<&Relocation> = self.relocations.iter().filter(|r| r.section == elf_section.section.name).collect();
+			for relocation in &relocations {
+				body.u32(relocation.offset);
+				body.u32((symbol_index(&relocation.target) as u32) << 8 | relocation.kind as u32);
+			}
+			headers.push(SectionHeader {
+				name: names.add(&format!(".rel{}", elf_section.section.name)),
+				kind: SHT_REL,
+				flags: SHF_INFO_LINK,
+				address: 0,
+				offset,
+				size: (relocations.len() * REL_SIZE) as u32,
+				link: symtab_index as u32,
+				info: *index as u32 + 1,
+				align: 4,
+				entry_size: REL_SIZE as u32,
+			});
+		}
+
+		body.pad(4, 0);
+		let symtab_offset = (start + body.bytes.len()) as u32;
+		body.bytes.extend([0; SYMBOL_SIZE]);
+		for index in 1..=section_symbols {
+			for field in [0, 0, 0] {
+				body.u32(field);
+			}
+			body.u8(STT_SECTION);
+			body.u8(0);
+			body.u16(index as u16);
+		}
+		for symbol in &symbols {
+			let index = match self.sections.iter().position(|s| s.section.name == symbol.section) {
+				_ if !symbol.defined => 0,
+				Some(i) => i as u16 + 1,
+				None => SHN_ABS,
+			};
+			body.u32(strings.add(&symbol.name));
+			body.u32(symbol.value);
+			body.u32(0);
+			body.u8(if symbol.global { STB_GLOBAL << 4 } else { 0 });
+			body.u8(0);
+			body.u16(index);
+		}
+		headers.push(SectionHeader {
+			name: names.add(".symtab"),
+			kind: SHT_SYMTAB,
+			flags: 0,
+			address: 0,
+			offset: symtab_offset,
+			size: ((1 + section_symbols + symbols.len()) * SYMBOL_SIZE) as u32,
+			link: symtab_index as u32 + 1,
+			info: first_global as u32,
+			align: 4,
+			entry_size: SYMBOL_SIZE as u32,
+		});
+		let strtab_name = names.add(".strtab");
+		// The names table holds its own name, so it is added before the table is written
+		let shstrtab_name = names.add(".shstrtab");
+		for (name, table) in [(strtab_name, &strings), (shstrtab_name, &names)] {
+			let offset = (start + body.bytes.len()) as u32;
+			body.bytes.extend(&table.0);
+			headers.push(SectionHeader { name, kind: SHT_STRTAB, flags: 0, address: 0, offset, size: table.0.len() as u32, link: 0, info: 0, align: 1, entry_size: 0 });
+		}
+
+		body.pad(4, 0);
+		let section_headers = (start + body.bytes.len()) as u32;
+		for header in &headers {
+			for field in [header.name, header.kind, header.flags, header.address, header.offset, header.size, header.link, header.info, header.align, header.entry_size] {
+				body.u32(field);
+			}
+		}
+
+		let mut file = Writer { bytes: b"\x7fELF".to_vec(), little_endian: self.little_endian };
+		file.bytes.extend([1, if self.little_endian { 1 } else { 2 }, 1]);
+		file.bytes.resize(16, 0);
+		file.u16(if self.relocatable { ET_REL } else { ET_EXEC });
+		file.u16(EM_ARM);
+		file.u32(1);
+		file.u32(if self.relocatable { 0 } else { self.entry });
+		file.u32(if loaded > 0 { HEADER_SIZE as u32 } else { 0 });
+		file.u32(section_headers);
+		file.u32(EF_ARM_EABI_VER5);
+		file.u16(HEADER_SIZE as u16);
+		file.u16(if loaded > 0 { PROGRAM_HEADER_SIZE as u16 } else { 0 });
+		file.u16(loaded as u16);
+		file.u16(SECTION_HEADER_SIZE as u16);
+		file.u16(headers.len() as u16);
+		file.u16(headers.len() as u16 - 1);
+		file.bytes.extend(program.bytes);
+		file.bytes.extend(body.bytes);
+		file.bytes
+	}
+
+	/// Read an ELF file for ARM.
+	/// Each loaded section's load address comes from the program header it is stored in, sections with none are stored where they run.
+	/// Section symbols and file symbols are left out, relocations against a section symbol target the section
+	pub fn parse(bytes: &[u8]) -> Result<Self, String> {
+		if !is_elf(bytes) {
+			return Err("not an ELF file".to_string());
+		}
+		if bytes.get(4) != Some(&1) {
+			return Err("only 32 bit ELF files can be read".to_string());
+		}
+		let little_endian = match bytes.get(5) {
+			Some(1) => true,
+			Some(2) => false,
+			_ => return Err("the ELF file has no byte order".to_string()),
+		};
+		let reader = Reader { bytes, little_endian };
+		let machine = reader.u16(18)?;
+		if machine != EM_ARM {
+			return Err(format!("the ELF file is for machine {}, not ARM", machine));
+		}
+		let relocatable = reader.u16(16)? == ET_REL;
+		let entry = reader.u32(24)?;
+		let (program_offset, section_offset) = (reader.u32(28)? as usize, reader.u32(32)? as usize);
+		let (program_size, program_count) = (reader.u16(42)? as usize, reader.u16(44)? as usize);
+		let (section_size, section_count, names_index) = (reader.u16(46)? as usize, reader.u16(48)? as usize, reader.u16(50)? as usize);
+
+		// Offset in the file, size in the file, address and load address of each loaded segment
+		let mut segments = Vec::new();
+		for i in 0..program_count {
+			let at = program_offset + i * program_size;
+			if reader.u32(at)? == PT_LOAD {
+				let (offset, size) = (reader.u32(at + 4)?, reader.u32(at + 16)?);
+				if offset as u64 + size as u64 > bytes.len() as u64 {
+					return Err("the ELF file is cut short".to_string());
+				}
+				segments.push((offset, size, reader.u32(at + 8)?, reader.u32(at + 12)?));
+			}
+		}
+		let mut headers = Vec::new();
+		for i in 0..section_count {
+			let at = section_offset + i * section_size;
+			let field = |n: usize| reader.u32(at + n * 4);
+			headers.push(SectionHeader {
+				name: field(0)?, kind: field(1)?, flags: field(2)?, address: field(3)?, offset: field(4)?,
+				size: field(5)?, link: field(6)?, info: field(7)?, align: field(8)?, entry_size: field(9)?,
+			});
+		}
+		let names = headers.get(names_index).ok_or("the ELF file has no section names")?;
+		let section_names: Vec<String> = headers.iter().map(|h| reader.string(names.offset as usize + h.name as usize)).collect::<Result<_, _>>()?;
+
+		let mut sections = Vec::new();
+		for (header, name) in headers.iter().zip(&section_names).filter(|(h, _)| h.flags & SHF_ALLOC != 0) {
+			let zeroed = header.kind == SHT_NOBITS;
+			let data = if zeroed { Vec::new() } else { reader.slice(header.offset as usize, header.size as usize)?.to_vec() };
+			let load_address = match segments.iter().find(|(offset, size, _, _)| (*offset as u64..*offset as u64 + *size as u64).contains(&(header.offset as u64))) {
+				Some((offset, _, _, load)) => load.checked_add(header.offset - offset).ok_or(format!("section {} is loaded past the end of memory", name))?,
+				None => header.address,
+			};
+			let section = Section {
+				name: name.clone(),
+				address: header.address,
+				load_address: (!zeroed).then_some(load_address),
+				size: header.size,
+				data,
+				align: header.align.max(1),
+			};
+			sections.push(ElfSection { section, flags: header.flags & (SHF_ALLOC | SHF_WRITE | SHF_EXECINSTR) });
+		}
+
+		let mut symbols = Vec::new();
+		// What each symbol index stands for when a relocation names it
+		let mut targets = Vec::new();
+		if let Some(symtab) = headers.iter().find(|h| h.kind == SHT_SYMTAB) {
+			let strings = headers.get(symtab.link as usize).ok_or("the symbol table has no names")?;
+			for i in 0..symtab.size as usize / SYMBOL_SIZE {
+				let at = symtab.offset as usize + i * SYMBOL_SIZE;
+				let name = reader.string(strings.offset as usize + reader.u32(at)? as usize)?;
+				let info = *reader.slice(at + 12, 1)?.first().unwrap_or(&0);
+				let index = reader.u16(at + 14)? as usize;
+				let section = if index == 0 || index >= section_names.len() { String::new() } else { section_names[index].clone() };
+				if info & 0xf == STT_SECTION {
+					targets.push(RelocationTarget::Section(section));
+					continue;
+				}
+				targets.push(RelocationTarget::Symbol(name.clone()));
+				if i == 0 || info & 0xf == STT_FILE {
+					continue;
+				}
+				symbols.push(ElfSymbol { name, value: reader.u32(at + 4)?, section, defined: index != 0, global: info >> 4 != 0 });
+			}
+		}
+
+		let mut relocations = Vec::new();
+		for header in &headers {
+			if header.kind == SHT_RELA {
+				return Err("relocations with addends (RELA) are not supported".to_string());
+			}
+			if header.kind != SHT_REL {
+				continue;
+			}
+			let section = section_names.get(header.info as usize).cloned().unwrap_or_default();
+			for i in 0..header.size as usize / REL_SIZE {
+				let at = header.offset as usize + i * REL_SIZE;
+				let info = reader.u32(at + 4)?;
+				let kind = RelocationKind::from_elf(info & 0xff).ok_or(format!("relocation type {} is not supported", info & 0xff))?;
+				let target = targets.get(info as usize >> 8).cloned().ok_or(format!("relocation {} in {} names a symbol that does not exist", i, section))?;
+				relocations.push(Relocation { section: section.clone(), offset: reader.u32(at)?, target, kind });
+			}
+		}
+
+		Ok(ElfFile { relocatable, little_endian, entry, sections, symbols, relocations })
+	}
+}
+
+/// Reads fields of a file that may be cut short
+struct Reader<'a> {
+	bytes: &'a [u8],
+	little_endian: bool,
+}
+
+impl Reader<'_> {
+	fn slice(&self, at: usize, size: usize) -> Result<&[u8], String> {
+		at.checked_add(size).and_then(|end| self.bytes.get(at..end)).ok_or("the ELF file is cut short".to_string())
+	}
+
+	fn u16(&self, at: usize) -> Result<u16, String> {
+		let bytes = self.slice(at, 2)?.try_into().unwrap();
+		Ok(if self.little_endian { u16::from_le_bytes(bytes) } else { u16::from_be_bytes(bytes) })
+	}
+
+	fn u32(&self, at: usize) -> Result<u32, String> {
+		let bytes = self.slice(at, 4)?.try_into().unwrap();
+		Ok(if self.little_endian { u32::from_le_bytes(bytes) } else { u32::from_be_bytes(bytes) })
+	}
+
+	fn string(&self, at: usize) -> Result<String, String> {
+		let rest = self.bytes.get(at..).ok_or("the ELF file is cut short")?;
+		let end = rest.iter().position(|b| *b == 0).ok_or("a name in the ELF file does not end")?;
+		Ok(String::from_utf8_lossy(&rest[..end]).to_string())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn section(name: &str, address: u32, load_address: Option<u32>, data: &[u8], size: u32, flags: u32) -> ElfSection {
+		ElfSection { section: Section { name: name.to_string(), address, load_address, size, data: data.to_vec(), align: 4 }, flags }
+	}
+
+	#[test]
+	fn round_trip() {
+		let symbol = |name: &str, value: u32, section: &str, global: bool| ElfSymbol { name: name.to_string(), value, section: section.to_string(), defined: true, global };
+		let executable = ElfFile {
+			little_endian: true,
+			entry: 0x10,
+			sections: vec![
+				section(".text", 0x10, Some(0x10), &[0x01, 0x20, 0x02, 0xdf], 4, SHF_ALLOC | SHF_EXECINSTR),
+				section(".data", 0x2000_0000, Some(0x14), &[1, 2, 3], 3, SHF_ALLOC | SHF_WRITE),
+				section(".bss", 0x2000_0004, None, &[], 8, SHF_ALLOC | SHF_WRITE),
+			],
+			symbols: vec![symbol("$t", 0x10, ".text", false), symbol("_start", 0x10, ".text", true), symbol("SIZE", 8, "", false)],
+			..Default::default()
+		};
+		let bytes = executable.to_bytes();
+		assert_eq!(&bytes[..8], b"\x7fELF\x01\x01\x01\x00");
+		assert_eq!(ElfFile::parse(&bytes), Ok(ElfFile { symbols: vec![executable.symbols[0].clone(), executable.symbols[2].clone(), executable.symbols[1].clone()], ..executable }));
+
+		let object = ElfFile {
+			relocatable: true,
+			little_endian: true,
+			sections: vec![section(".text", 0, Some(0), &[0xff, 0xf7, 0xfe, 0xff, 0, 0, 0, 0], 8, SHF_ALLOC | SHF_EXECINSTR)],
+			symbols: vec![symbol("main", 0, ".text", true), ElfSymbol { name: "puts".to_string(), value: 0, section: String::new(), defined: false, global: true }],
+			relocations: vec![
+				Relocation { section: ".text".to_string(), offset: 0, target: RelocationTarget::Symbol("puts".to_string()), kind: RelocationKind::ThumbCall },
+				Relocation { section: ".text".to_string(), offset: 4, target: RelocationTarget::Section(".text".to_string()), kind: RelocationKind::Abs32 },
+			],
+			..Default::default()
+		};
+		assert_eq!(ElfFile::parse(&object.to_bytes()), Ok(object));
+	}
+
+	#[test]
+	fn bad_files() {
+		assert_eq!(ElfFile::parse(b"\x7fELF\x02\x01"), Err("only 32 bit ELF files can be read".to_string()));
+		let mut bytes = ElfFile::from_image(&[0; 4], 0, true).to_bytes();
+		assert_eq!(ElfFile::parse(&bytes[..40]), Err("the ELF file is cut short".to_string()));
+		// A program header whose segment starts 16 bytes before the end of memory
+		let mut segment = bytes.clone();
+		let program_offset = u32::from_le_bytes(bytes[28..32].try_into().unwrap()) as usize;
+		segment[program_offset + 4..program_offset + 8].copy_from_slice(&0xffff_fff0u32.to_le_bytes());
+		assert_eq!(ElfFile::parse(&segment), Err("the ELF file is cut short".to_string()));
+		bytes[18] = 62;
+		assert_eq!(ElfFile::parse(&bytes), Err("the ELF file is for machine 62, not ARM".to_string()));
+	}
+}
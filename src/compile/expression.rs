@@ -0,0 +1,285 @@
+//! Constant expressions like `(BUF_SIZE * 4) - 1` in operands and data directives
+
+use std::ops::Range;
+
+use crate::error::ErrorCode;
+
+/// Why an expression could not be evaluated, span is the part of the expression text at fault
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExpressionError {
+	pub span: Range<usize>,
+	pub code: ErrorCode,
+	pub message: String,
+	/// The name that was not defined, for an UndefinedLabel error
+	pub symbol: Option<String>,
+}
+
+/// Smallest and largest value any part of an expression may have, anything that fits in 32 bits signed or unsigned
+const SMALLEST: i64 = i32::MIN as i64;
+const LARGEST: i64 = u32::MAX as i64;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+	Number(i64),
+	Symbol(String),
+	/// An operator or parenthesis, two characters for shifts, comparisons and logic
+	Op(&'static str),
+}
+
+/// Evaluate an integer expression.
+/// Supports + - * / % << >> & | ^, comparisons and && || giving 1 or 0, unary - ~ + !, parentheses, decimal, 0x, 0b and 0o numbers,
+/// character literals like 'A' or '\n', and symbols looked up with symbol.
+/// A numeric local reference like `1b` or `2f` is a symbol too
+pub fn evaluate(text: &str, symbol: &dyn Fn(&str) -> Option<i64>) -> Result<i64, ExpressionError> {
+	let tokens = tokenize(text)?;
+	if tokens.is_empty() {
+		return Err(error(0..text.len(), ErrorCode::BadOperand, "expected a value".to_string()));
+	}
+	let mut parser = Parser { tokens, position: 0, end: text.len(), symbol };
+	let value = parser.binary(0)?;
+	if let Some((_, span)) = parser.tokens.get(parser.position) {
+		return Err(error(span.clone(), ErrorCode::BadOperand, format!("unexpected `{}`", &text[span.clone()])));
+	}
+	Ok(value)
+}
+
+fn error(span: Range<usize>, code: ErrorCode, message: String) -> ExpressionError {
+	ExpressionError { span, code, message, symbol: None }
+}
+
+fn tokenize(text: &str) -> Result<Vec<(Token, Range<usize>)>, ExpressionError> {
+	// Longest first so << is not read as two <
+	const OPS: [&str; 22] = ["<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "<", ">", "+", "-", "*", "/", "%", "&", "|", "^", "~", "(", ")", "!"];
+	// Spans are byte offsets, taken from where each character starts so they always cover whole characters
+	let chars: Vec<(usize, char)> = text.char_indices().collect();
+	let at = |k: usize| chars.get(k).map_or(text.len(), |c| c.0);
+	let mut tokens = Vec::new();
+	let mut k = 0;
+	while k < chars.len() {
+		let (start, c) = chars[k];
+		if c.is_whitespace() {
+			k += 1;
+			continue;
+		}
+		if c.is_ascii_digit() {
+			while k < chars.len() && (chars[k].1.is_ascii_alphanumeric() || chars[k].1 == '_') {
+				k += 1;
+			}
+			let word = &text[start..at(k)];
+			if super::parse_code::local_reference(word).is_some() {
+				tokens.push((Token::Symbol(word.to_string()), start..at(k)));
+				continue;
+			}
+			tokens.push((Token::Number(number(word).ok_or(error(start..at(k), ErrorCode::BadOperand, format!("\"{}\" is not a valid number", word)))?), start..at(k)));
+		}
+		else if c.is_ascii_alphabetic() || c == '_' || c == '.' {
+			k += 1;
+			while k < chars.len() && (chars[k].1.is_ascii_alphanumeric() || matches!(chars[k].1, '_' | '.' | '$')) {
+				k += 1;
+			}
+			tokens.push((Token::Symbol(text[start..at(k)].to_string()), start..at(k)));
+		}
+		else if c == '\'' {
+			// Find the closing quote, skipping an escaped one
+			k += 1;
+			while k < chars.len() && chars[k].1 != '\'' {
+				k += if chars[k].1 == '\\' { 2 } else { 1 };
+			}
+			let closed = k < chars.len();
+			let end = at((k + 1).min(chars.len()));
+			let value = match super::parse_code::unescape(&text[start + 1..at(k.min(chars.len()))]) {
+				Ok(value) if closed && value.len() == 1 => value[0],
+				_ => return Err(error(start..end, ErrorCode::BadOperand, format!("{} is not a character literal", &text[start..end]))),
+			};
+			k += 1;
+			tokens.push((Token::Number(value as i64), start..end));
+		}
+		else {
+			let op = OPS.iter().find(|op| text[start..].starts_with(**op))
+				.ok_or(error(start..start + c.len_utf8(), ErrorCode::BadOperand, format!("unexpected `{}`", c)))?;
+			// Every operator is ASCII, one character for each byte
+			k += op.len();
+			tokens.push((Token::Op(op), start..start + op.len()));
+		}
+	}
+	Ok(tokens)
+}
+
+/// A number with an optional 0x, 0b or 0o prefix
+fn number(text: &str) -> Option<i64> {
+	let lower = text.to_lowercase().replace('_', "");
+	let (digits, radix) = match lower.get(..2) {
+		Some("0x") => (&lower[2..], 16),
+		Some("0b") => (&lower[2..], 2),
+		Some("0o") => (&lower[2..], 8),
+		_ => (&lower[..], 10),
+	};
+	i64::from_str_radix(digits, radix).ok().filter(|v| *v <= LARGEST)
+}
+
+/// Binding strength of each binary operator, higher binds tighter
+fn precedence(op: &str) -> Option<u8> {
+	match op {
+		"||" => Some(1),
+		"&&" => Some(2),
+		"|" => Some(3),
+		"^" => Some(4),
+		"&" => Some(5),
+		"==" | "!=" => Some(6),
+		"<" | "<=" | ">" | ">=" => Some(7),
+		"<<" | ">>" => Some(8),
+		"+" | "-" => Some(9),
+		"*" | "/" | "%" => Some(10),
+		_ => None,
+	}
+}
+
+struct Parser<'a> {
+	tokens: Vec<(Token, Range<usize>)>,
+	position: usize,
+	/// Length of the text, where an error about a missing value points
+	end: usize,
+	symbol: &'a dyn Fn(&str) -> Option<i64>,
+}
+
+impl Parser<'_> {
+	/// Operators binding at least as tight as min, left to right
+	fn binary(&mut self, min: u8) -> Result<i64, ExpressionError> {
+		let start = self.tokens.get(self.position).map_or(self.end, |t| t.1.start);
+		let mut left = self.unary()?;
+		while let Some((Token::Op(op), span)) = self.tokens.get(self.position).cloned() {
+			let Some(strength) = precedence(op).filter(|p| *p >= min.max(1)) else { break };
+			self.position += 1;
+			let right = self.binary(strength + 1)?;
+			let whole = start..self.tokens.get(self.position - 1).map_or(self.end, |t| t.1.end);
+			let value = match op {
+				"+" => left.checked_add(right),
+				"-" => left.checked_sub(right),
+				"*" => left.checked_mul(right),
+				"/" | "%" if right == 0 => return Err(error(span, ErrorCode::BadOperand, "division by zero".to_string())),
+				"/" => left.checked_div(right),
+				"%" => left.checked_rem(right),
+				"<<" | ">>" if !(0..32).contains(&right) => {
+					return Err(error(whole, ErrorCode::Overflow, format!("shift by {} is not in 0 to 31", right)));
+				}
+				"<<" => left.checked_mul(1 << right),
+				">>" => Some(left >> right),
+				"&" => Some(left & right),
+				"|" => Some(left | right),
+				"==" => Some((left == right) as i64),
+				"!=" => Some((left != right) as i64),
+				"<" => Some((left < right) as i64),
+				"<=" => Some((left <= right) as i64),
+				">" => Some((left > right) as i64),
+				">=" => Some((left >= right) as i64),
+				"&&" => Some((left != 0 && right != 0) as i64),
+				"||" => Some((left != 0 || right != 0) as i64),
+				_ => Some(left ^ right),
+			};
+			left = self.check(value, whole)?;
+		}
+		Ok(left)
+	}
+
+	fn unary(&mut self) -> Result<i64, ExpressionError> {
+		let Some((token, span)) = self.tokens.get(self.position).cloned() else {
+			return Err(error(self.end..self.end, ErrorCode::BadOperand, "expected a value".to_string()));
+		};
+		self.position += 1;
+		match token {
+			Token::Number(value) => Ok(value),
+			Token::Symbol(name) => (self.symbol)(&name).ok_or(ExpressionError {
+				span,
+				code: ErrorCode::UndefinedLabel,
+				message: format!("label `{}` is not defined", name),
+				symbol: Some(name),
+			}),
+			Token::Op("(") => {
+				let value = self.binary(0)?;
+				match self.tokens.get(self.position) {
+					Some((Token::Op(")"), _)) => {
+						self.position += 1;
+						Ok(value)
+					}
+					_ => Err(error(span, ErrorCode::BadOperand, "this ( is never closed".to_string())),
+				}
+			}
+			Token::Op(op @ ("-" | "~" | "+" | "!")) => {
+				let value = self.unary()?;
+				let whole = span.start..self.tokens.get(self.position - 1).map_or(self.end, |t| t.1.end);
+				let value = match op {
+					"-" => Some(-value),
+					// Bits are flipped as a 32 bit value, so ~0 is 0xffffffff read as -1
+					"~" if !value < SMALLEST => Some(!value as u32 as i64),
+					"~" => Some(!value),
+					"!" => Some((value == 0) as i64),
+					_ => Some(value),
+				};
+				self.check(value, whole)
+			}
+			Token::Op(op) => Err(error(span, ErrorCode::BadOperand, format!("expected a value before `{}`", op))),
+		}
+	}
+
+	/// Every step of an expression has to fit in 32 bits
+	fn check(&self, value: Option<i64>, span: Range<usize>) -> Result<i64, ExpressionError> {
+		match value {
+			Some(value) if (SMALLEST..=LARGEST).contains(&value) => Ok(value),
+			_ => Err(error(span, ErrorCode::Overflow, "value does not fit in 32 bits".to_string())),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn symbols(name: &str) -> Option<i64> {
+		match name {
+			"BUF_SIZE" => Some(16),
+			"table" => Some(0x100),
+			"base" => Some(0xf0),
+			_ => None,
+		}
+	}
+
+	fn eval(text: &str) -> Result<i64, ExpressionError> {
+		evaluate(text, &symbols)
+	}
+
+	#[test]
+	fn arithmetic() {
+		assert_eq!(eval("(BUF_SIZE * 4) - 1"), Ok(63));
+		assert_eq!(eval("table - base"), Ok(0x10));
+		assert_eq!(eval("table + 8"), Ok(0x108));
+		assert_eq!(eval("1 + 2 * 3"), Ok(7));
+		assert_eq!(eval("1 << 4 | 0b1010 & 0xf"), Ok(26));
+		assert_eq!(eval("-8 / 3"), Ok(-2));
+		assert_eq!(eval("~0"), Ok(-1));
+		assert_eq!(eval("'A' + 1"), Ok(66));
+		assert_eq!(eval("'\\n'"), Ok(10));
+		assert_eq!(eval("0xffffffff"), Ok(0xffff_ffff));
+		assert_eq!(eval("-(3 - 5) ^ 0o7"), Ok(5));
+		assert_eq!(eval("BUF_SIZE == 16 && table > base"), Ok(1));
+		assert_eq!(eval("1 << 2 <= 3 || !(base == 0xf0)"), Ok(0));
+	}
+
+	#[test]
+	fn errors() {
+		let error = eval("BUF_SIZE + missing").unwrap_err();
+		assert_eq!((error.code, error.span, error.symbol.as_deref()), (ErrorCode::UndefinedLabel, 11..18, Some("missing")));
+		assert_eq!(eval("0xffffffff + 1").unwrap_err().code, ErrorCode::Overflow);
+		assert_eq!(eval("1 << 32").unwrap_err().code, ErrorCode::Overflow);
+		assert_eq!(eval("0x1_0000_0000").unwrap_err().code, ErrorCode::BadOperand);
+		assert_eq!(eval("4 / (2 - 2)").unwrap_err().span, 2..3);
+		assert_eq!(eval("(1 + 2").unwrap_err().span, 0..1);
+		assert_eq!(eval("1 +").unwrap_err().span, 3..3);
+		assert_eq!(eval("1 2").unwrap_err().message, "unexpected `2`");
+		assert_eq!(eval("'ab'").unwrap_err().code, ErrorCode::BadOperand);
+		// Characters past ASCII are named whole, with a span that covers all their bytes
+		let error = eval("1 + \u{00e9}").unwrap_err();
+		assert_eq!((error.span, error.message.as_str()), (4..6, "unexpected `\u{00e9}`"));
+		assert_eq!(eval("BUF_SIZE\u{2192}1").unwrap_err().span, 8..11);
+		assert_eq!(eval("'\\\u{00e9}'").unwrap_err().span, 0..5);
+	}
+}
@@ -0,0 +1,224 @@
+//! An assembly listing like `as -al` writes: each source line beside its address, the halfwords or bytes it became
+//! and the values of the symbols it uses, then a cross reference of every symbol
+
+use super::{expression, parse_code::{self, ParsedCode, VarKind}, symbols::{SymbolKind, SymbolTable}, Section};
+use crate::prelude::{ProcessorDefinition, SegType};
+
+/// Bytes of data shown on each row of the listing, the rest go on rows below
+const ROW_BYTES: usize = 4;
+
+/// The listing of compiled code. Lines expanded from a macro are marked with a +,
+/// and a line naming the file is put in wherever the lines move into or out of an included file
+pub fn listing(code: &ParsedCode, processor: &ProcessorDefinition, sections: &[Section], table: &SymbolTable) -> String {
+	let file_name = if code.file_name.is_empty() { "<source>" } else { &code.file_name };
+	let mut text = format!("kgemu listing of {}\n\n line  address   code          source\n", file_name);
+	let mut file = code.file_name.as_str();
+	for line in &code.lines {
+		if line.origin.file_name != file {
+			file = &line.origin.file_name;
+			text.push_str(&format!("{:31}; {}\n", "", if file.is_empty() { "<source>" } else { file }));
+		}
+		// Whether it is a literal pool entry, address, code and the values the item uses of each row the line fills.
+		// The pool at the end of a section is put on the last line, after what that line holds
+		let mut rows: Vec<(bool, u32, String, String)> = Vec::new();
+		for command in code.commands.iter().filter(|c| c.line == line.index) {
+			let size = processor.get_format(command.format).map_or(0, |f| f.size() * command.split.max(1) as usize);
+			let bytes = bytes_at(sections, &command.section, command.address as u32, size);
+			let halfwords: Vec<String> = bytes.chunks(2).map(|h| {
+				let halfword = h.iter().fold(0u16, |value, b| value << 8 | *b as u16);
+				format!("{:04x}", if processor.little_endian { halfword.swap_bytes() } else { halfword })
+			}).collect();
+			let mut values: Vec<String> = command.operands.iter()
+				.filter(|o| matches!(o.0, SegType::Immediate | SegType::Offset))
+				.filter_map(|o| resolved(code, &o.1, command.address, command.line))
+				.collect();
+			if let Some(entry) = command.literal.and_then(|v| code.vars.get(v)) {
+				let value = resolved(code, &entry.value, entry.address, command.line).unwrap_or(format!("={}", entry.value));
+				values.push(format!("{} from 0x{:08x}", value.trim_start_matches('='), entry.address as u32));
+			}
+			rows.push((false, command.address as u32, halfwords.join(" "), values.join(", ")));
+		}
+		for var in code.vars.iter().filter(|v| v.line == line.index && v.size > 0) {
+			let bytes = bytes_at(sections, &var.section, var.address as u32, var.size as usize);
+			let value = match var.kind {
+				VarKind::Value => resolved(code, &var.value, var.address, var.loaded_by.unwrap_or(var.line)).unwrap_or_default(),
+				_ => String::new(),
+			};
+			// A zeroed section has no bytes to show, just where the space is
+			if bytes.is_empty() {
+				rows.push((var.loaded_by.is_some(), var.address as u32, String::new(), format!("{} bytes zeroed", var.size)));
+				continue;
+			}
+			for (i, chunk) in bytes.chunks(ROW_BYTES).enumerate() {
+				let chunk: Vec<String> = chunk.iter().map(|b| format!("{:02x}", b)).collect();
+				let value = if i == 0 { value.clone() } else { String::new() };
+				rows.push((var.loaded_by.is_some(), (var.address as usize + i * ROW_BYTES) as u32, chunk.join(" "), value));
+			}
+		}
+		rows.sort_by_key(|r| (r.0, r.1));
+		if rows.is_empty() {
+			if let Some(label) = code.labels.iter().find(|l| l.line == line.index) {
+				rows.push((false, label.address as u32, String::new(), String::new()));
+			}
+		}
+
+		let number = format!("{:>5}{}", line.origin.line + 1, if line.origin.expansion.is_empty() { ' ' } else { '+' });
+		let source = line.text.trim_end();
+		let mut printed = Vec::new();
+		match rows.split_first() {
+			None => printed.push(format!("{:31}{}", number, source)),
+			Some(((_, address, bytes, _), rest)) => {
+				printed.push(format!("{} {:08x}  {:<12}  {}", number, address, bytes, source));
+				printed.extend(rest.iter().map(|(_, address, bytes, _)| format!("{:6} {:08x}  {:<12}", "", address, bytes)));
+				// Values go after the source on the first row, and after the code on the rows below
+				for (row, (_, _, _, values)) in printed.iter_mut().zip(&rows).filter(|(_, r)| !r.3.is_empty()) {
+					row.push_str(&format!("  ; {}", values));
+				}
+			}
+		}
+		for row in printed {
+			text.push_str(row.trim_end());
+			text.push('\n');
+		}
+	}
+	text.push_str(&cross_reference(code, table));
+	text
+}
+
+/// Bytes of a section at an address, empty when the section starts zeroed
+fn bytes_at<'a>(sections: &'a [Section], section: &str, address: u32, size: usize) -> &'a [u8] {
+	sections.iter().find(|s| s.name == section)
+		.and_then(|s| s.data.get((address - s.address) as usize..(address - s.address) as usize + size))
+		.unwrap_or(&[])
+}
+
+/// Names an expression uses, `.` and numeric local references like `1f` included
+fn names(text: &str) -> impl Iterator<Item = &str> {
+	text.split(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '$'))
+		.filter(|name| !name.is_empty())
+		.filter(|name| !name.starts_with(|c: char| c.is_ascii_digit()) || parse_code::local_reference(name).is_some())
+}
+
+/// "text = value" for an expression that uses a symbol, None for a plain number or one that can not be worked out yet,
+/// like a symbol the linker fills in
+fn resolved(code: &ParsedCode, text: &str, here: i32, line: i32) -> Option<String> {
+	names(text).next()?;
+	let value = expression::evaluate(text, &super::symbols(code, here, line)).ok()?;
+	Some(format!("{} = 0x{:08x}", text.trim(), value as u32))
+}
+
+/// Where a line is in the source, its number or file:number when it is in an included file
+fn place(code: &ParsedCode, index: i32) -> String {
+	match code.lines.iter().find(|l| l.index == index) {
+		Some(l) if l.origin.file_name != code.file_name => format!("{}:{}", l.origin.file_name, l.origin.line + 1),
+		Some(l) => (l.origin.line + 1).to_string(),
+		None => "-".to_string(),
+	}
+}
+
+/// A table of every symbol with its value, the line that defines it and the lines that use it
+fn cross_reference(code: &ParsedCode, table: &SymbolTable) -> String {
+	// The line that defines each symbol a name on a line means, a constant is known by its name whichever .set it is
+	let definition = |name: &str, line: i32| -> Option<i32> {
+		match parse_code::local_reference(name) {
+			Some((number, forward)) => code.local_label(number, forward, line).map(|l| l.line),
+			None if code.constant(name, line).is_some() => Some(-1),
+			None => code.labels.iter().find(|l| l.name == name).map(|l| l.line),
+		}
+	};
+	let mut uses: Vec<(String, i32, i32)> = Vec::new();
+	let mut used = |text: &str, line: i32| for name in names(text) {
+		if let Some(defined) = definition(name, line) {
+			let name = parse_code::local_reference(name).map_or(name, |(number, _)| number);
+			uses.push((name.to_string(), defined, line));
+		}
+	};
+	for command in &code.commands {
+		for operand in command.operands.iter().filter(|o| matches!(o.0, SegType::Immediate | SegType::Offset | SegType::Literal)) {
+			used(&operand.1, command.line);
+		}
+	}
+	// A literal pool entry is used by the ldr that loads it
+	for var in code.vars.iter().filter(|v| v.kind == VarKind::Value && v.loaded_by.is_none()) {
+		used(&var.value, var.line);
+	}
+	for constant in &code.constants {
+		used(&constant.expression, constant.line);
+	}
+
+	let width = table.symbols.iter().map(|s| s.name.len()).max().unwrap_or(0).max(6);
+	let mut text = format!("\nsymbols\n\n{:<width$}  {:<10}  {:<8}  {:<10}  {:<8}  used on\n", "name", "value", "kind", "section", "defined");
+	for symbol in &table.symbols {
+		let defined = match symbol.kind {
+			SymbolKind::Constant => Some(-1),
+			_ => code.labels.iter().find(|l| l.name == symbol.name && code.source_line(l.line) == symbol.line).map(|l| l.line),
+		};
+		let mut lines: Vec<i32> = uses.iter().filter(|u| u.0 == symbol.name && Some(u.1) == defined).map(|u| u.2).collect();
+		lines.sort();
+		lines.dedup();
+		let lines: Vec<String> = lines.iter().map(|l| place(code, *l)).collect();
+		let kind = format!("{:?}", symbol.kind).to_lowercase();
+		let at = match symbol.kind {
+			SymbolKind::Constant => code.constants.iter().rfind(|c| c.name == symbol.name).map_or("-".to_string(), |c| place(code, c.line)),
+			_ => defined.map_or("-".to_string(), |l| place(code, l)),
+		};
+		let row = format!("{:<width$}  0x{:08x}  {:<8}  {:<10}  {:<8}  {}", symbol.name, symbol.value as u32, kind, symbol.section, at, lines.join(" "));
+		text.push_str(row.trim_end());
+		text.push('\n');
+	}
+	// Symbols another file defines, used here and filled in by the linker
+	for name in code.externs.iter().filter(|e| table.get(e).is_none()) {
+		let mut lines: Vec<i32> = code.commands.iter().filter(|c| c.operands.iter().any(|o| names(&o.1).any(|n| n == name))).map(|c| c.line).collect();
+		lines.extend(code.vars.iter().filter(|v| v.kind == VarKind::Value && v.loaded_by.is_none() && names(&v.value).any(|n| n == name)).map(|v| v.line));
+		lines.sort();
+		lines.dedup();
+		let lines: Vec<String> = lines.iter().map(|l| place(code, *l)).collect();
+		let row = format!("{:<width$}  {:<10}  {:<8}  {:<10}  {:<8}  {}", name, "-", "extern", "", "-", lines.join(" "));
+		text.push_str(row.trim_end());
+		text.push('\n');
+	}
+	text
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::compile::Complier;
+
+	#[test]
+	fn listing() {
+		let code = ".equ STEP, 2\n.macro bump reg\n\tadd \\reg, #STEP\n.endm\n.global _start\n_start:\tldr r0, =count\n\tbump r1\n1:\tbl 1b\n.data\ncount:\t.word _start, 0x1234\n";
+		let mut complier: Complier = Default::default();
+		complier.compile_from_str(code).unwrap();
+		let listing = complier.get_listing();
+		let lines: Vec<&str> = listing.lines().collect();
+		assert_eq!(lines[..2], ["kgemu listing of <source>", ""]);
+		assert_eq!(lines[3], "    1                          .equ STEP, 2");
+		assert_eq!(lines[8], "    6  00000000  4801          _start:\tldr r0, =count  ; count = 0x20000000 from 0x00000008");
+		assert_eq!(lines[9], "    7                          \tbump r1");
+		assert_eq!(lines[10], "    3+ 00000002  3102          \tadd r1, #STEP  ; STEP = 0x00000002");
+		assert_eq!(lines[11], "    8  00000004  f7ff fffe     1:\tbl 1b  ; 1b = 0x00000004");
+		assert_eq!(lines[13], "   10  20000000  00 00 00 00   count:\t.word _start, 0x1234  ; _start = 0x00000000");
+		assert_eq!(lines[14], "       20000004  34 12 00 00");
+		// The pool at the end of .text is put on the last line
+		assert_eq!(lines[15], "       00000008  00 00 00 20   ; count = 0x20000000");
+
+		let symbols = &lines[lines.iter().position(|l| *l == "symbols").unwrap() + 2..];
+		assert_eq!(symbols, [
+			"name    value       kind      section     defined   used on",
+			"_start  0x00000000  global    .text       6         10",
+			"STEP    0x00000002  constant              1         3",
+			"1       0x00000004  local     .text       8         8",
+			"count   0x20000000  label     .data       10        6",
+		]);
+	}
+
+	#[test]
+	fn uses_in_line_order() {
+		let code = ".data\nat:\t.word here\n.text\nhere:\tb here\n\tb here\n";
+		let mut complier: Complier = Default::default();
+		complier.compile_from_str(code).unwrap();
+		let listing = complier.get_listing();
+		let row = listing.lines().find(|l| l.starts_with("here ")).unwrap();
+		assert!(row.ends_with("4         2 4 5"), "{row}");
+	}
+}
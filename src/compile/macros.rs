@@ -0,0 +1,122 @@
+//! Macros defined with .macro and .endm, expanded while the code is parsed
+
+use std::ops::Range;
+
+use super::parse_code::{split_operands, LineOrigin};
+
+/// How deep macros may invoke macros before it is taken as endless recursion
+pub const MAX_DEPTH: usize = 64;
+
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub struct MacroParam {
+	pub name: String,
+	/// Used when an invocation leaves the argument out
+	pub default: Option<String>,
+	/// Written `name:req`, leaving the argument out is an error
+	pub required: bool,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub struct ParsedMacro {
+	pub name: String,
+	pub params: Vec<MacroParam>,
+	/// Lines between .macro and .endm, with where each came from
+	pub body: Vec<(String, LineOrigin)>,
+	/// Index of the parsed .macro line
+	pub line: i32,
+}
+
+impl ParsedMacro {
+	/// Read the text after .macro, a name then parameters like `delay count=10, reg:req`.
+	/// Parameters are split by commas or spaces like GNU as does
+	pub fn from_header(text: &str, line: i32) -> Result<Self, (Range<usize>, String)> {
+		let name_end = text.find(|c: char| c.is_whitespace() || c == ',').unwrap_or(text.len());
+		let name = &text[..name_end];
+		if !is_name(name) {
+			return Err((0..name_end, format!("\"{}\" is not a macro name", name)));
+		}
+		let mut params: Vec<MacroParam> = Vec::new();
+		for (part, span) in split_operands(&text[name_end..]) {
+			let mut search = 0;
+			for word in part.split_whitespace() {
+				let found = search + part[search..].find(word).unwrap_or(0);
+				search = found + word.len();
+				let at = name_end + span.start + found;
+				let (word, default) = match word.split_once('=') {
+					Some((word, default)) => (word, Some(default.to_string())),
+					None => (word, None),
+				};
+				let (word, required) = match word.strip_suffix(":req") {
+					Some(word) => (word, true),
+					None => (word, false),
+				};
+				if !is_name(word) {
+					return Err((at..at + word.len(), format!("\"{}\" is not a parameter name", word)));
+				}
+				if params.iter().any(|p| p.name == word) {
+					return Err((at..at + word.len(), format!("parameter `{}` is named twice", word)));
+				}
+				params.push(MacroParam { name: word.to_string(), default, required });
+			}
+		}
+		Ok(ParsedMacro { name: name.to_string(), params, body: Vec::new(), line })
+	}
+
+	/// Match the comma separated arguments of an invocation to the parameters,
+	/// in order or by name like `reg=r1`
+	pub fn arguments(&self, text: &str) -> Result<Vec<String>, (Range<usize>, String)> {
+		let mut values: Vec<Option<String>> = vec![None; self.params.len()];
+		let mut next = 0;
+		for (argument, span) in split_operands(text) {
+			let named = argument.split_once('=')
+				.and_then(|(name, value)| self.params.iter().position(|p| p.name == name.trim()).map(|i| (i, value.trim())));
+			let (i, value) = match named {
+				Some(named) => named,
+				None if next < self.params.len() => (next, argument),
+				None => return Err((span, format!("macro `{}` takes {} argument{}", self.name, self.params.len(), if self.params.len() == 1 { "" } else { "s" }))),
+			};
+			values[i] = Some(value.to_string());
+			next = i + 1;
+		}
+		self.params.iter().zip(values).map(|(param, value)| match value.or(param.default.clone()) {
+			Some(value) => Ok(value),
+			None if param.required => Err((0..text.len(), format!("macro `{}` needs a value for `{}`", self.name, param.name))),
+			None => Ok(String::new()),
+		}).collect()
+	}
+
+	/// The body with `\param` replaced by its argument, `\@` by the number of expansions before this one and `\()` by nothing
+	pub fn expand(&self, arguments: &[String], count: usize) -> Vec<(String, LineOrigin)> {
+		self.body.iter().map(|(text, origin)| {
+			let mut out = String::new();
+			let mut rest = text.as_str();
+			while let Some(at) = rest.find('\\') {
+				out.push_str(&rest[..at]);
+				rest = &rest[at + 1..];
+				let word_end = rest.find(|c: char| !c.is_ascii_alphanumeric() && c != '_').unwrap_or(rest.len());
+				if let Some(after) = rest.strip_prefix('@') {
+					out.push_str(&count.to_string());
+					rest = after;
+				}
+				else if let Some(after) = rest.strip_prefix("()") {
+					rest = after;
+				}
+				else if let Some(i) = self.params.iter().position(|p| p.name == rest[..word_end]) {
+					out.push_str(&arguments[i]);
+					rest = &rest[word_end..];
+				}
+				else {
+					// Not a parameter, like the \n in a string
+					out.push('\\');
+				}
+			}
+			out.push_str(rest);
+			(out, origin.clone())
+		}).collect()
+	}
+}
+
+fn is_name(name: &str) -> bool {
+	name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_' || c == '.')
+		&& name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '$')
+}
@@ -1,57 +1,1156 @@
-/// Mod to compile assembly code into machine code
-use crate::prelude::LanguageDefinition;
+//! Mod to compile assembly code into machine code
+use std::{cell::RefCell, fs, ops::Range, rc::Rc};
+
+use elf::{ElfFile, ElfSection, ElfSymbol, Relocation, RelocationKind, RelocationTarget};
+use output::OutputFormat;
+use parse_code::{ParsedVar, VarKind};
+use symbols::SymbolKind;
+
+use crate::prelude::{DefinitionError, DeviceDefinition, Diagnostic, ErrorCode, Format, KgemuError, LanguageDefinition, ProcessorDefinition, Region, SegType};
 
 
 pub mod parse_code;
+pub mod expression;
+pub mod macros;
+pub mod symbols;
+pub mod disassemble;
+pub mod output;
+pub mod elf;
+pub mod listing;
 
 pub mod prelude {
-	pub use super::Complier;
+	pub use super::{Complier, Section};
+	pub use super::output::OutputFormat;
+	pub use super::elf::{ElfFile, Relocation, RelocationKind, RelocationTarget};
+	pub use super::symbols::{Symbol, SymbolKind, SymbolTable};
+}
+
+/// An output section after layout
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub struct Section {
+	pub name: String,
+	/// Address the section is used at while the code runs
+	pub address: u32,
+	/// Where its initial data is stored in rom, None when it starts zeroed
+	pub load_address: Option<u32>,
+	pub size: u32,
+	/// Initial contents, empty when the section starts zeroed
+	pub data: Vec<u8>,
+	/// The section starts on a multiple of this many bytes
+	pub align: u32,
 }
 
+#[derive(Default)]
 pub struct Complier {
 	language_def: LanguageDefinition,
+	/// The regexes of language_def, compiled by the first parse and kept until the definition changes
+	regexes: Option<Rc<parse_code::LanguageRegexes>>,
+	device_def: DeviceDefinition,
+	source: String,
 	parsed_code: parse_code::ParsedCode,
+	sections: Vec<Section>,
+	symbols: symbols::SymbolTable,
+	include_paths: Vec<String>,
+	defines: Vec<(String, i64)>,
+	/// Compile to an object for a linker rather than code that runs
+	relocatable: bool,
+	relocations: Vec<Relocation>,
 	complied_code: Vec<u8>
 }
 
 impl Complier {
-	pub fn load_def_from_file() {
+	/// Replace the language definition with one loaded from a json file, it must pass validation to be used
+	pub fn load_def_from_file(&mut self, file_name: &str) -> Result<(), DefinitionError> {
+		let def = LanguageDefinition::from_file(file_name)?;
+		def.validate().map_err(|problems| DefinitionError::Invalid { file_name: file_name.to_string(), problems })?;
+		self.language_def = def;
+		self.regexes = None;
+		Ok(())
+	}
+	pub fn set_def(&mut self, def: LanguageDefinition) {
+		self.language_def = def;
+		self.regexes = None;
+	}
+	/// Replace the memory map with one loaded from a json file
+	pub fn load_device_from_file(&mut self, file_name: &str) -> Result<(), DefinitionError> {
+		self.device_def = DeviceDefinition::from_file(file_name)?;
+		Ok(())
+	}
+	pub fn set_device(&mut self, device: DeviceDefinition) {
+		self.device_def = device;
+	}
+	/// Add a folder for .include to search, after the folder of the including file
+	pub fn add_include_path(&mut self, path: &str) {
+		self.include_paths.push(path.to_string());
+	}
+	/// Define a symbol before the code is parsed, for .if and .ifdef and as a constant
+	pub fn define(&mut self, name: &str, value: i64) {
+		self.defines.retain(|d| d.0 != name);
+		self.defines.push((name.to_string(), value));
+	}
+	/// Compile to a relocatable object for a linker: every section starts at 0,
+	/// and symbols that are not defined or labels in another section are left as relocations to fill in
+	pub fn set_relocatable(&mut self, relocatable: bool) {
+		self.relocatable = relocatable;
+	}
+	/// Read source code from a file, ready to be parsed
+	pub fn load_from_file(&mut self, file_name: &str) -> Result<(), KgemuError> {
+		self.source = fs::read_to_string(file_name)
+			.map_err(|error| KgemuError::Io { file_name: file_name.to_string(), error })?;
+		self.parsed_code = Default::default();
+		self.parsed_code.file_name = file_name.to_string();
+		Ok(())
+	}
+	pub fn load_from_str(&mut self, code: &str) {
+		self.source = code.to_string();
+		self.parsed_code = Default::default();
+	}
+	/// Parse the loaded source using the language definition
+	pub fn parse(&mut self) -> Result<(), KgemuError> {
+		let file_name = std::mem::take(&mut self.parsed_code.file_name);
+		self.parsed_code = Default::default();
+		self.parsed_code.file_name = file_name;
+		self.parsed_code.include_paths = self.include_paths.clone();
+		self.parsed_code.defines = self.defines.clone();
+		self.parsed_code.regexes = self.regexes.clone();
+		let parsed = self.parsed_code.parse_from_str(&self.source, &self.language_def);
+		self.regexes = self.parsed_code.regexes.clone();
+		parsed
+	}
+	pub fn parse_from_file(&mut self, file_name: &str) -> Result<(), KgemuError> {
+		self.load_from_file(file_name)?;
+		self.parse()
+	}
+	pub fn parse_from_str(&mut self, code: &str) -> Result<(), KgemuError> {
+		self.load_from_str(code);
+		self.parse()
+	}
+	/// Two pass compile of the parsed code.
+	/// Pass one assigns an address to every command, label and var,
+	/// pass two encodes each command with its format and emits the data.
+	pub fn compile(&mut self) -> Result<(), KgemuError> {
+		// Nothing from an earlier compile is left to be mistaken for this one when it fails
+		self.complied_code.clear();
+		self.sections.clear();
+		self.relocations.clear();
+		let mut diagnostics = self.assign_addresses().err().unwrap_or_default();
+		// Code that does not fit has no addresses to encode at, and its data could be too big to build
+		let fits = !diagnostics.iter().any(|d| d.code == Some(ErrorCode::DoesNotFit));
+		diagnostics.extend(self.resolve_constants().err().unwrap_or_default());
+		self.symbols = symbols::SymbolTable::new(&self.parsed_code);
+		if fits {
+			diagnostics.extend(self.encode().err().unwrap_or_default());
+		}
+		if diagnostics.is_empty() {
+			// The sections of an object all start at 0, there is no rom image until it is linked
+			self.complied_code = if self.relocatable { Vec::new() } else { rom_image(&self.sections, self.device_def.rom_start) };
+			return Ok(());
+		}
+		// Sections laid out before an error are not a build either
+		self.sections.clear();
+		self.relocations.clear();
+		diagnostics.sort_by_key(|d| d.line);
+		Err(diagnostics.into_iter().map(|d| self.parsed_code.locate(d)).collect::<Vec<Diagnostic>>().into())
+	}
+	pub fn compile_from_file(&mut self, file_name: &str) -> Result<(), KgemuError> {
+		let parsed = self.parse_from_file(file_name);
+		self.compile_after(parsed)
+	}
+	pub fn compile_from_str(&mut self, code: &str) -> Result<(), KgemuError> {
+		let parsed = self.parse_from_str(code);
+		self.compile_after(parsed)
+	}
+	/// Compile even when some lines did not parse, so every error in the source is reported in one run
+	fn compile_after(&mut self, parsed: Result<(), KgemuError>) -> Result<(), KgemuError> {
+		match parsed {
+			Ok(()) => self.compile(),
+			Err(KgemuError::Diagnostics(mut diagnostics)) => {
+				if let Err(KgemuError::Diagnostics(more)) = self.compile() {
+					diagnostics.extend(more);
+					diagnostics.sort_by_key(|d| d.line);
+				}
+				self.complied_code.clear();
+				Err(diagnostics.into())
+			}
+			Err(e) => Err(e),
+		}
+	}
+	pub fn get_parsed_code(&self) -> &parse_code::ParsedCode {
+		&self.parsed_code
+	}
+	/// The rom image, starting at the device's rom_start
+	pub fn get_bin(&self) -> &Vec<u8> {
+		&self.complied_code
+	}
+	/// Every label and constant with its value, filled in by compile
+	pub fn get_symbols(&self) -> &symbols::SymbolTable {
+		&self.symbols
+	}
+	pub fn get_device(&self) -> &DeviceDefinition {
+		&self.device_def
+	}
+	/// Every output section with its addresses and data, for loading into an emulator
+	pub fn get_sections(&self) -> &Vec<Section> {
+		&self.sections
+	}
+	/// Places a linker fills in, only found when compiling a relocatable object
+	pub fn get_relocations(&self) -> &[Relocation] {
+		&self.relocations
+	}
+	/// Render an error from this complier like a compiler error, with the source line it is about
+	pub fn render_error(&self, error: &KgemuError, color: bool) -> String {
+		self.parsed_code.render_error(error, color)
+	}
+	/// Diagnostics raised with .warning in the last parse
+	pub fn get_warnings(&self) -> &[Diagnostic] {
+		&self.parsed_code.warnings
+	}
+	pub fn render_warnings(&self, color: bool) -> String {
+		self.parsed_code.render_warnings(color)
+	}
+	/// The rom image as a hex dump with addresses
+	pub fn get_bin_as_hex(&self) -> String {
+		output::to_hex_dump(&self.complied_code, self.device_def.rom_start)
+	}
+	/// The rom image as a dump of binary digits with addresses
+	pub fn get_bin_as_bin(&self) -> String {
+		output::to_binary_dump(&self.complied_code, self.device_def.rom_start)
+	}
+	/// The rom image written in a format, addressed from the start of rom.
+	/// ELF has every section, symbol and relocation rather than just the rom image
+	pub fn get_output(&self, format: OutputFormat) -> Vec<u8> {
+		match format {
+			OutputFormat::Elf => self.to_elf().to_bytes(),
+			_ => output::write(format, &self.complied_code, self.device_def.rom_start),
+		}
+	}
+	/// Write the rom image to a file, in the format its extension names when none is given
+	pub fn write_output(&self, file_name: &str, format: Option<OutputFormat>) -> Result<(), KgemuError> {
+		let format = format.unwrap_or(OutputFormat::from_file_name(file_name));
+		if self.relocatable && format != OutputFormat::Elf {
+			return Err(KgemuError::Image { file_name: file_name.to_string(), message: format!("a relocatable object can only be written as elf, not {}", format) });
+		}
+		fs::write(file_name, self.get_output(format)).map_err(|error| KgemuError::Io { file_name: file_name.to_string(), error })
+	}
+	/// A listing of the compiled code, each source line with its address, the code it became and the values it uses,
+	/// and a cross reference of every symbol
+	pub fn get_listing(&self) -> String {
+		listing::listing(&self.parsed_code, &self.language_def.processor_def, &self.sections, &self.symbols)
+	}
+	pub fn write_listing(&self, file_name: &str) -> Result<(), KgemuError> {
+		fs::write(file_name, self.get_listing()).map_err(|error| KgemuError::Io { file_name: file_name.to_string(), error })
+	}
+	/// The compiled code as an ELF file, an executable that starts at `_start` or a relocatable object.
+	/// Code sections get `$t` and `$d` mapping symbols so tools know which bytes are Thumb instructions
+	pub fn to_elf(&self) -> ElfFile {
+		let code = &self.parsed_code;
+		let mut symbols = Vec::new();
+		let mut sections = Vec::new();
+		for section in &self.sections {
+			let placement = self.device_def.placement(&section.name);
+			let commands: Vec<i32> = code.commands.iter().filter(|c| c.section == section.name).map(|c| c.address).collect();
+			let write = if placement.region == Region::Ram { elf::SHF_WRITE } else { 0 };
+			let execute = if commands.is_empty() { 0 } else { elf::SHF_EXECINSTR };
+			sections.push(ElfSection { section: section.clone(), flags: elf::SHF_ALLOC | write | execute });
+
+			let data = code.vars.iter().filter(|v| v.section == section.name && v.size > 0 && section.load_address.is_some()).map(|v| (v.address, "$d"));
+			let mut items: Vec<(i32, &str)> = commands.iter().map(|a| (*a, "$t")).chain(data).collect();
+			items.sort();
+			items.dedup_by(|b, a| a.1 == b.1);
+			symbols.extend(items.iter().map(|(address, name)| ElfSymbol { name: name.to_string(), value: *address as u32, section: section.name.clone(), defined: true, global: false }));
+		}
+		for symbol in self.symbols.symbols.iter().filter(|s| s.kind != SymbolKind::Local) {
+			symbols.push(ElfSymbol { name: symbol.name.clone(), value: symbol.value as u32, section: symbol.section.clone(), defined: true, global: symbol.global });
+		}
+		// A global that is not defined here is one this object expects another to define
+		for name in code.globals.iter().filter(|g| self.relocatable && self.symbols.get(g).is_none()) {
+			symbols.push(ElfSymbol { name: name.clone(), value: 0, section: String::new(), defined: false, global: true });
+		}
+		let entry = self.symbols.get("_start").filter(|s| s.kind != SymbolKind::Constant).map_or(self.device_def.rom_start, |s| s.value as u32);
+		ElfFile {
+			relocatable: self.relocatable,
+			little_endian: self.language_def.processor_def.little_endian,
+			entry,
+			sections,
+			symbols,
+			relocations: self.relocations.clone(),
+		}
+	}
+
+	/// Pass one: walk the lines in order, giving each label the address of the next item in its section,
+	/// then place the sections in rom and ram following the device.
+	/// Commands with an unknown format take no space, pass two reports them
+	fn assign_addresses(&mut self) -> Result<(), Vec<Diagnostic>> {
+		let processor = &self.language_def.processor_def;
+		let code = &mut self.parsed_code;
+		// Name, size so far and largest alignment of each section, in the order they are first used
+		let mut used: Vec<(String, u32, u32)> = Vec::new();
+		// The address of the item in its section, None when the section would grow past the 2 GiB an address can reach
+		let mut place = |name: &str, size: i32, align: i32| -> Option<i32> {
+			let i = match used.iter().position(|s| s.0 == name) {
+				Some(i) => i,
+				None => {
+					used.push((name.to_string(), 0, 1));
+					used.len() - 1
+				}
+			};
+			let section = &mut used[i];
+			let address = section.1;
+			section.1 = section.1.checked_add(size as u32).filter(|end| *end <= i32::MAX as u32)?;
+			section.2 = section.2.max(align as u32);
+			Some(address as i32)
+		};
+		let too_big = |file_name: &str, line: i32, span: Range<usize>, section: &str| Diagnostic::new(file_name, line, span, format!("the {} section grows past 2 GiB here", section))
+			.with_code(ErrorCode::DoesNotFit);
+		let mut diagnostics = Vec::new();
+		let (mut c, mut l, mut v) = (0, 0, 0);
+
+		for line in &code.lines {
+			while l < code.labels.len() && code.labels[l].line == line.index {
+				code.labels[l].address = place(&code.labels[l].section, 0, 1).unwrap_or(0);
+				l += 1;
+			}
+			while c < code.commands.len() && code.commands[c].line == line.index {
+				let command = &mut code.commands[c];
+				let size = processor.get_format(command.format).map_or(0, |f| f.size() as i32);
+				match place(&command.section, size * command.split.max(1), 1) {
+					Some(address) => command.address = address,
+					None => diagnostics.push(too_big(&code.file_name, command.line, command.span.clone(), &command.section)),
+				}
+				c += 1;
+			}
+			while v < code.vars.len() && code.vars[v].line == line.index {
+				let var = &mut code.vars[v];
+				if var.kind == VarKind::Align {
+					// parse_directive only makes alignments of up to 2^16 bytes
+					let boundary: i32 = var.value.parse().unwrap_or(1);
+					let at = place(&var.section, 0, boundary).unwrap_or(0);
+					var.size = (boundary - at % boundary) % boundary;
+				}
+				match place(&var.section, var.size, 1) {
+					Some(address) => var.address = address,
+					None => diagnostics.push(too_big(&code.file_name, var.line, var.span.clone(), &var.section)),
+				}
+				v += 1;
+			}
+		}
+		if !diagnostics.is_empty() {
+			return Err(diagnostics);
+		}
+
+		// Sections the device lists come first in its order, the rest in the order they are used
+		let device = &self.device_def;
+		let rank = |name: &str| device.sections.iter().position(|s| s.name == name).unwrap_or(device.sections.len());
+		used.sort_by_key(|s| rank(&s.0));
+		// Worked out past 32 bits so a program too big for the address space is reported rather than wrapping
+		let mut rom = device.rom_start as u64;
+		let mut ram = device.ram_start as u64;
+		let align_up = |value: u64, align: u32| value.div_ceil(align as u64) * align as u64;
+		self.sections.clear();
+		for region in [Region::Rom, Region::Ram] {
+			for (name, size, align) in &used {
+				let placement = device.placement(name);
+				if placement.region != region {
+					continue;
+				}
+				let align = placement.align.max(*align).max(1);
+				let (address, load_address) = match region {
+					Region::Rom => {
+						rom = align_up(rom, align) + *size as u64;
+						(rom - *size as u64, Some(rom - *size as u64))
+					}
+					Region::Ram => {
+						ram = align_up(ram, align) + *size as u64;
+						// The initial data of a ram section is stored in rom after the rom sections
+						let load_address = (!placement.uninitialized).then(|| {
+							rom = align_up(rom, align) + *size as u64;
+							rom - *size as u64
+						});
+						(ram - *size as u64, load_address)
+					}
+				};
+				let load_address = if placement.uninitialized { None } else { load_address };
+				self.sections.push(Section { name: name.clone(), address: address as u32, load_address: load_address.map(|a| a as u32), size: *size, data: Vec::new(), align });
+			}
+		}
+		if self.relocatable {
+			for section in &mut self.sections {
+				section.address = 0;
+				section.load_address = section.load_address.map(|_| 0);
+			}
+		}
+		// Sections of an object can go anywhere, the linker checks they fit
+		else {
+			let mut diagnostics = Vec::new();
+			for (memory, used, start, size) in [("rom", rom - device.rom_start as u64, device.rom_start, device.rom_size), ("ram", ram - device.ram_start as u64, device.ram_start, device.ram_size)] {
+				if used <= size as u64 {
+					continue;
+				}
+				let inside: Vec<String> = self.sections.iter()
+					.filter(|s| if memory == "rom" { s.load_address.is_some() } else { s.load_address != Some(s.address) })
+					.map(|s| format!("{} ({} bytes)", s.name, s.size))
+					.collect();
+				diagnostics.push(Diagnostic::new(&code.file_name, -1, 0..0, format!("the program does not fit in {}, it needs {} bytes and {} has {} from 0x{:08x}", memory, used, device.name, size, start))
+					.with_code(ErrorCode::DoesNotFit)
+					.with_hint(format!("{} holds {}", memory, inside.join(", "))));
+			}
+			if !diagnostics.is_empty() {
+				return Err(diagnostics);
+			}
+		}
+
+		let sections = &self.sections;
+		let base = |section: &str| sections.iter().find(|s| s.name == section).map_or(0, |s| s.address as i32);
+		code.labels.iter_mut().for_each(|l| l.address = l.address.wrapping_add(base(&l.section)));
+		code.commands.iter_mut().for_each(|c| c.address = c.address.wrapping_add(base(&c.section)));
+		code.vars.iter_mut().for_each(|v| v.address = v.address.wrapping_add(base(&v.section)));
+
+		for (i, label) in code.labels.iter().enumerate() {
+			// Numeric local labels can be defined any number of times
+			if label.name.bytes().all(|b| b.is_ascii_digit()) {
+				continue;
+			}
+			if let Some(first) = code.labels[..i].iter().find(|l| l.name == label.name) {
+				let start = code.lines.iter().find(|l| l.index == label.line).map_or(0, |l| l.text.find(&label.name).unwrap_or(0));
+				diagnostics.push(Diagnostic::new(&code.file_name, label.line, start..start + label.name.len(), format!("label `{}` is defined more than once", label.name))
+					.with_code(ErrorCode::DuplicateLabel)
+					.with_hint(format!("it is first defined on {}", code.describe_line(first.line))));
+			}
+			else if let Some(constant) = code.constants.iter().find(|c| c.name == label.name) {
+				let start = code.lines.iter().find(|l| l.index == label.line).map_or(0, |l| l.text.find(&label.name).unwrap_or(0));
+				diagnostics.push(Diagnostic::new(&code.file_name, label.line, start..start + label.name.len(), format!("label `{}` has the name of a constant", label.name))
+					.with_code(ErrorCode::DuplicateLabel)
+					.with_hint(format!("the constant is defined on {}", code.describe_line(constant.line))));
+			}
+		}
+		if diagnostics.is_empty() { Ok(()) } else { Err(diagnostics) }
+	}
+
+	/// Work out constants that use labels, now that labels have addresses.
+	/// Constants may use ones defined further down, so keep going while any more can be worked out
+	fn resolve_constants(&mut self) -> Result<(), Vec<Diagnostic>> {
+		loop {
+			let code = &self.parsed_code;
+			let solved: Vec<(usize, i64)> = code.constants.iter().enumerate()
+				.filter(|(_, c)| c.value.is_none())
+				.filter_map(|(i, c)| expression::evaluate(&c.expression, &symbols(code, 0, c.line)).ok().map(|v| (i, v)))
+				.collect();
+			if solved.is_empty() {
+				break;
+			}
+			for (i, value) in solved {
+				self.parsed_code.constants[i].value = Some(value);
+			}
+		}
+		let code = &self.parsed_code;
+		let diagnostics: Vec<Diagnostic> = code.constants.iter().filter(|c| c.value.is_none())
+			.filter_map(|c| expression::evaluate(&c.expression, &symbols(code, 0, c.line)).err()
+				.map(|e| expression_diagnostic(code, c.line, c.span.start, e)))
+			.collect();
+		if diagnostics.is_empty() { Ok(()) } else { Err(diagnostics) }
+	}
+
+	/// Pass two: encode every command and var into the data of its section.
+	/// Every command that can not be encoded is reported, not just the first
+	fn encode(&mut self) -> Result<(), Vec<Diagnostic>> {
+		let processor = &self.language_def.processor_def;
+		let code = &self.parsed_code;
+		let mut diagnostics = Vec::new();
+		let mut relocations = Vec::new();
+
+		let mut items: Vec<(&str, i32, Vec<u8>)> = Vec::new();
+		for command in &code.commands {
+			let bytes = processor.get_format(command.format)
+				.ok_or(Diagnostic::new(&code.file_name, command.line, command.span.clone(), format!("{} uses unknown format {}", command.op_code, command.format)).with_code(ErrorCode::Definition))
+				.and_then(|format| encode_command(code, processor, format, command, self.relocatable));
+			match bytes {
+				Ok(_) if self.device_def.placement(&command.section).uninitialized => {
+					diagnostics.push(Diagnostic::new(&code.file_name, command.line, command.span.clone(), format!("{} can only reserve space, it can not hold instructions", command.section))
+						.with_code(ErrorCode::DataInUninitialized));
+				}
+				Ok((bytes, relocation)) => {
+					items.push((&command.section, command.address, bytes));
+					relocations.extend(relocation);
+				}
+				Err(diagnostic) => diagnostics.push(diagnostic),
+			}
+		}
+		for var in &code.vars {
+			match var_bytes(var, code, processor.little_endian, self.relocatable) {
+				Ok((_, Some(_))) if self.device_def.placement(&var.section).uninitialized => {
+					diagnostics.push(Diagnostic::new(&code.file_name, var.line, var.span.clone(), format!("{} starts zeroed, it can not hold an address", var.section))
+						.with_code(ErrorCode::DataInUninitialized));
+				}
+				Ok((bytes, _)) if self.device_def.placement(&var.section).uninitialized && bytes.iter().any(|b| *b != 0) => {
+					diagnostics.push(Diagnostic::new(&code.file_name, var.line, var.span.clone(), format!("{} starts zeroed, it can not hold data that is not zero", var.section))
+						.with_code(ErrorCode::DataInUninitialized)
+						.with_hint("use .data for variables that start with a value".to_string()));
+				}
+				Ok((bytes, relocation)) => {
+					items.push((&var.section, var.address, bytes));
+					relocations.extend(relocation);
+				}
+				Err(diagnostic) => diagnostics.push(diagnostic),
+			}
+		}
+		if !diagnostics.is_empty() {
+			return Err(diagnostics);
+		}
+		let rank = |name: &str| self.sections.iter().position(|s| s.name == name);
+		relocations.sort_by_key(|r| (rank(&r.section), r.offset));
+		self.relocations = relocations;
+
+		for section in self.sections.iter_mut().filter(|s| s.load_address.is_some()) {
+			section.data = vec![0; section.size as usize];
+			for (_, address, bytes) in items.iter().filter(|i| i.0 == section.name) {
+				let start = (*address as u32 - section.address) as usize;
+				section.data[start..start + bytes.len()].copy_from_slice(bytes);
+			}
+		}
+		Ok(())
+	}
+}
+
+/// Everything stored in rom, from rom_start to the end of the last section loaded there
+pub fn rom_image(sections: &[Section], rom_start: u32) -> Vec<u8> {
+	let mut output: Vec<u8> = Vec::new();
+	for section in sections {
+		if let Some(load_address) = section.load_address {
+			let start = (load_address - rom_start) as usize;
+			let end = start + section.data.len();
+			if output.len() < end {
+				output.resize(end, 0);
+			}
+			output[start..end].copy_from_slice(&section.data);
+		}
+	}
+	output
+}
+
+/// Turn an operand string into its numeric value.
+/// Accepts registers (r3, h3), hex (0x4f) and decimal numbers
+pub fn operand_value(operand: &str) -> Result<u32, String> {
+	let operand = operand.trim();
+	let lower = operand.to_lowercase();
+	if let Some(hex) = lower.strip_prefix("0x") {
+		return u32::from_str_radix(hex, 16).map_err(|_| format!("\"{}\" is not a valid number", operand));
+	}
+	if let Some(reg) = lower.strip_prefix('r').or(lower.strip_prefix('h')) {
+		if let Ok(n) = reg.parse::<u32>() {
+			return Ok(n);
+		}
+	}
+	lower.parse::<u32>().map_err(|_| format!("\"{}\" is not a register or number", operand))
+}
+
+/// Turn a register list like "r0, r3, r6" into a bit per register
+pub fn register_list_value(list: &str) -> Result<u32, String> {
+	let mut value = 0;
+	for reg in list.split(',').map(|r| r.trim()).filter(|r| !r.is_empty()) {
+		value |= 1 << operand_value(reg)?;
+	}
+	Ok(value)
+}
+
+/// Build the instruction bytes for one command from its format, in memory order.
+/// A label given as an Offset is turned into the distance from PC to it.
+/// In an object a branch to a symbol the linker fills in holds the distance from PC to the start of it, and comes with a relocation.
+/// Errors point at the operand that could not be encoded
+fn encode_command(code: &parse_code::ParsedCode, processor: &ProcessorDefinition, format: &Format, command: &parse_code::ParsedCommand, relocatable: bool) -> Result<(Vec<u8>, Option<Relocation>), Diagnostic> {
+	let mut fields: Vec<(SegType, u32)> = Vec::new();
+	let mut relocation = None;
+	let parts = command.split.max(1) as u32;
+	let error = |i: usize, code_: ErrorCode, message: String| {
+		let span = command.operand_spans.get(i).cloned().unwrap_or(command.span.clone());
+		Diagnostic::new(&code.file_name, command.line, span, message).with_code(code_)
+	};
+
+	for (i, operand) in command.operands.iter().enumerate() {
+		let kind = operand_kind(operand.0);
+		// The operand as written, with the # the parser leaves out of immediates
+		let written = match command.operand_spans.get(i) {
+			Some(span) if span.len() == operand.1.len() + 1 => format!("#{}", operand.1),
+			_ => operand.1.clone(),
+		};
+		let nth = fields.iter().filter(|f| f.0 == operand.0).count();
+		let seg = format.segments.iter().filter(|s| s.seg_type == operand.0).nth(nth);
+		let width = seg.map_or(32, |s| s.width() * parts).min(32);
+
+		let value = match operand.0 {
+			// The Immediate field holds the word distance from PC, rounded down to a word, to the pool entry
+			SegType::Literal => {
+				let Some(entry) = command.literal.and_then(|v| code.vars.get(v)) else {
+					return Err(error(i, ErrorCode::BadOperand, format!("{} is not in a literal pool", written)));
+				};
+				let width = format.segments.iter().find(|s| s.seg_type == SegType::Immediate && s.values.is_none()).map_or(8, |s| s.width());
+				let distance = entry.address - ((command.address + processor.pc_offset) & !3);
+				let largest = ((1i32 << width) - 1) << command.scale;
+				if !(0..=largest).contains(&distance) {
+					return Err(error(i, ErrorCode::OutOfRange, format!("the literal pool is {} bytes away, out of range of {}", distance, command.op_code))
+						.with_hint(format!("{} can reach {} bytes ahead, put a .ltorg closer after this line where it is not run", command.op_code, largest)));
+				}
+				fields.push((SegType::Immediate, (distance >> command.scale) as u32));
+				continue;
+			}
+			SegType::RegisterList => register_list_value(&operand.1).map_err(|e| error(i, ErrorCode::BadOperand, e))?,
+			SegType::Immediate | SegType::Offset if !is_register(&operand.1) => {
+				let span = command.operand_spans.get(i).cloned().unwrap_or(command.span.clone());
+				let offset = span.end.saturating_sub(operand.1.len());
+				// An offset written without a # is a branch target, relative to PC which reads ahead of the instruction
+				let branch = operand.0 == SegType::Offset && written == operand.1;
+				let value = if relocatable {
+					relocatable_value(code, &operand.1, command.address, &command.section, command.line, branch)
+				}
+				else {
+					expression::evaluate(&operand.1, &symbols(code, command.address, command.line)).map(|value| (value, None))
+				};
+				// An operand an alias built is not all written out, its errors cover the text it was taken from
+				let (value, linked) = value
+					.map_err(|e| match expression_diagnostic(code, command.line, offset, e) {
+						diagnostic if span.len() < operand.1.len() => Diagnostic { span: span.clone(), ..diagnostic },
+						diagnostic => diagnostic,
+					})?;
+				if let (Some(_), false) = (&linked, branch) {
+					return Err(error(i, ErrorCode::Relocation, format!("{} {} is an address the linker fills in, {} can not hold one", kind, written, command.op_code))
+						.with_hint("load an address from a literal pool with `ldr rX, =address`".to_string()));
+				}
+				if branch {
+					let target = if is_label(&operand.1) { format!("label `{}`", operand.1) } else { format!("`{}`", operand.1) };
+					let distance = match linked {
+						Some(linked) => {
+							let kind = match (parts, width) {
+								(2, _) => RelocationKind::ThumbCall,
+								(1, 11) => RelocationKind::ThumbJump11,
+								(1, 8) => RelocationKind::ThumbJump8,
+								_ => return Err(error(i, ErrorCode::Relocation, format!("{} can not branch to {} in another section or file", command.op_code, target))),
+							};
+							// Sections of an object start at 0, so the address is the offset in the section
+							relocation = Some(Relocation { section: command.section.clone(), offset: command.address as u32, target: linked, kind });
+							value - processor.pc_offset as i64
+						}
+						None => value - (command.address as i64 + processor.pc_offset as i64),
+					};
+					if distance % (1 << command.scale) != 0 {
+						return Err(error(i, ErrorCode::Misaligned, format!("{} is {} bytes away, not a multiple of {}", target, distance, 1 << command.scale)));
+					}
+					let smallest = -(1i64 << (width - 1)) << command.scale;
+					let largest = ((1i64 << (width - 1)) - 1) << command.scale;
+					if distance < smallest || distance > largest {
+						return Err(error(i, ErrorCode::OutOfRange, format!("{} is out of range of {}", target, command.op_code))
+							.with_hint(format!("it is {} bytes away, {} can reach {} to {}", distance, command.op_code, smallest, largest)));
+					}
+					fields.push((operand.0, ((distance >> command.scale) as u32) & (u32::MAX >> (32 - width))));
+					continue;
+				}
+				value as u32
+			}
+			_ => operand_value(&operand.1).map_err(|e| error(i, ErrorCode::BadOperand, e))?,
+		};
+
+		let value = if matches!(operand.0, SegType::Immediate | SegType::Offset) {
+			if value % (1 << command.scale) != 0 {
+				return Err(error(i, ErrorCode::Misaligned, format!("{} {} is not a multiple of {}", kind, written, 1 << command.scale))
+					.with_hint(format!("{} stores this {} divided by {}", command.op_code, kind, 1 << command.scale)));
+			}
+			value >> command.scale
+		}
+		else {
+			value
+		};
+
+		if let Some(seg) = seg {
+			if seg.values.is_none() && width < 32 && value >> width != 0 {
+				let name = seg.name.clone().unwrap_or(format!("{:?}", seg.seg_type));
+				let largest = ((1u64 << width) - 1) << command.scale;
+				return Err(error(i, ErrorCode::OperandTooWide, format!("{} {} exceeds {}-bit {} field", kind, written, width, name))
+					.with_hint(format!("the largest {} {} can take here is {}", kind, command.op_code, largest)));
+			}
+		}
+		fields.push((operand.0, value));
+	}
+
+	// Split commands put the next lower slice of each wide field in each instruction
+	let mut bytes = Vec::new();
+	for part in 0..parts {
+		let mut part_fields: Vec<(SegType, u32)> = fields.iter().map(|f| {
+			match format.segments.iter().find(|s| s.seg_type == f.0 && s.values.is_none()) {
+				Some(seg) if parts > 1 && matches!(f.0, SegType::Offset | SegType::Immediate) => {
+					let width = seg.width();
+					(f.0, f.1 >> (width * (parts - 1 - part)) & (u32::MAX >> (32 - width)))
+				}
+				_ => *f,
+			}
+		}).collect();
+		if parts > 1 {
+			part_fields.insert(0, (SegType::Flag, part));
+		}
+		let mut instruction = format.encode(&part_fields)
+			.map_err(|e| Diagnostic::new(&code.file_name, command.line, command.span.clone(), format!("{}: {}", command.op_code, e)).with_code(ErrorCode::OperandTooWide))?;
+		if processor.little_endian {
+			instruction.reverse();
+		}
+		bytes.extend(instruction);
+	}
+	Ok((bytes, relocation))
+}
+
+/// True when an operand is a register like r3 or h3 rather than a value
+fn is_register(operand: &str) -> bool {
+	let lower = operand.trim().to_lowercase();
+	lower.strip_prefix('r').or(lower.strip_prefix('h')).is_some_and(|n| n.parse::<u32>().is_ok())
+}
+
+/// Look up symbols for an expression on a line, labels are their address, constants the value they have on that line
+/// and . is the address of the item it is in
+fn symbols(code: &parse_code::ParsedCode, here: i32, line: i32) -> impl Fn(&str) -> Option<i64> + '_ {
+	move |name| match name {
+		"." => Some(here as i64),
+		_ if parse_code::local_reference(name).is_some() => parse_code::local_reference(name)
+			.and_then(|(number, forward)| code.local_label(number, forward, line))
+			.map(|l| l.address as i64),
+		_ => code.constant(name, line).map_or_else(
+			|| code.labels.iter().find(|l| l.name == name).map(|l| l.address as i64),
+			|c| c.value,
+		),
+	}
+}
+
+/// Evaluate an expression in an object, where the linker adds the address of a section or of a symbol from another file.
+/// Moving each section and undefined symbol the expression uses, one at a time, shows which the value follows.
+/// It may follow one of them by its address, a branch's own section cancels out of the distance from PC.
+/// Returns the value with every section at 0 and every undefined symbol 0, and what the linker has to add to it
+fn relocatable_value(code: &parse_code::ParsedCode, text: &str, here: i32, section: &str, line: i32, branch: bool) -> Result<(i64, Option<RelocationTarget>), expression::ExpressionError> {
+	// A multiple of any alignment, so rounding to a boundary moves by the same amount
+	const MOVE: i64 = 0x1_0000;
+	let used: RefCell<Vec<RelocationTarget>> = RefCell::new(Vec::new());
+	let lookup = |moved: Option<RelocationTarget>| {
+		let used = &used;
+		move |name: &str| -> Option<i64> {
+			let label = |label: &parse_code::ParsedLabel| (RelocationTarget::Section(label.section.clone()), label.address as i64);
+			let (target, value) = match name {
+				"." => (RelocationTarget::Section(section.to_string()), here as i64),
+				_ if parse_code::local_reference(name).is_some() => parse_code::local_reference(name)
+					.and_then(|(number, forward)| code.local_label(number, forward, line))
+					.map(label)?,
+				_ => match (code.constant(name, line), code.labels.iter().find(|l| l.name == name)) {
+					(Some(constant), _) => return constant.value,
+					(None, Some(found)) => label(found),
+					(None, None) if symbols::is_local(name) => return None,
+					(None, None) => (RelocationTarget::Symbol(name.to_string()), 0),
+				},
+			};
+			let value = if moved.as_ref() == Some(&target) { value + MOVE } else { value };
+			used.borrow_mut().push(target);
+			Some(value)
+		}
+	};
+	let value = expression::evaluate(text, &lookup(None))?;
+	let targets = used.take();
+	let mut follows = Vec::new();
+	for target in targets.iter().enumerate().filter(|(i, t)| !targets[..*i].contains(t)).map(|(_, t)| t) {
+		let own = branch && *target == RelocationTarget::Section(section.to_string());
+		let moved = expression::evaluate(text, &lookup(Some(target.clone())))? - value - if own { MOVE } else { 0 };
+		if moved != 0 {
+			follows.push((target.clone(), moved));
+		}
+	}
+	match follows.as_slice() {
+		[] => Ok((value, None)),
+		[(target, MOVE)] => Ok((value, Some(target.clone()))),
+		_ => Err(expression::ExpressionError {
+			span: 0..text.len(),
+			code: ErrorCode::Relocation,
+			message: format!("`{}` can not be filled in by a linker, it has to be one address plus or minus a constant", text),
+			symbol: None,
+		}),
+	}
+}
+
+/// Point an expression error at the text it is about, offset is the column the expression starts at
+fn expression_diagnostic(code: &parse_code::ParsedCode, line: i32, offset: usize, error: expression::ExpressionError) -> Diagnostic {
+	let mut diagnostic = Diagnostic::new(&code.file_name, line, offset + error.span.start..offset + error.span.end, error.message).with_code(error.code);
+	if let Some((number, forward)) = error.symbol.as_deref().and_then(parse_code::local_reference) {
+		return diagnostic.with_hint(format!("there is no `{}:` {} this line", number, if forward { "below" } else { "at or above" }));
+	}
+	if let Some(name) = error.symbol.as_deref().filter(|s| code.externs.iter().any(|e| e == s)) {
+		return diagnostic.with_hint(format!("`{}` is declared .extern, compile with -c to leave it for a linker", name));
+	}
+	let names = code.labels.iter().map(|l| l.name.as_str()).chain(code.constants.iter().map(|c| c.name.as_str()));
+	if let Some(name) = error.symbol.as_deref().and_then(|s| parse_code::closest(s, names)) {
+		diagnostic = diagnostic.with_hint(format!("did you mean `{}`?", name));
+	}
+	diagnostic
+}
+
+/// True when an operand is written like a label name rather than a number or register
+fn is_label(operand: &str) -> bool {
+	parse_code::local_reference(operand).is_some()
+		|| (operand.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_' || c == '.')
+		&& operand.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '$'))
+}
+
+/// How an operand of a segment type is named in diagnostics
+fn operand_kind(seg_type: SegType) -> &'static str {
+	match seg_type {
+		SegType::Immediate => "immediate",
+		SegType::Offset => "offset",
+		SegType::RegisterList => "register list",
+		SegType::Literal => "literal",
+		SegType::Source | SegType::Destination => "register",
+		_ => "value",
+	}
+}
+
+/// Bytes for a var in memory order, and in an object the relocation that fills in an address it holds
+fn var_bytes(var: &ParsedVar, code: &parse_code::ParsedCode, little_endian: bool, relocatable: bool) -> Result<(Vec<u8>, Option<Relocation>), Diagnostic> {
+	let line = var.loaded_by.unwrap_or(var.line);
+	let error = |code_: ErrorCode, message: String| Diagnostic::new(&code.file_name, line, var.span.clone(), message).with_code(code_);
+	let mut relocation = None;
+	let mut bytes: Vec<u8> = match var.kind {
+		VarKind::Text => {
+			let text = var.value.strip_prefix('"').and_then(|v| v.strip_suffix('"')).unwrap_or(&var.value);
+			parse_code::unescape(text).map_err(|e| error(ErrorCode::BadOperand, e))?
+		}
+		VarKind::Align => Vec::new(),
+		VarKind::Value | VarKind::Fill => {
+			let text = var.value.trim_start_matches('#');
+			let offset = var.span.end.saturating_sub(text.len());
+			let value = if relocatable {
+				relocatable_value(code, text, var.address, &var.section, line, false)
+			}
+			else {
+				expression::evaluate(text, &symbols(code, var.address, line)).map(|value| (value, None))
+			};
+			let (value, linked) = value.map_err(|e| expression_diagnostic(code, line, offset, e))?;
+			let unit = if var.kind == VarKind::Fill { 1 } else { var.size.clamp(0, 4) as usize };
+			if let Some(linked) = linked {
+				let kind = match (var.kind, unit) {
+					(VarKind::Value, 4) => RelocationKind::Abs32,
+					(VarKind::Value, 2) => RelocationKind::Abs16,
+					(VarKind::Value, 1) => RelocationKind::Abs8,
+					_ => return Err(error(ErrorCode::Relocation, format!("{} is an address the linker fills in, space can only be filled with a constant", var.value))),
+				};
+				relocation = Some(Relocation { section: var.section.clone(), offset: var.address as u32, target: linked, kind });
+			}
+			let fits = unit == 4 || (-(1i64 << (unit * 8 - 1))..1i64 << (unit * 8)).contains(&value);
+			if !fits {
+				return Err(error(ErrorCode::OperandTooWide, format!("{} does not fit in {} byte{}", var.value, unit, if unit == 1 { "" } else { "s" })));
+			}
+			let value = value as u32;
+			let mut bytes = value.to_be_bytes()[4 - unit..].to_vec();
+			if little_endian {
+				bytes.reverse();
+			}
+			if var.kind == VarKind::Fill {
+				bytes.repeat(var.size.max(0) as usize)
+			}
+			else {
+				bytes
+			}
+		}
+	};
+	bytes.resize(var.size.max(0) as usize, 0);
+	Ok((bytes, relocation))
+}
+
+#[cfg(test)]
+mod tests {
+	use std::ops::Range;
 
+	use super::*;
+	use crate::prelude::AliasDefinition;
+
+	#[test]
+	fn compile_simple_program() {
+		let mut complier: Complier = Default::default();
+		complier.compile_from_str("_start:\n\tadd r2, r2, r3 @ add\n\tmov r1, #0x12\nloop:\tpush {r0, r3, LR}\n").unwrap();
+		assert_eq!(complier.get_bin(), &vec![0xd2, 0x18, 0x12, 0x21, 0x09, 0xb5]);
+		assert_eq!(complier.get_parsed_code().labels[1].address, 4);
 	}
-	pub fn set_def() {
-		
+
+	#[test]
+	fn compile_scaled_offset() {
+		let mut complier: Complier = Default::default();
+		complier.compile_from_str("ldr r5, [r5, #44]\nldr r5, [r5, #43]").unwrap_err();
+		complier.compile_from_str("ldr r5, [r5, #44]").unwrap();
+		// 0110 1 01011 101 101
+		assert_eq!(complier.get_bin(), &vec![0xed, 0x6a]);
 	}
-	pub fn load_from_file() {
 
+	#[test]
+	fn branch_offsets() {
+		let mut complier: Complier = Default::default();
+		complier.compile_from_str("\tb forward\nback:\tmov r0, #1\n\tbeq back\nforward:\tbl back\n\tswi 0").unwrap();
+		assert_eq!(complier.get_bin(), &vec![0x01, 0xe0, 0x01, 0x20, 0xfd, 0xd0, 0xff, 0xf7, 0xfc, 0xff, 0x00, 0xdf]);
+		assert_eq!(complier.get_parsed_code().labels[1].address, 6);
 	}
-	pub fn load_from_str() {
 
+	#[test]
+	fn label_errors() {
+		let mut complier: Complier = Default::default();
+		let far = format!("beq far\n{}far: b far", "mov r0, #1\n".repeat(130));
+		let error = complier.compile_from_str(&far).unwrap_err();
+		assert_eq!(error.diagnostics()[0].code, Some(ErrorCode::OutOfRange));
+		assert_eq!(error.diagnostics()[0].hints, vec!["it is 258 bytes away, BEQ can reach -256 to 254".to_string()]);
+
+		let error = complier.compile_from_str("loop: b lop\nloop: b loop").unwrap_err();
+		let diagnostics = error.diagnostics();
+		assert_eq!(diagnostics[0].code, Some(ErrorCode::UndefinedLabel));
+		assert_eq!(diagnostics[0].hints, vec!["did you mean `loop`?".to_string()]);
+		assert_eq!((diagnostics[1].code, diagnostics[1].line, diagnostics[1].span.clone()), (Some(ErrorCode::DuplicateLabel), 1, 0..4));
+	}
+
+	#[test]
+	fn data_sections() {
+		let mut complier: Complier = Default::default();
+		complier.compile_from_str(".data\nvalue: .hword 0x1234, -1\n.text\nstart: mov r0, #1\n.data\n.align 3\nname: .ascii \"hi\"\n.byte 7\n.text\nb start\n.word value").unwrap();
+		assert_eq!(complier.get_bin(), &vec![
+			// .text: mov, b, a word holding the address of value in ram
+			0x01, 0x20, 0xfd, 0xe7, 0, 0, 0, 0x20,
+			// initial data of .data is stored in rom after .text
+			0x34, 0x12, 0xff, 0xff, 0, 0, 0, 0, b'h', b'i', 7,
+		]);
+		let labels: Vec<(&str, i32)> = complier.get_parsed_code().labels.iter().map(|l| (l.name.as_str(), l.address)).collect();
+		assert_eq!(labels, vec![("value", 0x2000_0000), ("start", 0), ("name", 0x2000_0008)]);
+		let sections: Vec<(&str, u32, Option<u32>, u32)> = complier.get_sections().iter().map(|s| (s.name.as_str(), s.address, s.load_address, s.size)).collect();
+		assert_eq!(sections, vec![(".text", 0, Some(0), 8), (".data", 0x2000_0000, Some(8), 11)]);
+
+		complier.compile_from_str(".bss
+buffer: .space 16
+.text
+.word buffer").unwrap();
+		assert_eq!(complier.get_bin(), &vec![0, 0, 0, 0x20]);
+		assert_eq!(complier.get_sections()[1].load_address, None);
+		let error = complier.compile_from_str(".bss
+.word 1
+mov r0, #1").unwrap_err();
+		let codes: Vec<Option<ErrorCode>> = error.diagnostics().iter().map(|d| d.code).collect();
+		assert_eq!(codes, vec![Some(ErrorCode::DataInUninitialized), Some(ErrorCode::DataInUninitialized)]);
+
+		let error = complier.compile_from_str(".byte 256\n.hword -40000\n.word missing").unwrap_err();
+		let codes: Vec<Option<ErrorCode>> = error.diagnostics().iter().map(|d| d.code).collect();
+		assert_eq!(codes, vec![Some(ErrorCode::OperandTooWide), Some(ErrorCode::OperandTooWide), Some(ErrorCode::UndefinedLabel)]);
 	}
-	pub fn parse() {
 
+	#[test]
+	fn constant_expressions() {
+		let mut complier: Complier = Default::default();
+		complier.compile_from_str("start: mov r0, #(4 * 4) - 1\nmov r1, #'A'\nmov r2, #0b1010 | 1\nmov r3, #end - start\nend: .word start + 8, -1\n.byte 'z', ~0, ','").unwrap();
+		assert_eq!(complier.get_bin(), &vec![0x0f, 0x20, 0x41, 0x21, 0x0b, 0x22, 0x08, 0x23, 8, 0, 0, 0, 0xff, 0xff, 0xff, 0xff, b'z', 0xff, b',']);
+
+		let error = complier.compile_from_str("loop: mov r0, #lop + 1\n.word 0xffffffff + 1\nmov r1, #1 << 8\nb loop + 1").unwrap_err();
+		let codes: Vec<(Option<ErrorCode>, Range<usize>)> = error.diagnostics().iter().map(|d| (d.code, d.span.clone())).collect();
+		assert_eq!(codes, vec![
+			(Some(ErrorCode::UndefinedLabel), 15..18),
+			(Some(ErrorCode::Overflow), 6..20),
+			(Some(ErrorCode::OperandTooWide), 8..15),
+			(Some(ErrorCode::Misaligned), 2..10),
+		]);
+		assert_eq!(error.diagnostics()[0].hints, vec!["did you mean `loop`?"]);
 	}
-	pub fn parse_from_file() {
 
+	#[test]
+	fn constants_and_symbols() {
+		let mut complier: Complier = Default::default();
+		complier.compile_from_str(concat!(
+			".equ SIZE, 4\n",
+			".global main, LENGTH\n",
+			"main: mov r0, #SIZE * 2\n",
+			".set STEP, 1\n",
+			"add r0, #STEP\n",
+			".set STEP, STEP + 1\n",
+			"add r0, #STEP\n",
+			".equ LENGTH, end - table\n",
+			".data\n",
+			"table: .space SIZE\n",
+			"end: .word LENGTH\n",
+		)).unwrap();
+		assert_eq!(&complier.get_bin()[..6], &[0x08, 0x20, 0x01, 0x30, 0x02, 0x30]);
+		assert_eq!(&complier.get_bin()[8..], &[0, 0, 0, 0, 4, 0, 0, 0]);
+
+		let symbols = complier.get_symbols();
+		let table: Vec<(&str, i64, SymbolKind, &str, i32)> = symbols.symbols.iter().map(|s| (s.name.as_str(), s.value, s.kind, s.section.as_str(), s.line)).collect();
+		// By value then name like nm, not by line
+		assert_eq!(table, vec![
+			("main", 0, SymbolKind::Global, ".text", 2),
+			("STEP", 2, SymbolKind::Constant, "", 5),
+			("LENGTH", 4, SymbolKind::Constant, "", 7),
+			("SIZE", 4, SymbolKind::Constant, "", 0),
+			("table", 0x2000_0000, SymbolKind::Label, ".data", 9),
+			("end", 0x2000_0004, SymbolKind::Label, ".data", 10),
+		]);
+		assert_eq!(symbols.to_nm(complier.get_device()), concat!(
+			"00000000 T main\n",
+			"00000002 a STEP\n",
+			"00000004 A LENGTH\n",
+			"00000004 a SIZE\n",
+			"20000000 d table\n",
+			"20000004 d end\n",
+		));
+
+		let error = complier.compile_from_str(".equ A, 1\n.equ A, 2\n.equ B, missing + 1\nA: mov r0, #1").unwrap_err();
+		let codes: Vec<(i32, Option<ErrorCode>)> = error.diagnostics().iter().map(|d| (d.line, d.code)).collect();
+		assert_eq!(codes, vec![(1, Some(ErrorCode::DuplicateLabel)), (2, Some(ErrorCode::UndefinedLabel)), (3, Some(ErrorCode::DuplicateLabel))]);
+		assert_eq!(error.diagnostics()[1].span, 8..15);
 	}
-	pub fn parse_from_str() {
 
+	#[test]
+	fn local_labels() {
+		let mut complier: Complier = Default::default();
+		complier.compile_from_str(concat!(
+			"main:\tmov r0, #3\n",
+			"1:\tsub r0, #1\n",
+			"\tbne 1b\n",
+			"\tbeq 1f\n",
+			"\tb .Ldone\n",
+			"1:\tadd r0, #1\n",
+			".Ldone:\tb .Ldone\n",
+		)).unwrap();
+		assert_eq!(complier.get_bin(), &[0x03, 0x20, 0x01, 0x38, 0xfd, 0xd1, 0x00, 0xd0, 0x00, 0xe0, 0x01, 0x30, 0xfe, 0xe7]);
+		let kinds: Vec<(&str, SymbolKind)> = complier.get_symbols().symbols.iter().map(|s| (s.name.as_str(), s.kind)).collect();
+		assert_eq!(kinds, vec![("main", SymbolKind::Label), ("1", SymbolKind::Local), ("1", SymbolKind::Local), (".Ldone", SymbolKind::Local)]);
+		assert_eq!(complier.get_symbols().to_nm(complier.get_device()), "00000000 t main\n");
+
+		let error = complier.compile_from_str("1:\tb 1b\n\tb 2f\n2:\n\tb 2f\n").unwrap_err();
+		let found: Vec<(i32, Range<usize>, Option<ErrorCode>)> = error.diagnostics().iter().map(|d| (d.line, d.span.clone(), d.code)).collect();
+		assert_eq!(found, vec![(3, 3..5, Some(ErrorCode::UndefinedLabel))]);
+		assert_eq!(error.diagnostics()[0].hints, vec!["there is no `2:` below this line"]);
 	}
-	pub fn compile() {
 
+	#[test]
+	fn literal_pools() {
+		let mut complier: Complier = Default::default();
+		complier.compile_from_str(concat!(
+			"main:\tldr r0, =0xDEADBEEF\n",
+			"\tldr r1, =table\n",
+			"\tldr r2, =0xdeadbeef\n",
+			"\tldr r3, =3\n",
+			"\tswi 0\n",
+			"\t.ltorg\n",
+			"\tldr r4, =0x12345678\n",
+			"\tswi 1\n",
+			".data\n",
+			"table: .word 7\n",
+		)).unwrap();
+		assert_eq!(complier.get_bin(), &[
+			0x02, 0x48, 0x03, 0x49, 0x01, 0x4a, 0x03, 0x4b, 0x00, 0xdf, 0x00, 0x00,
+			0xef, 0xbe, 0xad, 0xde, 0x00, 0x00, 0x00, 0x20, 0x03, 0x00, 0x00, 0x00,
+			0x00, 0x4c, 0x01, 0xdf, 0x78, 0x56, 0x34, 0x12,
+			0x07, 0x00, 0x00, 0x00,
+		]);
+
+		let error = complier.compile_from_str("\tldr r0, =1\n\t.space 1024\n\tldr r1, =missing\n").unwrap_err();
+		let found: Vec<(i32, Range<usize>, Option<ErrorCode>)> = error.diagnostics().iter().map(|d| (d.line, d.span.clone(), d.code)).collect();
+		assert_eq!(found, vec![(0, 10..11, Some(ErrorCode::OutOfRange)), (2, 10..17, Some(ErrorCode::UndefinedLabel))]);
+		assert_eq!(error.diagnostics()[0].message, "the literal pool is 1024 bytes away, out of range of LDR");
+		complier.compile_from_str("\tldr r0, =1\n\t.space 1022\n").unwrap();
 	}
-	pub fn compile_from_file() {
 
+	#[test]
+	fn aliases() {
+		let mut complier: Complier = Default::default();
+		complier.compile_from_str("start:\tnop\n\tmov r1, r2\n\tadr r0, data\n\tswi 0\n\tswi 1\n\t.balign 4\ndata:\t.word 5\n").unwrap();
+		assert_eq!(complier.get_bin(), &[0xc0, 0x46, 0x11, 0x1c, 0x01, 0xa0, 0x00, 0xdf, 0x01, 0xdf, 0x00, 0x00, 0x05, 0x00, 0x00, 0x00]);
+
+		let mut def: LanguageDefinition = Default::default();
+		def.aliases.push(("INC2".to_string(), AliasDefinition {
+			regex: r"^inc2[ \t]+(?P<reg>[rR][0-9]+)".to_string(),
+			expansion: vec!["add ${reg}, #1".to_string(), "add ${reg}, #1".to_string()],
+		}));
+		assert_eq!(def.validate(), Ok(()));
+		complier.set_def(def);
+		complier.compile_from_str("\tinc2 r3\n").unwrap();
+		assert_eq!(complier.get_bin(), &[0x01, 0x33, 0x01, 0x33]);
+
+		let error = complier.compile_from_str("\tinc2 r9\n\tadr r0, 3\n").unwrap_err();
+		let found: Vec<(i32, Range<usize>, Option<ErrorCode>)> = error.diagnostics().iter().map(|d| (d.line, d.span.clone(), d.code)).collect();
+		assert_eq!(found, vec![(0, 6..8, Some(ErrorCode::InvalidOperands)), (1, 9..10, Some(ErrorCode::Misaligned))]);
+		assert_eq!(error.diagnostics()[0].notes, vec!["`inc2 r9` stands for `add r9, #1`"]);
 	}
-	pub fn compile_from_str() {
 
+	#[test]
+	fn relocatable_objects() {
+		let mut complier: Complier = Default::default();
+		let source = ".global main\n.extern puts\nmain:\tldr r0, =message\n\tbl puts\n\tbeq main\n.data\nmessage: .asciz \"hi\"\n\t.balign 4\ntable:\t.word main + 2, puts, table\n";
+		let error = complier.compile_from_str(source).unwrap_err();
+		assert_eq!(error.diagnostics()[0].hints, vec!["`puts` is declared .extern, compile with -c to leave it for a linker"]);
+
+		complier.set_relocatable(true);
+		complier.compile_from_str(source).unwrap();
+		let relocation = |section: &str, offset: u32, target: RelocationTarget, kind: RelocationKind| Relocation { section: section.to_string(), offset, target, kind };
+		let text = || RelocationTarget::Section(".text".to_string());
+		let data = || RelocationTarget::Section(".data".to_string());
+		let puts = || RelocationTarget::Symbol("puts".to_string());
+		assert_eq!(complier.get_relocations(), &[
+			relocation(".text", 2, puts(), RelocationKind::ThumbCall),
+			relocation(".text", 8, data(), RelocationKind::Abs32),
+			relocation(".data", 4, text(), RelocationKind::Abs32),
+			relocation(".data", 8, puts(), RelocationKind::Abs32),
+			relocation(".data", 12, data(), RelocationKind::Abs32),
+		]);
+		// The bytes hold the addend, a BL to the start of a symbol branches 4 back from PC
+		let sections = complier.get_sections();
+		assert_eq!((sections[0].address, &sections[0].data[2..6]), (0, &[0xff, 0xf7, 0xfe, 0xff][..]));
+		assert_eq!(&sections[1].data[4..], &[2, 0, 0, 0, 0, 0, 0, 0, 4, 0, 0, 0]);
+		assert!(complier.get_bin().is_empty());
+
+		let object = ElfFile::parse(&complier.get_output(OutputFormat::Elf)).unwrap();
+		assert!(object.relocatable);
+		assert_eq!(object.relocations, complier.get_relocations());
+		assert_eq!(object.sections, complier.to_elf().sections);
+		assert!(object.symbols.iter().any(|s| s.name == "puts" && !s.defined && s.global));
+
+		let error = complier.compile_from_str("\tmov r0, #puts\n.word table - puts\ntable: .space 4, table\n").unwrap_err();
+		let found: Vec<(i32, Range<usize>, Option<ErrorCode>)> = error.diagnostics().iter().map(|d| (d.line, d.span.clone(), d.code)).collect();
+		assert_eq!(found, vec![(0, 9..14, Some(ErrorCode::Relocation)), (1, 6..18, Some(ErrorCode::Relocation)), (2, 14..22, Some(ErrorCode::Relocation))]);
+		assert_eq!(error.diagnostics()[1].message, "`table - puts` can not be filled in by a linker, it has to be one address plus or minus a constant");
 	}
-	pub fn get_bin() {
 
+	#[test]
+	fn every_error_is_reported() {
+		let mut complier: Complier = Default::default();
+		let error = complier.compile_from_str("lsl r1, r3, #222\nfoo\nldr r5, [r5, #43]\nmov r1, #1").unwrap_err();
+		let codes: Vec<(i32, Option<ErrorCode>)> = error.diagnostics().iter().map(|d| (d.line, d.code)).collect();
+		assert_eq!(codes, vec![(0, Some(ErrorCode::OperandTooWide)), (1, Some(ErrorCode::UnknownMnemonic)), (2, Some(ErrorCode::Misaligned))]);
+		assert_eq!(error.diagnostics()[0].span, 12..16);
+		assert!(complier.get_bin().is_empty());
+
+		// A failed compile leaves nothing of the last one that worked
+		complier.compile_from_str("mov r1, #1").unwrap();
+		complier.parse_from_str("mov r1, #1\nb nowhere").unwrap();
+		assert!(complier.compile().is_err());
+		assert!(complier.get_bin().is_empty() && complier.get_sections().is_empty());
 	}
-	pub fn get_bin_as_hex() {
 
+	#[test]
+	fn huge_sections() {
+		let mut complier: Complier = Default::default();
+		let error = complier.compile_from_str(".space 2147483647\n.space 2147483647\n").unwrap_err();
+		let found: Vec<(i32, Range<usize>, Option<ErrorCode>)> = error.diagnostics().iter().map(|d| (d.line, d.span.clone(), d.code)).collect();
+		assert_eq!(found, vec![(1, 7..17, Some(ErrorCode::DoesNotFit))]);
+
+		let error = complier.compile_from_str(".space 3000000000\n.align 31\n.balign 0x20000\n.align 16\n").unwrap_err();
+		let found: Vec<(i32, Option<ErrorCode>)> = error.diagnostics().iter().map(|d| (d.line, d.code)).collect();
+		assert_eq!(found, vec![(0, Some(ErrorCode::BadOperand)), (1, Some(ErrorCode::BadOperand)), (2, Some(ErrorCode::BadOperand))]);
+		assert_eq!(error.diagnostics()[1].message, "31 is more than the largest alignment of 65536 bytes");
 	}
-	pub fn get_bin_as_bin() {
 
+	#[test]
+	fn program_must_fit() {
+		let mut complier: Complier = Default::default();
+		let error = complier.compile_from_str("\tmov r0, #1\n.data\n.space 0x10000000\n").unwrap_err();
+		let messages: Vec<&str> = error.diagnostics().iter().map(|d| d.message.as_str()).collect();
+		assert_eq!(messages, vec![
+			"the program does not fit in rom, it needs 268435460 bytes and Generic Thumb has 65536 from 0x00000000",
+			"the program does not fit in ram, it needs 268435456 bytes and Generic Thumb has 65536 from 0x20000000",
+		]);
+		assert!(error.diagnostics().iter().all(|d| d.code == Some(ErrorCode::DoesNotFit) && d.line == -1));
+		assert_eq!(error.diagnostics()[0].hints, vec!["rom holds .text (2 bytes), .data (268435456 bytes)".to_string()]);
+		assert!(complier.get_bin().is_empty());
+
+		// Zeroed space only needs room in ram, and a relocatable object is left for the linker to check
+		assert!(complier.compile_from_str(".bss\n.space 0x10000\n").is_ok());
+		complier.set_relocatable(true);
+		assert!(complier.compile_from_str(".bss\n.space 0x10001\n").is_ok());
 	}
-}
\ No newline at end of file
+}
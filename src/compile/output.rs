@@ -0,0 +1,395 @@
+//! Rom images written as raw binary, Intel HEX, Motorola S-records, ELF or a dump, and read back for the emulator
+
+use std::{fmt, str::FromStr};
+
+use super::elf::{self, ElfFile};
+
+/// Bytes written on each data record or dump line
+const HEX_RECORD: usize = 16;
+const BINARY_LINE: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+	/// The bytes as they are, starting at the first address
+	Binary,
+	/// Intel HEX, with extended linear address records above 64KiB
+	IntelHex,
+	/// Motorola S-records, S1, S2 or S3 depending on the highest address
+	Srec,
+	/// Addresses then 16 bytes in hex and as text, like hexdump -C
+	HexDump,
+	/// Addresses then 4 bytes written in binary
+	BinaryDump,
+	/// An ELF executable, with sections and symbols when the complier writes it
+	Elf,
+}
+
+impl OutputFormat {
+	/// The format a file name's extension suggests, raw binary when it is not known
+	pub fn from_file_name(file_name: &str) -> Self {
+		let extension = file_name.rsplit_once('.').map_or("", |e| e.1);
+		extension.parse().unwrap_or(OutputFormat::Binary)
+	}
+}
+
+impl FromStr for OutputFormat {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s.to_lowercase().as_str() {
+			"bin" | "binary" => Ok(OutputFormat::Binary),
+			"hex" | "ihex" => Ok(OutputFormat::IntelHex),
+			"srec" | "s19" | "s28" | "s37" | "mot" => Ok(OutputFormat::Srec),
+			"dump" | "hexdump" => Ok(OutputFormat::HexDump),
+			"bindump" => Ok(OutputFormat::BinaryDump),
+			"elf" | "o" | "axf" => Ok(OutputFormat::Elf),
+			_ => Err(format!("{} is not an output format, use bin, ihex, srec, dump, bindump or elf", s)),
+		}
+	}
+}
+
+impl fmt::Display for OutputFormat {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let name = match self {
+			OutputFormat::Binary => "bin",
+			OutputFormat::IntelHex => "ihex",
+			OutputFormat::Srec => "srec",
+			OutputFormat::HexDump => "dump",
+			OutputFormat::BinaryDump => "bindump",
+			OutputFormat::Elf => "elf",
+		};
+		write!(f, "{}", name)
+	}
+}
+
+/// Write data that starts at address start in a format.
+/// As ELF it is one little endian code section that starts running at its first byte
+pub fn write(format: OutputFormat, data: &[u8], start: u32) -> Vec<u8> {
+	match format {
+		OutputFormat::Elf => ElfFile::from_image(data, start, true).to_bytes(),
+		OutputFormat::Binary => data.to_vec(),
+		OutputFormat::IntelHex => to_intel_hex(data, start).into_bytes(),
+		OutputFormat::Srec => to_srec(data, start).into_bytes(),
+		OutputFormat::HexDump => to_hex_dump(data, start).into_bytes(),
+		OutputFormat::BinaryDump => to_binary_dump(data, start).into_bytes(),
+	}
+}
+
+/// Work out the format of an image from its contents and read it.
+/// Returns the address of the first byte and the bytes, gaps between records are filled with zeros.
+/// Raw binary has no address, it is taken to start at default_start.
+/// Code can look like text, so an image that does not read as the text format it looks like is raw binary.
+/// largest is the most bytes the records may span, like the size of rom
+pub fn read(image: &[u8], default_start: u32, largest: u32) -> Result<(u32, Vec<u8>), String> {
+	match detect(image) {
+		OutputFormat::Binary => Ok((default_start, image.to_vec())),
+		OutputFormat::Elf => read_as(OutputFormat::Elf, image, default_start, largest),
+		format => Ok(read_as(format, image, default_start, largest).unwrap_or((default_start, image.to_vec()))),
+	}
+}
+
+/// The format of an image, text formats are told apart by how their first line starts
+pub fn detect(image: &[u8]) -> OutputFormat {
+	if elf::is_elf(image) {
+		return OutputFormat::Elf;
+	}
+	let Ok(text) = std::str::from_utf8(image) else { return OutputFormat::Binary };
+	let Some(first) = text.lines().map(str::trim).find(|l| !l.is_empty()) else { return OutputFormat::Binary };
+	let bytes = first.as_bytes();
+	if bytes[0] == b':' {
+		OutputFormat::IntelHex
+	}
+	else if bytes[0] == b'S' && bytes.get(1).is_some_and(|b| b.is_ascii_digit()) {
+		OutputFormat::Srec
+	}
+	else if let Some((_, rest)) = first.split_once(char::is_whitespace).filter(|(a, _)| a.len() == 8 && a.bytes().all(|b| b.is_ascii_hexdigit())) {
+		match rest.split_whitespace().next() {
+			Some(word) if word.len() == 8 && word.bytes().all(|b| b == b'0' || b == b'1') => OutputFormat::BinaryDump,
+			_ => OutputFormat::HexDump,
+		}
+	}
+	else {
+		OutputFormat::Binary
+	}
+}
+
+/// Read an image known to be in format
+pub fn read_as(format: OutputFormat, image: &[u8], default_start: u32, largest: u32) -> Result<(u32, Vec<u8>), String> {
+	match format {
+		OutputFormat::Binary => return Ok((default_start, image.to_vec())),
+		// Everything stored in rom, where it is stored rather than where it runs
+		OutputFormat::Elf => {
+			let file = ElfFile::parse(image)?;
+			if file.relocatable {
+				return Err("a relocatable object has to be linked before it can be loaded".to_string());
+			}
+			return join(file.sections.into_iter().filter_map(|s| Some((s.section.load_address?, s.section.data))).collect(), largest);
+		}
+		_ => {}
+	}
+	let text = std::str::from_utf8(image).map_err(|_| format!("a {} image has to be text", format))?;
+	let chunks = match format {
+		OutputFormat::IntelHex => from_intel_hex(text)?,
+		OutputFormat::Srec => from_srec(text)?,
+		_ => from_dump(text)?,
+	};
+	join(chunks, largest)
+}
+
+/// Intel HEX data records of 16 bytes, an extended linear address record before the first one
+/// and each time the upper 16 bits of the address change, then an end of file record
+pub fn to_intel_hex(data: &[u8], start: u32) -> String {
+	let mut text = String::new();
+	let mut upper = None;
+	for (i, chunk) in data.chunks(HEX_RECORD).enumerate() {
+		let address = start.wrapping_add((i * HEX_RECORD) as u32);
+		if upper != Some(address >> 16) && (address >> 16 != 0 || upper.is_some()) {
+			text.push_str(&intel_record(4, 0, &((address >> 16) as u16).to_be_bytes()));
+		}
+		upper = Some(address >> 16);
+		// A record that runs past a 64KiB boundary is split so each half has the right upper address
+		let room = (0x10000 - (address & 0xffff)) as usize;
+		if chunk.len() > room {
+			text.push_str(&intel_record(0, address as u16, &chunk[..room]));
+			let next = address.wrapping_add(room as u32);
+			text.push_str(&intel_record(4, 0, &((next >> 16) as u16).to_be_bytes()));
+			text.push_str(&intel_record(0, next as u16, &chunk[room..]));
+			upper = Some(next >> 16);
+		}
+		else {
+			text.push_str(&intel_record(0, address as u16, chunk));
+		}
+	}
+	text.push_str(&intel_record(1, 0, &[]));
+	text
+}
+
+fn intel_record(kind: u8, address: u16, data: &[u8]) -> String {
+	let mut bytes = vec![data.len() as u8];
+	bytes.extend(address.to_be_bytes());
+	bytes.push(kind);
+	bytes.extend(data);
+	let checksum = bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)).wrapping_neg();
+	bytes.push(checksum);
+	format!(":{}\n", hex(&bytes))
+}
+
+/// Motorola S-records: an S0 header, data records with the shortest address that fits every byte,
+/// an S5 count of the data records and the matching S9, S8 or S7 end record holding the start address
+pub fn to_srec(data: &[u8], start: u32) -> String {
+	let last = start as u64 + data.len().max(1) as u64 - 1;
+	let (width, data_kind) = match last {
+		0..=0xffff => (2, 1),
+		0x1_0000..=0xff_ffff => (3, 2),
+		_ => (4, 3),
+	};
+	let mut text = srec_record(0, 2, 0, b"kgemu");
+	let mut count = 0;
+	for (i, chunk) in data.chunks(HEX_RECORD).enumerate() {
+		text.push_str(&srec_record(data_kind, width, start.wrapping_add((i * HEX_RECORD) as u32), chunk));
+		count += 1;
+	}
+	if count <= 0xffff {
+		text.push_str(&srec_record(5, 2, count, &[]));
+	}
+	text.push_str(&srec_record(10 - data_kind, width, start, &[]));
+	text
+}
+
+fn srec_record(kind: u8, width: usize, address: u32, data: &[u8]) -> String {
+	let mut bytes = vec![(width + data.len() + 1) as u8];
+	bytes.extend(&address.to_be_bytes()[4 - width..]);
+	bytes.extend(data);
+	let checksum = !bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+	bytes.push(checksum);
+	format!("S{}{}\n", kind, hex(&bytes))
+}
+
+/// Lines of an address, 16 bytes in hex split in two groups of 8, then the bytes as text
+/// with anything that is not printable shown as a dot
+pub fn to_hex_dump(data: &[u8], start: u32) -> String {
+	data.chunks(HEX_RECORD).enumerate().map(|(i, chunk)| {
+		let bytes: Vec<String> = chunk.iter().map(|b| format!("{:02x}", b)).collect();
+		let (left, right) = bytes.split_at(bytes.len().min(8));
+		let text: String = chunk.iter().map(|b| if b.is_ascii_graphic() || *b == b' ' { *b as char } else { '.' }).collect();
+		format!("{:08x}  {:<23}  {:<23}  |{}|\n", start.wrapping_add((i * HEX_RECORD) as u32), left.join(" "), right.join(" "), text)
+	}).collect()
+}
+
+/// Lines of an address then 4 bytes written as 8 binary digits each
+pub fn to_binary_dump(data: &[u8], start: u32) -> String {
+	data.chunks(BINARY_LINE).enumerate().map(|(i, chunk)| {
+		let bytes: Vec<String> = chunk.iter().map(|b| format!("{:08b}", b)).collect();
+		format!("{:08x}  {}\n", start.wrapping_add((i * BINARY_LINE) as u32), bytes.join(" "))
+	}).collect()
+}
+
+fn hex(bytes: &[u8]) -> String {
+	bytes.iter().map(|b| format!("{:02X}", b)).collect()
+}
+
+/// The bytes of a record written in hex after its first `skip` characters
+fn record_bytes(line: &str, skip: usize, number: usize) -> Result<Vec<u8>, String> {
+	let digits = line.as_bytes().get(skip..).unwrap_or(&[]);
+	if !digits.iter().all(u8::is_ascii_hexdigit) {
+		return Err(format!("line {}: a record is written in hex digits", number));
+	}
+	if digits.len() % 2 != 0 {
+		return Err(format!("line {}: odd number of hex digits", number));
+	}
+	// Every byte is a hex digit, so each pair is one ascii character each
+	Ok(digits.chunks(2).map(|pair| (hex_digit(pair[0]) << 4) | hex_digit(pair[1])).collect())
+}
+
+fn hex_digit(digit: u8) -> u8 {
+	(digit as char).to_digit(16).unwrap_or(0) as u8
+}
+
+fn from_intel_hex(text: &str) -> Result<Vec<(u32, Vec<u8>)>, String> {
+	let mut chunks = Vec::new();
+	let mut upper = 0u32;
+	for (i, line) in text.lines().enumerate().map(|(i, l)| (i + 1, l.trim())).filter(|(_, l)| !l.is_empty()) {
+		if !line.starts_with(':') {
+			return Err(format!("line {}: an Intel HEX record starts with :", i));
+		}
+		let bytes = record_bytes(line, 1, i)?;
+		if bytes.len() < 5 || bytes.len() != bytes[0] as usize + 5 {
+			return Err(format!("line {}: record length does not match its byte count", i));
+		}
+		if bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) != 0 {
+			return Err(format!("line {}: checksum does not match", i));
+		}
+		let address = u16::from_be_bytes([bytes[1], bytes[2]]) as u32;
+		let data = &bytes[4..bytes.len() - 1];
+		match bytes[3] {
+			// A segment base is added to the address, a linear one only has upper bits
+			0 => chunks.push((upper.wrapping_add(address), data.to_vec())),
+			1 => return Ok(chunks),
+			2 if data.len() == 2 => upper = (u16::from_be_bytes([data[0], data[1]]) as u32) << 4,
+			4 if data.len() == 2 => upper = (u16::from_be_bytes([data[0], data[1]]) as u32) << 16,
+			// Start addresses say where to run, the processor always starts at rom_start
+			3 | 5 => {}
+			kind => return Err(format!("line {}: record type {:02X} is not known", i, kind)),
+		}
+	}
+	Err("the Intel HEX image has no end of file record".to_string())
+}
+
+fn from_srec(text: &str) -> Result<Vec<(u32, Vec<u8>)>, String> {
+	let mut chunks = Vec::new();
+	for (i, line) in text.lines().enumerate().map(|(i, l)| (i + 1, l.trim())).filter(|(_, l)| !l.is_empty()) {
+		let kind = line.strip_prefix('S').and_then(|l| l.chars().next()).and_then(|c| c.to_digit(10))
+			.ok_or(format!("line {}: an S-record starts with S and a digit", i))?;
+		let bytes = record_bytes(line, 2, i)?;
+		if bytes.is_empty() || bytes.len() != bytes[0] as usize + 1 {
+			return Err(format!("line {}: record length does not match its byte count", i));
+		}
+		if bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) != 0xff {
+			return Err(format!("line {}: checksum does not match", i));
+		}
+		let width = match kind {
+			1 | 9 | 0 | 5 => 2,
+			2 | 8 | 6 => 3,
+			3 | 7 => 4,
+			_ => return Err(format!("line {}: record type S{} is not known", i, kind)),
+		};
+		if bytes.len() < width + 2 {
+			return Err(format!("line {}: record is too short for its address", i));
+		}
+		let address = bytes[1..=width].iter().fold(0u32, |a, b| a << 8 | *b as u32);
+		if (1..=3).contains(&kind) {
+			chunks.push((address, bytes[width + 1..bytes.len() - 1].to_vec()));
+		}
+	}
+	Ok(chunks)
+}
+
+/// Read a hex or binary dump, anything after a | on a line is the text column and is skipped
+fn from_dump(text: &str) -> Result<Vec<(u32, Vec<u8>)>, String> {
+	let mut chunks = Vec::new();
+	for (i, line) in text.lines().enumerate().map(|(i, l)| (i + 1, l.split('|').next().unwrap_or("").trim())).filter(|(_, l)| !l.is_empty()) {
+		let mut words = line.split_whitespace();
+		let address = words.next().and_then(|a| u32::from_str_radix(a, 16).ok())
+			.ok_or(format!("line {}: a dump line starts with its address in hex", i))?;
+		let data = words.map(|word| {
+			let radix = if word.len() == 8 { 2 } else { 16 };
+			u8::from_str_radix(word, radix).map_err(|_| format!("line {}: \"{}\" is not a byte", i, word))
+		}).collect::<Result<Vec<u8>, String>>()?;
+		chunks.push((address, data));
+	}
+	Ok(chunks)
+}
+
+/// Put chunks of data at their addresses in one block from the lowest address, no more than largest bytes long
+fn join(chunks: Vec<(u32, Vec<u8>)>, largest: u32) -> Result<(u32, Vec<u8>), String> {
+	let chunks: Vec<(u32, Vec<u8>)> = chunks.into_iter().filter(|c| !c.1.is_empty()).collect();
+	let Some(start) = chunks.iter().map(|c| c.0).min() else { return Ok((0, Vec::new())) };
+	let end = chunks.iter().map(|c| c.0 as u64 + c.1.len() as u64).max().unwrap_or(start as u64);
+	if end - start as u64 > largest as u64 {
+		return Err(format!("the image spans {} bytes from {:08x}, more than the {} it can have", end - start as u64, start, largest));
+	}
+	let mut data = Vec::new();
+	for (address, bytes) in chunks {
+		let at = (address - start) as usize;
+		if data.len() < at + bytes.len() {
+			data.resize(at + bytes.len(), 0);
+		}
+		data[at..at + bytes.len()].copy_from_slice(&bytes);
+	}
+	Ok((start, data))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn records() {
+		assert_eq!(to_intel_hex(&[0x02, 0x48], 0), ":020000000248B4\n:00000001FF\n");
+		assert_eq!(to_srec(&[0x02, 0x48], 0), "S00800006B67656D75DE\nS10500000248B0\nS5030001FB\nS9030000FC\n");
+		assert_eq!(to_hex_dump(b"kgemu\x00", 0x10), "00000010  6b 67 65 6d 75 00                                 |kgemu.|\n");
+		assert_eq!(to_binary_dump(&[0x02, 0x48], 0x2000_0000), "20000000  00000010 01001000\n");
+
+		// Rom in flash at 0x0800_0000 needs an extended linear address and S3 records
+		let hex = to_intel_hex(&[1, 2, 3], 0x0800_fffe);
+		assert_eq!(hex, ":020000040800F2\n:02FFFE000102FE\n:020000040801F1\n:0100000003FC\n:00000001FF\n");
+		assert!(to_srec(&[1], 0x0800_0000).contains("\nS3060800000001F0\n"));
+	}
+
+	#[test]
+	fn round_trip() {
+		let data: Vec<u8> = (0..=255u8).chain(0..40).collect();
+		for format in [OutputFormat::IntelHex, OutputFormat::Srec, OutputFormat::HexDump, OutputFormat::BinaryDump, OutputFormat::Elf] {
+			for start in [0, 0x1_fff8, 0x0800_0000] {
+				let image = write(format, &data, start);
+				assert_eq!(detect(&image), format);
+				assert_eq!(read(&image, 0, u32::MAX), Ok((start, data.clone())), "{} at {:x}", format, start);
+			}
+		}
+		assert_eq!(read(&[0x00, 0x20, 0x00, 0xd0], 0x100, u32::MAX), Ok((0x100, vec![0x00, 0x20, 0x00, 0xd0])));
+		assert_eq!(OutputFormat::from_file_name("out/blink.s19"), OutputFormat::Srec);
+	}
+
+	#[test]
+	fn record_addresses() {
+		// An empty record does not move the start of the image
+		assert_eq!(read(b":0000000000\n:01010000AA54\n:00000001FF\n", 0, u32::MAX), Ok((0x100, vec![0xaa])));
+		// A segment base of 0x12340 is added to the address 0xfff0
+		assert_eq!(read(b":020000021234B6\n:01FFF000AA66\n:00000001FF\n", 0, u32::MAX), Ok((0x2_2330, vec![0xaa])));
+	}
+
+	#[test]
+	fn bad_images() {
+		assert_eq!(read_as(OutputFormat::IntelHex, b":020000000248B5\n:00000001FF\n", 0, u32::MAX), Err("line 1: checksum does not match".to_string()));
+		assert_eq!(read_as(OutputFormat::IntelHex, b":020000000248B4\n", 0, u32::MAX), Err("the Intel HEX image has no end of file record".to_string()));
+		assert_eq!(read_as(OutputFormat::Srec, b"S10500000248B0\nS4030000FC\n", 0, u32::MAX), Err("line 2: record type S4 is not known".to_string()));
+		assert_eq!(read_as(OutputFormat::HexDump, b"00000000  0g\n", 0, u32::MAX), Err("line 1: \"0g\" is not a byte".to_string()));
+		assert_eq!(read_as(OutputFormat::IntelHex, ":02é0000248B4\n:00000001FF\n".as_bytes(), 0, u32::MAX), Err("line 1: a record is written in hex digits".to_string()));
+		assert_eq!(read_as(OutputFormat::Srec, b"S1050000zz48B0\n", 0, u32::MAX), Err("line 1: a record is written in hex digits".to_string()));
+		// Sniffed from the contents, an image that only looks like text is raw binary
+		assert_eq!(read(b":020000000248B5\n:00000001FF\n", 0, u32::MAX), Ok((0, b":020000000248B5\n:00000001FF\n".to_vec())));
+		assert_eq!(read(&[0x3a, 0x20, 0x30, 0x21], 0x100, u32::MAX), Ok((0x100, vec![0x3a, 0x20, 0x30, 0x21])));
+		// Records 4GB apart are not joined into one block
+		assert_eq!(read_as(OutputFormat::IntelHex, b":01000000AA55\n:02000004FFFFFC\n:01FFFF00BB46\n:00000001FF\n", 0, 0x1_0000), Err("the image spans 4294967296 bytes from 00000000, more than the 65536 it can have".to_string()));
+	}
+}
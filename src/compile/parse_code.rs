@@ -1,9 +1,10 @@
-use std::{str::FromStr, fs};
+use std::{borrow::Cow, str::FromStr, fs, ops::Range, path::{Path, PathBuf}, rc::Rc, sync::OnceLock};
 
 
 use regex::Regex;
 
-use crate::{definitions::{language, processor}, prelude::SegType};
+use crate::{definitions::{language, processor}, error::{Diagnostic, ErrorCode, KgemuError}, prelude::SegType};
+use super::{expression, macros::{self, ParsedMacro}};
 
 #[derive(Default, Debug, PartialEq, Eq, Clone, Copy)]
 pub enum SectionType {
@@ -29,7 +30,7 @@ impl FromStr for SectionType {
 			"literal" => Ok(SectionType::Literal),
 			"command" => Ok(SectionType::Command),
 			_ => {
-				if s.starts_with("op") && s.ends_with(&['0','1','2','3','4','5','6','7','8','9']) {
+				if s.starts_with("op") && s.ends_with(['0','1','2','3','4','5','6','7','8','9']) {
 					Ok(SectionType::Operand)
 				}
 				else {
@@ -46,29 +47,100 @@ impl FromStr for SectionType {
 pub struct ParsedCommand {
 	pub op_code: String,
 	pub address: i32,
+	/// Output section the command is placed in, like .text
+	pub section: String,
 	pub format: i32,
+	pub scale: i32,
+	/// Number of instructions the command is spread over, 0 or 1 for a single one
+	pub split: i32,
+	pub line: i32,
+	/// Columns of the line the command covers
+	pub span: Range<usize>,
 	pub operands: Vec<(processor::SegType, String)>,
+	/// Columns each operand covers, literal operands cover the mnemonic
+	pub operand_spans: Vec<Range<usize>>,
+	/// Index in vars of the literal pool entry an `ldr rX, =value` loads
+	pub literal: Option<usize>,
 }
 
 #[derive(Default, Debug)]
 pub struct ParsedLabel {
 	pub name: String,
 	pub address: i32,
+	pub section: String,
+	pub line: i32,
 }
 
+/// A name given to a value with .equ or .set
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub struct ParsedConstant {
+	pub name: String,
+	/// The expression as written
+	pub expression: String,
+	/// Worked out while parsing, or once labels have addresses when the expression uses one
+	pub value: Option<i64>,
+	/// Made with .set, so it may be given a new value further down
+	pub redefinable: bool,
+	pub line: i32,
+	/// Columns of the line the expression covers
+	pub span: Range<usize>,
+}
+
+/// What the value of a var holds and how it becomes bytes
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VarKind {
+	/// A number or label address stored in size bytes
+	#[default]
+	Value,
+	/// A quoted string, padded with zeros up to size
+	Text,
+	/// size copies of the low byte of value
+	Fill,
+	/// Zeros up to the next multiple of value, the size is worked out when addresses are assigned
+	Align,
+}
+
+/// Data placed by a directive like .word or .ascii
 #[derive(Default, Debug)]
 pub struct ParsedVar {
+	/// Label on the same line as the directive, if any
 	pub label: String,
 	pub address: i32,
+	pub section: String,
+	pub kind: VarKind,
 	pub size: i32,
 	pub value: String,
+	pub line: i32,
+	/// Columns of the line the value covers
+	pub span: Range<usize>,
+	/// For a literal pool entry, the line of the first ldr that loads it.
+	/// The value is worked out there and errors point at it
+	pub loaded_by: Option<i32>,
 }
 
 
 #[derive(Default, Debug)]
 pub struct ParsedLine {
-	pub sections: Vec<(SectionType, String)>,
+	/// Type, text and starting column of each part of the line
+	pub sections: Vec<(SectionType, String, usize)>,
 	pub index: i32,
+	/// The line as written, for showing it in diagnostics
+	pub text: String,
+	/// Where the line came from, index counts lines after includes and macros are expanded
+	pub origin: LineOrigin,
+}
+
+/// The file and line a parsed line came from, and how it was reached
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub struct LineOrigin {
+	/// File the line is written in, empty for code parsed from a string
+	pub file_name: String,
+	/// Index of the line in that file
+	pub line: i32,
+	/// File and line of each .include the file was reached through, outermost first
+	pub includes: Vec<(String, i32)>,
+	/// Macro name, file and line of each invocation the line was expanded from, outermost first
+	pub expansion: Vec<(String, String, i32)>,
 }
 
 #[derive(Default)]
@@ -79,104 +151,1204 @@ pub struct ParsedCode {
 	pub labels: Vec<ParsedLabel>,
 	pub vars: Vec<ParsedVar>,
 	pub commands: Vec<ParsedCommand>,
+	/// Every .equ and .set in the order they are written
+	pub constants: Vec<ParsedConstant>,
+	/// Symbols named by .global
+	pub globals: Vec<String>,
+	/// Symbols named by .extern, defined in another file
+	pub externs: Vec<String>,
+	/// Macros in the order they are defined
+	pub macros: Vec<ParsedMacro>,
+	/// Folders .include looks in after the folder of the file it is in
+	pub include_paths: Vec<String>,
+	/// Symbols defined from outside the code, like -D on the command line
+	pub defines: Vec<(String, i64)>,
+	/// Diagnostics raised with .warning, they do not stop the code compiling
+	pub warnings: Vec<Diagnostic>,
+	/// The regexes of the language definition, compiled when parsing starts unless the ones given are of the same definition
+	pub regexes: Option<Rc<LanguageRegexes>>,
+}
+
+/// Every regex of a language definition compiled once, in the same order as the definition
+#[derive(Debug, Clone)]
+pub struct LanguageRegexes {
+	lines: Vec<Regex>,
+	/// One list for each command, with a regex for each version
+	commands: Vec<Vec<Regex>>,
+	aliases: Vec<Regex>,
+}
+
+impl LanguageRegexes {
+	pub fn new(def: &language::LanguageDefinition) -> Result<Self, String> {
+		let lines = def.regex_list.iter()
+			.map(|re| Regex::new(re).map_err(|e| format!("line regex does not compile: {}", e)))
+			.collect::<Result<_, _>>()?;
+		let commands = def.commands.iter()
+			.map(|cmd| cmd.1.iter()
+				.map(|version| Regex::new(&version.regex).map_err(|e| format!("regex for {} does not compile: {}", cmd.0, e)))
+				.collect::<Result<_, _>>())
+			.collect::<Result<_, _>>()?;
+		let aliases = def.aliases.iter()
+			.map(|(name, alias)| Regex::new(&alias.regex).map_err(|e| format!("regex for alias {} does not compile: {}", name, e)))
+			.collect::<Result<_, _>>()?;
+		Ok(LanguageRegexes { lines, commands, aliases })
+	}
+
+	/// True when these are the regexes of def, so they can be used to parse with it
+	pub fn matches(&self, def: &language::LanguageDefinition) -> bool {
+		let same = |regexes: &[Regex], sources: Vec<&String>| regexes.len() == sources.len() && regexes.iter().zip(sources).all(|(r, s)| r.as_str() == s);
+		same(&self.lines, def.regex_list.iter().collect())
+			&& same(&self.aliases, def.aliases.iter().map(|a| &a.1.regex).collect())
+			&& self.commands.len() == def.commands.len()
+			&& self.commands.iter().zip(&def.commands).all(|(regexes, cmd)| same(regexes, cmd.1.iter().map(|v| &v.regex).collect()))
+	}
+}
+
+/// A register written as r and a number, to point at one that is out of range
+fn register_regex() -> &'static Regex {
+	static REGISTER: OnceLock<Regex> = OnceLock::new();
+	REGISTER.get_or_init(|| Regex::new(r"\b[rR]([0-9]+)\b").unwrap())
 }
 
 impl ParsedCode {
-	/// takes a line and uses a def regex to parse into parts
-	pub fn parse_line(&self, line: &str, def: &language::LanguageDefinition) -> ParsedLine {
-		let mut parsed_line: ParsedLine = Default::default();
+	/// The definition of a constant in use on a line, the last one above it or the first one when it is used before it is defined
+	pub fn constant(&self, name: &str, line: i32) -> Option<&ParsedConstant> {
+		let mut definitions = self.constants.iter().filter(|c| c.name == name);
+		let first = definitions.clone().next();
+		definitions.rfind(|c| c.line <= line).or(first)
+	}
+
+	/// The label a numeric local reference like `1b` or `2f` on a line means,
+	/// the last `1:` at or above the line or the first `2:` below it
+	pub fn local_label(&self, number: &str, forward: bool, line: i32) -> Option<&ParsedLabel> {
+		let mut labels = self.labels.iter().filter(|l| l.name == number);
+		if forward { labels.find(|l| l.line > line) } else { labels.rfind(|l| l.line <= line) }
+	}
+
+	/// Line of the file a parsed line came from
+	pub fn source_line(&self, index: i32) -> i32 {
+		self.lines.iter().find(|l| l.index == index).map_or(index, |l| l.origin.line)
+	}
+
+	/// How a hint names the line a parsed line came from, "line 3" or "file.s:3" when it is in another file
+	pub fn describe_line(&self, index: i32) -> String {
+		match self.lines.iter().find(|l| l.index == index) {
+			Some(l) if l.origin.file_name != self.file_name => format!("{}:{}", l.origin.file_name, l.origin.line + 1),
+			Some(l) => format!("line {}", l.origin.line + 1),
+			None if index < 0 => "the command line".to_string(),
+			None => format!("line {}", index + 1),
+		}
+	}
+
+	/// The line as written in its file, not from a macro expansion
+	fn written_line(&self, file_name: &str, line: i32) -> Option<&ParsedLine> {
+		self.lines.iter().find(|l| l.origin.file_name == file_name && l.origin.line == line && l.origin.expansion.is_empty())
+	}
 
-			for re in &def.regex_list {
-				let regex = Regex::new(re.as_str()).unwrap();
+	/// Point a diagnostic made for a parsed line at the file and line it came from.
+	/// A line expanded from a macro points into the macro body, with a note for each invocation it is inside,
+	/// and a line from an included file has a note for each .include it was reached through
+	pub fn locate(&self, mut diagnostic: Diagnostic) -> Diagnostic {
+		let Some(line) = self.lines.iter().find(|l| l.index == diagnostic.line) else { return diagnostic };
+		let origin = &line.origin;
+		diagnostic.line = origin.line;
+		diagnostic.file_name = origin.file_name.clone();
+		let place = |file_name: &str, line: i32| if file_name == origin.file_name { format!("line {}", line + 1) } else { format!("{}:{}", file_name, line + 1) };
 
+		// The span is in the expanded text, find the same text in the body as written
+		if let Some(written) = self.written_line(&origin.file_name, origin.line).filter(|_| !origin.expansion.is_empty()) {
+			let piece = line.text.get(diagnostic.span.clone()).unwrap_or("");
+			diagnostic.span = match written.text.find(piece).filter(|_| !piece.is_empty()) {
+				Some(at) => at..at + piece.len(),
+				None => {
+					let start = written.text.len() - written.text.trim_start().len();
+					start..written.text.trim_end().len().max(start)
+				}
+			};
+			if written.text != line.text {
+				diagnostic = diagnostic.with_note(format!("the line expands to `{}`", line.text.trim()));
+			}
+		}
+		// A macro invoking itself over and over is one note with a count
+		let mut invocations = origin.expansion.iter().rev().peekable();
+		while let Some(invocation) = invocations.next() {
+			let mut times = 1;
+			while invocations.next_if(|next| *next == invocation).is_some() {
+				times += 1;
+			}
+			let times = if times > 1 { format!(" ({} times)", times) } else { String::new() };
+			diagnostic = diagnostic.with_note(format!("in expansion of macro `{}` invoked on {}{}", invocation.0, place(&invocation.1, invocation.2), times));
+		}
+		for (file_name, line) in origin.includes.iter().rev() {
+			diagnostic = diagnostic.with_note(format!("included from {}:{}", file_name, line + 1));
+		}
+		diagnostic
+	}
+
+	/// The compiled regexes of the definition, compiled here when parsing did not start from parse_from_str with it
+	fn regexes<'a>(&'a self, def: &language::LanguageDefinition, index: i32, span: Range<usize>) -> Result<Cow<'a, LanguageRegexes>, Diagnostic> {
+		match &self.regexes {
+			Some(regexes) if regexes.matches(def) => Ok(Cow::Borrowed(regexes)),
+			_ => LanguageRegexes::new(def).map(Cow::Owned)
+				.map_err(|e| Diagnostic::new(&self.file_name, index, span, e).with_code(ErrorCode::Definition)),
+		}
+	}
+
+	/// takes a line and uses a def regex to parse into parts
+	pub fn parse_line(&self, line: &str, index: i32, def: &language::LanguageDefinition) -> Result<ParsedLine, Diagnostic> {
+		let mut parsed_line = ParsedLine { index, text: line.to_string(), ..Default::default() };
+		let mut matched = false;
+
+			for regex in &self.regexes(def, index, 0..line.len())?.lines {
 				if let Some(caps) = regex.captures(line) {
-					for n in regex.capture_names().into_iter() {
-						match n {
-							None => {}
-							Some(name) => {
-								if let Some(cap) = &caps.name(name) {
-									let section = (SectionType::from_str(name).unwrap(), cap.as_str().to_string());
-									parsed_line.sections.push(section);
-									//print!("{}: {} | ", name, cap.as_str());
-								}
-							}
+					matched = true;
+					for name in regex.capture_names().flatten() {
+						if let Some(cap) = &caps.name(name) {
+							let section_type = SectionType::from_str(name)
+								.map_err(|e| Diagnostic::new(&self.file_name, index, cap.range(), e).with_code(ErrorCode::Definition))?;
+							parsed_line.sections.push((section_type, cap.as_str().to_string(), cap.start()));
 						}
 					}
 				}
 			}
 
-		parsed_line
+		if !matched {
+			let start = line.len() - line.trim_start().len();
+			return Err(Diagnostic::new(&self.file_name, index, start..line.trim_end().len(), "could not parse line".to_string()).with_code(ErrorCode::BadLine));
+		}
+		Ok(parsed_line)
 	}
 
-	pub fn parse_command(&self, command: String, def: &language::LanguageDefinition ) -> Option<ParsedCommand> {
-		
+	/// Match command text against every version of every command.
+	/// column is where the command starts in its line, used for the span of the command and any error
+	pub fn parse_command(&self, command: &str, index: i32, column: usize, def: &language::LanguageDefinition) -> Result<ParsedCommand, Diagnostic> {
+		let command = command.trim_end();
+		let span = column..column + command.len();
+		let mnemonic = command.split_whitespace().next().unwrap_or(command);
 
+		let regexes = self.regexes(def, index, span.clone())?;
 		// For each command word
-		for cmd in &def.commands {
-			// For each version of the command
-			for cmd_version in &cmd.1 {
-				// Get the regex for that version of the command
-				let re = Regex::new(cmd_version.regex.as_str()).unwrap();
-				// If the command fits the regex start parsing the captures
-				if let Some(caps) = re.captures(&command) {
-					let mut parsed_command: ParsedCommand = Default::default();
-					parsed_command.op_code = cmd.0.clone();
-					parsed_command.format = cmd_version.format_index;
-					for seg in &cmd_version.segments {
-						match seg.0 {
-							SegType::Op => {
-								parsed_command.operands.push((seg.0,seg.1.clone()));
-							}
-							SegType::Flag => {
-								parsed_command.operands.push((seg.0,seg.1.clone()));
-							}
-							_ => {
-								if let Some(cap) = caps.name(seg.1.as_str()) {
-									parsed_command.operands.push((seg.0, cap.as_str().to_string()));
-								}
+		for (cmd, versions) in def.commands.iter().zip(&regexes.commands) {
+			// For each version of the command, with its regex
+			for (cmd_version, re) in cmd.1.iter().zip(versions) {
+				// If the whole command fits the regex start parsing the captures
+				let Some(caps) = re.captures(command) else { continue };
+				if caps.get(0).map(|m| m.end()) != Some(command.len()) {
+					continue;
+				}
+				let mut parsed_command = ParsedCommand {
+					op_code: cmd.0.clone(),
+					format: cmd_version.format_index,
+					scale: cmd_version.scale,
+					split: cmd_version.split,
+					line: index,
+					span: span.clone(),
+					..Default::default()
+				};
+				for seg in &cmd_version.segments {
+					match seg.0 {
+						SegType::Op | SegType::Flag | SegType::Condition => {
+							parsed_command.operands.push((seg.0,seg.1.clone()));
+							parsed_command.operand_spans.push(column..column + mnemonic.len());
+						}
+						_ => {
+							if let Some(cap) = caps.name(seg.1.as_str()) {
+								parsed_command.operands.push((seg.0, cap.as_str().to_string()));
+								// Include the # of an immediate so it is underlined as written
+								let start = if command[..cap.start()].ends_with('#') { cap.start() - 1 } else { cap.start() };
+								parsed_command.operand_spans.push(column + start..column + cap.end());
 							}
 						}
 					}
-					return Some(parsed_command);
+				}
+				return Ok(parsed_command);
+			}
+		}
+
+		let names = || def.commands.iter().map(|c| c.0.as_str()).chain(def.aliases.iter().map(|a| a.0.as_str()));
+		if names().any(|name| name.eq_ignore_ascii_case(mnemonic)) {
+			let mut diagnostic = Diagnostic::new(&self.file_name, index, span, format!("invalid operands for {}: \"{}\"", mnemonic.to_uppercase(), command))
+				.with_code(ErrorCode::InvalidOperands);
+			// Point at a register that is out of range, the most common reason none of the versions match
+			let register = register_regex().captures_iter(command).find(|c| c[1].parse::<u32>().is_ok_and(|n| n > 7));
+			if let Some(register) = register {
+				let m = register.get(0).map_or(0..0, |m| m.range());
+				let n: u32 = register[1].parse().unwrap_or(0);
+				diagnostic.span = column + m.start..column + m.end;
+				diagnostic = diagnostic.with_hint("did you mean `r7`? low registers are r0-r7".to_string());
+				if n < def.processor_def.num_register as u32 {
+					diagnostic = diagnostic.with_hint(format!("where a hi register is allowed r{} is written `h{}`", n, n - 8));
+				}
+			}
+			Err(diagnostic)
+		}
+		else {
+			let mut diagnostic = Diagnostic::new(&self.file_name, index, column..column + mnemonic.len(), format!("unknown mnemonic \"{}\"", mnemonic))
+				.with_code(ErrorCode::UnknownMnemonic);
+			if let Some(name) = closest(mnemonic, names()) {
+				diagnostic = diagnostic.with_hint(format!("did you mean `{}`?", name.to_lowercase()));
+			}
+			Err(diagnostic)
+		}
+	}
+
+	/// Replace a command matching an alias with the commands it stands for, None when no alias matches.
+	/// Operand spans point back at the text each operand was taken from, anything else covers the whole command
+	pub fn parse_alias(&self, command: &str, index: i32, column: usize, def: &language::LanguageDefinition) -> Result<Option<Vec<ParsedCommand>>, Diagnostic> {
+		let command = command.trim_end();
+		let span = column..column + command.len();
+		let regexes = self.regexes(def, index, span.clone())?;
+		for (alias, re) in def.aliases.iter().map(|a| &a.1).zip(&regexes.aliases) {
+			let Some(caps) = re.captures(command) else { continue };
+			if caps.get(0).map(|m| m.end()) != Some(command.len()) {
+				continue;
+			}
+
+			let mut commands = Vec::new();
+			for line in &alias.expansion {
+				// Where each capture was put in the text and where it was taken from
+				let mut text = String::new();
+				let mut moved: Vec<(Range<usize>, Range<usize>)> = Vec::new();
+				let mut rest = line.as_str();
+				while let Some((before, after)) = rest.split_once("${") {
+					text.push_str(before);
+					let Some((group, after)) = after.split_once('}') else { break };
+					if let Some(cap) = caps.name(group) {
+						moved.push((text.len()..text.len() + cap.len(), column + cap.start()..column + cap.end()));
+						text.push_str(cap.as_str());
+					}
+					rest = after;
+				}
+				text.push_str(rest);
+				let place = |s: &Range<usize>| moved.iter().find(|(to, _)| s.start < to.end && to.start < s.end).map_or(span.clone(), |m| m.1.clone());
+
+				match self.parse_command(&text, index, 0, def) {
+					Ok(mut parsed) => {
+						parsed.span = span.clone();
+						parsed.operand_spans = parsed.operand_spans.iter().map(place).collect();
+						commands.push(parsed);
+					}
+					Err(mut diagnostic) => {
+						diagnostic.span = place(&diagnostic.span);
+						return Err(diagnostic.with_note(format!("`{}` stands for `{}`", command, text)));
+					}
 				}
 			}
+			return Ok(Some(commands));
 		}
-		None
+		Ok(None)
 	}
 
-	pub fn parse_from_file(&mut self, file_name: &str, def: language::LanguageDefinition )  {
+	pub fn parse_from_file(&mut self, file_name: &str, def: &language::LanguageDefinition) -> Result<(), KgemuError> {
 		
 		
 		self.file_name = file_name.to_string();
 
 		// Get the contents from a filer	
 		let contents = fs::read_to_string(file_name)
-			.expect("Could not open file.");
+			.map_err(|error| KgemuError::Io { file_name: file_name.to_string(), error })?;
 
-		for line in contents.lines() {
-			let new_line = self.parse_line(line, &def);
+		self.parse_from_str(&contents, def)
+	}
 
-			
-			self.lines.push(new_line);
+	/// Parse source text line by line, collecting the commands, labels and data it defines.
+	/// Keeps going after a bad line so every problem in the source is reported at once
+	pub fn parse_from_str(&mut self, contents: &str, def: &language::LanguageDefinition) -> Result<(), KgemuError> {
+		self.file_size = contents.len() as i32;
+		if !self.regexes.as_ref().is_some_and(|r| r.matches(def)) {
+			let regexes = LanguageRegexes::new(def)
+				.map_err(|e| Diagnostic::new(&self.file_name, -1, 0..0, e).with_code(ErrorCode::Definition))?;
+			self.regexes = Some(Rc::new(regexes));
+		}
+		let mut diagnostics = Vec::new();
+		let mut section = ".text".to_string();
+
+		// Lines are taken from the end, includes and macro expansions are pushed back on in front of the rest
+		let mut pending: Vec<(String, LineOrigin)> = file_lines(contents, LineOrigin { file_name: self.file_name.clone(), ..Default::default() });
+		// The macro being defined and how many .macro lines deep inside it the body is
+		let mut defining: Option<(ParsedMacro, usize)> = None;
+		// The .if blocks the line is inside, innermost last
+		let mut conditions: Vec<Condition> = Vec::new();
+		// Commands loading a literal that is not in a pool yet
+		let mut waiting: Vec<usize> = Vec::new();
+		let mut expansions = 0;
+		let mut index = 0;
+
+		// Defined symbols act like constants set before the first line
+		for (name, value) in &self.defines {
+			self.constants.push(ParsedConstant { name: name.clone(), expression: value.to_string(), value: Some(*value), redefinable: true, line: -1, span: 0..0 });
+		}
+
+		while let Some((line, origin)) = pending.pop() {
+			let line = line.as_str();
+			index += 1;
+			let index = index - 1;
+			let bare = ParsedLine { index, text: line.to_string(), origin: origin.clone(), ..Default::default() };
+
+			// Collect the body of a macro without parsing it, a nested .macro needs its own .endm
+			if let Some((definition, depth)) = &mut defining {
+				let word = line.split('@').next().unwrap_or("").split_whitespace().next().unwrap_or("").to_lowercase();
+				match word.as_str() {
+					".endm" if *depth == 0 => {
+						self.macros.push(definition.clone());
+						defining = None;
+					}
+					".endm" => {
+						*depth -= 1;
+						definition.body.push((line.to_string(), origin));
+					}
+					".macro" => {
+						*depth += 1;
+						definition.body.push((line.to_string(), origin));
+					}
+					_ => definition.body.push((line.to_string(), origin)),
+				}
+				self.lines.push(bare);
+				continue;
+			}
+
+			// Lines in a block whose condition is false are skipped, only keeping count of nested blocks
+			let skipping = conditions.iter().any(|c| !c.active);
+			let word = line.split('@').next().unwrap_or("").split_whitespace().next().unwrap_or("").to_lowercase();
+			if skipping && !CONDITIONALS.contains(&word.as_str()) {
+				self.lines.push(bare);
+				continue;
+			}
+
+			let new_line = match self.parse_line(line, index, def) {
+				Ok(mut new_line) => {
+					new_line.origin = origin.clone();
+					new_line
+				}
+				Err(diagnostic) => {
+					diagnostics.push(diagnostic);
+					bare
+				}
+			};
 
-			let new_line =  self.lines.last().unwrap();
-			
 			let command = new_line.sections.iter().find(|i| i.0 == SectionType::Command);
 			let label = new_line.sections.iter().find(|i| i.0 == SectionType::Label);
-			let comp_mark = new_line.sections.iter().find(|i| i.0 == SectionType::ComplierMark);
+			let mark = new_line.sections.iter().find(|i| i.0 == SectionType::ComplierMark);
 			let literal = new_line.sections.iter().find(|i| i.0 == SectionType::Literal);
 
-			match command {
+			if let Some(l) = label {
+				self.labels.push(ParsedLabel { name: l.1.clone(), address: 0, section: section.clone(), line: index });
+			}
+
+			let mnemonic = command.and_then(|c| c.1.split_whitespace().next()).unwrap_or("");
+			if let Some(invoked) = self.macros.iter().rev().find(|m| m.name.eq_ignore_ascii_case(mnemonic)) {
+				let c = command.unwrap_or(&new_line.sections[0]);
+				let text = c.1.trim_end();
+				let arguments = text[mnemonic.len()..].trim_start();
+				let start = c.2 + text.len() - arguments.len();
+				let error = |span: Range<usize>, code: ErrorCode, message: String| Diagnostic::new(&self.file_name, index, span, message).with_code(code);
+				if origin.expansion.len() >= macros::MAX_DEPTH {
+					diagnostics.push(error(c.2..c.2 + mnemonic.len(), ErrorCode::MacroRecursion, format!("macros are expanded more than {} deep", macros::MAX_DEPTH))
+						.with_hint(format!("macro `{}` is likely invoking itself without end", invoked.name)));
+				}
+				else {
+					match invoked.arguments(arguments) {
+						Ok(arguments) => {
+							let mut chain = origin.expansion.clone();
+							chain.push((invoked.name.clone(), origin.file_name.clone(), origin.line));
+							pending.extend(invoked.expand(&arguments, expansions).into_iter().rev()
+								.map(|(text, body)| (text, LineOrigin { expansion: chain.clone(), ..body })));
+							expansions += 1;
+						}
+						Err((span, message)) => diagnostics.push(error(start + span.start..start + span.end, ErrorCode::Macro, message)
+							.with_hint(format!("`{}` is defined on {}", invoked.name, self.describe_line(invoked.line)))),
+					}
+				}
+			}
+			else if let Some(c) = command {
+				let parsed = match self.parse_alias(&c.1, index, c.2, def) {
+					Ok(Some(commands)) => Ok(commands),
+					Ok(None) => self.parse_command(&c.1, index, c.2, def).map(|command| vec![command]),
+					Err(diagnostic) => Err(diagnostic),
+				};
+				match parsed {
+					Ok(commands) => {
+						for mut new_command in commands {
+							new_command.section = section.clone();
+							if new_command.operands.iter().any(|o| o.0 == SegType::Literal) {
+								waiting.push(self.commands.len());
+							}
+							self.commands.push(new_command);
+						}
+					}
+					Err(diagnostic) => diagnostics.push(diagnostic),
+				}
+			}
+
+			match mark.map(|m| m.1.to_lowercase()).as_deref() {
+				Some(".macro") => {
+					let (text, start) = literal.map_or(("", mark.map_or(0, |m| m.2 + m.1.len())), |l| (l.1.as_str(), l.2));
+					match ParsedMacro::from_header(text, index) {
+						Ok(definition) => defining = Some((definition, 0)),
+						Err((span, message)) => {
+							diagnostics.push(Diagnostic::new(&self.file_name, index, start + span.start..start + span.end, message).with_code(ErrorCode::Macro));
+							// Still skip the body so its lines are not taken as code
+							defining = Some((ParsedMacro { line: index, ..Default::default() }, 0));
+						}
+					}
+				}
+				Some(".endm") => {
+					let m = mark.unwrap_or(&new_line.sections[0]);
+					diagnostics.push(Diagnostic::new(&self.file_name, index, m.2..m.2 + m.1.len(), ".endm without a .macro".to_string()).with_code(ErrorCode::Macro));
+				}
+				Some(name @ (".if" | ".ifdef" | ".ifndef" | ".elseif" | ".else" | ".endif")) => {
+					let m = mark.unwrap_or(&new_line.sections[0]);
+					let operand = literal.map_or(("", m.2 + m.1.len()), |l| (l.1.trim_end(), l.2));
+					let error = |message: String| Diagnostic::new(&self.file_name, index, m.2..m.2 + m.1.len(), message).with_code(ErrorCode::Conditional);
+					let mut test = || self.condition(name, operand, index).unwrap_or_else(|diagnostic| {
+						diagnostics.push(diagnostic);
+						false
+					});
+					match (name, conditions.last_mut()) {
+						(".if" | ".ifdef" | ".ifndef", _) => {
+							// Inside a skipped block the condition is not looked at, and no branch of it is taken
+							let active = !skipping && test();
+							conditions.push(Condition { active, taken: active || skipping, else_line: None, line: index });
+						}
+						(_, None) => diagnostics.push(error(format!("{} without an .if", name))),
+						(_, Some(condition)) if condition.else_line.is_some() && name != ".endif" => {
+							diagnostics.push(error(format!("{} after the .else of this block", name))
+								.with_hint(format!("the .else is on {}", self.describe_line(condition.else_line.unwrap_or(0)))));
+						}
+						(".elseif", Some(condition)) => {
+							condition.active = !condition.taken && test();
+							condition.taken |= condition.active;
+						}
+						(".else", Some(condition)) => {
+							condition.active = !condition.taken;
+							condition.taken = true;
+							condition.else_line = Some(index);
+						}
+						_ => {
+							conditions.pop();
+						}
+					}
+				}
+				Some(".ltorg" | ".pool") => self.dump_pool(&mut waiting, &section, index),
+				Some(name @ (".error" | ".warning")) => {
+					let m = mark.unwrap_or(&new_line.sections[0]);
+					let span = literal.map_or(m.2..m.2 + m.1.len(), |l| l.2..l.2 + l.1.trim_end().len());
+					let message = match literal.map(|l| l.1.trim_end()) {
+						Some(text) => match text.strip_prefix('"').and_then(|t| t.strip_suffix('"')).map(unescape) {
+							Some(Ok(bytes)) => String::from_utf8_lossy(&bytes).to_string(),
+							_ => text.to_string(),
+						},
+						None => format!("{} directive invoked in source", name.trim_start_matches('.')),
+					};
+					let diagnostic = Diagnostic::new(&self.file_name, index, span, message);
+					match name {
+						".error" => diagnostics.push(diagnostic.with_code(ErrorCode::User)),
+						_ => self.warnings.push(diagnostic.as_warning()),
+					}
+				}
+				Some(".include") => {
+					let m = mark.unwrap_or(&new_line.sections[0]);
+					let operand = literal.map_or(("", m.2..m.2 + m.1.len()), |l| (l.1.trim_end(), l.2..l.2 + l.1.trim_end().len()));
+					match self.read_include(operand.0, &origin) {
+						Ok((file_name, contents)) => {
+							let mut includes = origin.includes.clone();
+							includes.push((origin.file_name.clone(), origin.line));
+							pending.extend(file_lines(&contents, LineOrigin { file_name, includes, ..Default::default() }));
+						}
+						Err((message, hints)) => {
+							let mut diagnostic = Diagnostic::new(&self.file_name, index, operand.1, message).with_code(ErrorCode::Include);
+							for hint in hints {
+								diagnostic = diagnostic.with_hint(hint);
+							}
+							diagnostics.push(diagnostic);
+						}
+					}
+				}
+				Some(_) => {
+					let m = mark.unwrap_or(&new_line.sections[0]);
+					let directive = Directive {
+						name: &m.1,
+						column: m.2,
+						operands: literal.map(|l| (l.1.as_str(), l.2)),
+						label: label.map_or("", |l| l.1.as_str()),
+						line: index,
+					};
+					if let Err(diagnostic) = self.parse_directive(&directive, &mut section) {
+						diagnostics.push(diagnostic);
+					}
+				}
 				None => {}
-				Some(c) => {
-					let new_command = self.parse_command(c.1.clone(), &def);
-					match new_command {
-						None => {}
-						Some(s) => {self.commands.push(s);}
+			}
+
+			self.lines.push(new_line);
+		}
+
+		// Literals with no .ltorg after them go at the end of their section
+		while let Some(first) = waiting.first() {
+			let section = self.commands[*first].section.clone();
+			self.dump_pool(&mut waiting, &section, index - 1);
+		}
+
+		if let Some((definition, _)) = defining {
+			let text = self.lines.iter().find(|l| l.index == definition.line).map_or("", |l| l.text.as_str());
+			diagnostics.push(Diagnostic::new(&self.file_name, definition.line, 0..text.trim_end().len(), format!("macro `{}` is never closed", definition.name))
+				.with_code(ErrorCode::Macro)
+				.with_hint("end the macro body with .endm".to_string()));
+		}
+		for condition in conditions.iter().rev() {
+			let text = self.lines.iter().find(|l| l.index == condition.line).map_or("", |l| l.text.as_str());
+			let start = text.len() - text.trim_start().len();
+			diagnostics.push(Diagnostic::new(&self.file_name, condition.line, start..text.trim_end().len().max(start), "this .if is never closed".to_string())
+				.with_code(ErrorCode::Conditional)
+				.with_hint("end the block with .endif".to_string()));
+		}
+		let diagnostics: Vec<Diagnostic> = diagnostics.into_iter().map(|d| self.locate(d)).collect();
+		self.warnings = std::mem::take(&mut self.warnings).into_iter().map(|d| self.locate(d)).collect();
+
+		if diagnostics.is_empty() { Ok(()) } else { Err(diagnostics.into()) }
+	}
+
+	/// Place the literals waiting in a section in a pool on this line, word aligned.
+	/// Literals with the same value share an entry
+	fn dump_pool(&mut self, waiting: &mut Vec<usize>, section: &str, line: i32) {
+		let (here, rest): (Vec<usize>, Vec<usize>) = waiting.iter().partition(|i| self.commands[**i].section == section);
+		*waiting = rest;
+		if here.is_empty() {
+			return;
+		}
+		self.vars.push(ParsedVar { section: section.to_string(), kind: VarKind::Align, value: "4".to_string(), line, ..Default::default() });
+		// Value of each entry when it is known while parsing, an entry using a label is only shared with the same text
+		let mut entries: Vec<(usize, Option<i64>)> = Vec::new();
+		for i in here {
+			let command = &self.commands[i];
+			let Some(o) = command.operands.iter().position(|o| o.0 == SegType::Literal) else { continue };
+			let text = command.operands[o].1.trim().to_string();
+			let value = expression::evaluate(&text, &|name| self.constant(name, command.line).and_then(|c| c.value)).ok();
+			let shared = entries.iter().find(|(v, known)| self.vars[*v].value == text || (value.is_some() && *known == value));
+			let entry = match shared {
+				Some((v, _)) => *v,
+				None => {
+					self.vars.push(ParsedVar {
+						section: section.to_string(),
+						size: 4,
+						value: text,
+						line,
+						span: command.operand_spans.get(o).cloned().unwrap_or(command.span.clone()),
+						loaded_by: Some(command.line),
+						..Default::default()
+					});
+					entries.push((self.vars.len() - 1, value));
+					self.vars.len() - 1
+				}
+			};
+			self.commands[i].literal = Some(entry);
+		}
+	}
+
+	/// Whether the condition of an .if, .ifdef, .ifndef or .elseif holds.
+	/// An expression can only use constants defined above it and defined symbols, labels have no address yet
+	fn condition(&self, name: &str, operand: (&str, usize), line: i32) -> Result<bool, Diagnostic> {
+		let (text, start) = operand;
+		let error = |span: Range<usize>, code: ErrorCode, message: String| Diagnostic::new(&self.file_name, line, span, message).with_code(code);
+		if text.is_empty() {
+			return Err(error(start..start, ErrorCode::Conditional, format!("{} needs a condition", name)));
+		}
+		if name == ".ifdef" || name == ".ifndef" {
+			if !text.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_' || c == '.') || !text.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.') {
+				return Err(error(start..start + text.len(), ErrorCode::BadOperand, format!("\"{}\" is not a symbol name", text)));
+			}
+			let defined = self.constants.iter().any(|c| c.name == text && c.line <= line) || self.labels.iter().any(|l| l.name == text);
+			return Ok(defined == (name == ".ifdef"));
+		}
+		let constant = |symbol: &str| self.constant(symbol, line).filter(|c| c.line <= line).and_then(|c| c.value);
+		match expression::evaluate(text, &constant) {
+			Ok(value) => Ok(value != 0),
+			Err(e) => {
+				let diagnostic = error(start + e.span.start..start + e.span.end, e.code, e.message);
+				match e.code {
+					ErrorCode::UndefinedLabel => Err(diagnostic.with_hint("a condition can only use constants defined above it and symbols given with -D".to_string())),
+					_ => Err(diagnostic),
+				}
+			}
+		}
+	}
+
+	/// Find and read the file a .include names, returns its path and contents.
+	/// A relative path is looked for next to the file with the .include, then in each include path
+	fn read_include(&self, operand: &str, origin: &LineOrigin) -> Result<(String, String), (String, Vec<String>)> {
+		let name = operand.strip_prefix('"').and_then(|o| o.strip_suffix('"'))
+			.ok_or((".include needs a quoted file name".to_string(), Vec::new()))?;
+		let here = Path::new(&origin.file_name).parent().unwrap_or(Path::new(""));
+		let candidates: Vec<PathBuf> = std::iter::once(here.join(name))
+			.chain(self.include_paths.iter().map(|p| Path::new(p).join(name)))
+			.collect();
+		let Some(path) = candidates.iter().find(|p| p.is_file()) else {
+			let searched = candidates.iter().map(|p| p.display().to_string()).collect::<Vec<String>>().join(", ");
+			return Err((format!("can not find \"{}\" to include", name), vec![format!("looked for {}", searched), "add a folder to search with -I".to_string()]));
+		};
+
+		// A file including itself, directly or through others, would never end
+		let same = |other: &str| fs::canonicalize(other).ok().zip(fs::canonicalize(path).ok()).is_some_and(|(a, b)| a == b);
+		let chain: Vec<&str> = origin.includes.iter().map(|i| i.0.as_str()).chain(std::iter::once(origin.file_name.as_str())).collect();
+		if let Some(first) = chain.iter().position(|f| same(f)) {
+			let cycle: Vec<&str> = chain[first..].iter().copied().chain(std::iter::once(chain[first])).collect();
+			return Err((format!("\"{}\" includes itself", name), vec![format!("the include cycle is {}", cycle.join(" -> "))]));
+		}
+
+		let file_name = path.display().to_string();
+		let contents = fs::read_to_string(path).map_err(|e| (format!("can not read \"{}\": {}", file_name, e), Vec::new()))?;
+		Ok((file_name, contents))
+	}
+
+	/// Act on an assembler directive, switching section, recording globals or adding data to vars
+	fn parse_directive(&mut self, directive: &Directive, section: &mut String) -> Result<(), Diagnostic> {
+		let name = directive.name.to_lowercase();
+		let name_span = directive.column..directive.column + directive.name.len();
+		let (text, start) = directive.operands.unwrap_or(("", name_span.end));
+		let operands: Vec<(&str, Range<usize>)> = split_operands(text).into_iter()
+			.map(|(operand, span)| (operand, start + span.start..start + span.end))
+			.collect();
+		let error = |span: Range<usize>, code: ErrorCode, message: String| Diagnostic::new(&self.file_name, directive.line, span, message).with_code(code);
+		let needs = |count: usize| if operands.len() < count {
+			Err(error(name_span.clone(), ErrorCode::BadOperand, format!("{} needs a value", name)))
+		}
+		else {
+			Ok(())
+		};
+		// Sizes have to be known while parsing, before any label has an address
+		let constant = |name: &str| self.constant(name, directive.line).and_then(|c| c.value);
+		let number = |(operand, span): &(&str, Range<usize>)| {
+			let text = operand.trim_start_matches('#');
+			let offset = span.end - text.len();
+			expression::evaluate(text, &constant)
+				.map_err(|e| error(offset + e.span.start..offset + e.span.end, e.code, e.message))
+				.and_then(|value| u32::try_from(value).map_err(|_| error(span.clone(), ErrorCode::BadOperand, format!("{} can not be negative", operand))))
+		};
+		let var = |kind: VarKind, size: i32, value: &str, span: Range<usize>| ParsedVar {
+			label: directive.label.to_string(),
+			section: section.clone(),
+			kind,
+			size,
+			value: value.to_string(),
+			line: directive.line,
+			span,
+			..Default::default()
+		};
+
+		let mut vars = Vec::new();
+		match name.as_str() {
+			".text" | ".data" | ".bss" => {
+				*section = name.clone();
+			}
+			".section" => {
+				needs(1)?;
+				*section = operands[0].0.to_string();
+			}
+			".global" | ".globl" | ".extern" => {
+				needs(1)?;
+				for (operand, span) in &operands {
+					if !operand.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+						return Err(error(span.clone(), ErrorCode::BadOperand, format!("\"{}\" is not a symbol name", operand)));
 					}
-					
+					if name == ".extern" {
+						self.externs.push(operand.to_string());
+					}
+					else {
+						self.globals.push(operand.to_string());
+					}
+				}
+			}
+			".equ" | ".set" => {
+				needs(2)?;
+				let (symbol, symbol_span) = operands[0].clone();
+				if !symbol.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_' || c == '.') || !symbol.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.') {
+					return Err(error(symbol_span, ErrorCode::BadOperand, format!("\"{}\" is not a symbol name", symbol)));
+				}
+				if let Some(first) = self.constants.iter().find(|c| c.name == symbol && !(c.redefinable && name == ".set")) {
+					return Err(error(symbol_span, ErrorCode::DuplicateLabel, format!("constant `{}` is defined more than once", symbol))
+						.with_hint(format!("it is first defined on {}", self.describe_line(first.line)))
+						.with_hint("use .set for a constant that changes value".to_string()));
+				}
+				// The expression is everything after the first comma, so it may hold commas in character literals
+				let (text, span) = (&text[operands[1].1.start - start..], operands[1].1.start..start + text.len());
+				let value = match expression::evaluate(text, &constant) {
+					Ok(value) => Some(value),
+					// It may use a label, try again once addresses are assigned
+					Err(e) if e.code == ErrorCode::UndefinedLabel => None,
+					Err(e) => return Err(error(span.start + e.span.start..span.start + e.span.end, e.code, e.message)),
+				};
+				self.constants.push(ParsedConstant {
+					name: symbol.to_string(),
+					expression: text.to_string(),
+					value,
+					redefinable: name == ".set",
+					line: directive.line,
+					span,
+				});
+			}
+			".word" | ".hword" | ".short" | ".byte" => {
+				needs(1)?;
+				let size = match name.as_str() { ".word" => 4, ".byte" => 1, _ => 2 };
+				for (operand, span) in &operands {
+					vars.push(var(VarKind::Value, size, operand, span.clone()));
+				}
+			}
+			".ascii" | ".asciz" | ".string" => {
+				needs(1)?;
+				for (operand, span) in &operands {
+					let text = operand.strip_prefix('"').and_then(|o| o.strip_suffix('"'))
+						.ok_or(error(span.clone(), ErrorCode::BadOperand, format!("{} needs a quoted string", name)))?;
+					let bytes = unescape(text).map_err(|e| error(span.clone(), ErrorCode::BadOperand, e))?;
+					let size = bytes.len() as i32 + if name == ".ascii" { 0 } else { 1 };
+					vars.push(var(VarKind::Text, size, operand, span.clone()));
+				}
+			}
+			".space" | ".skip" => {
+				needs(1)?;
+				let size = i32::try_from(number(&operands[0])?)
+					.map_err(|_| error(operands[0].1.clone(), ErrorCode::BadOperand, format!("{} is more than the {} bytes {} can reserve", operands[0].0, i32::MAX, name)))?;
+				let fill = operands.get(1).map_or("0", |o| o.0);
+				vars.push(var(VarKind::Fill, size, fill, operands[0].1.start..operands.last().map_or(0, |o| o.1.end)));
+			}
+			".align" | ".balign" => {
+				needs(1)?;
+				let value = number(&operands[0])?;
+				// .align counts in powers of two like GNU as does for ARM, .balign in bytes
+				let boundary = if name == ".align" { 1u32.checked_shl(value).unwrap_or(0) } else { value };
+				if boundary == 0 || !boundary.is_power_of_two() {
+					return Err(error(operands[0].1.clone(), ErrorCode::BadOperand, format!("{} is not a valid alignment", operands[0].0)));
+				}
+				if boundary > MAX_ALIGN {
+					return Err(error(operands[0].1.clone(), ErrorCode::BadOperand, format!("{} is more than the largest alignment of {} bytes", operands[0].0, MAX_ALIGN)));
+				}
+				vars.push(var(VarKind::Align, 0, &boundary.to_string(), operands[0].1.clone()));
+			}
+			_ => {
+				let mut diagnostic = error(name_span, ErrorCode::UnknownDirective, format!("unknown directive \"{}\"", directive.name));
+				if let Some(known) = closest(&name, DIRECTIVES.iter().copied()) {
+					diagnostic = diagnostic.with_hint(format!("did you mean `{}`?", known));
+				}
+				return Err(diagnostic);
+			}
+		}
+		self.vars.extend(vars);
+		Ok(())
+	}
+
+	/// Render an error like a compiler would, showing the source line of each diagnostic
+	pub fn render_error(&self, error: &KgemuError, color: bool) -> String {
+		error.render(|d| self.written_line(&d.file_name, d.line).map(|l| l.text.as_str()), color)
+	}
+
+	/// Render each warning like a compiler would
+	pub fn render_warnings(&self, color: bool) -> String {
+		self.warnings.iter()
+			.map(|d| d.render(self.written_line(&d.file_name, d.line).map(|l| l.text.as_str()), color))
+			.collect::<Vec<String>>()
+			.join("\n")
+	}
+}
+
+/// Lines of a file ready to be popped off the pending stack in order
+fn file_lines(contents: &str, origin: LineOrigin) -> Vec<(String, LineOrigin)> {
+	let mut lines: Vec<(String, LineOrigin)> = contents.lines().enumerate()
+		.map(|(i, text)| (text.to_string(), LineOrigin { line: i as i32, ..origin.clone() }))
+		.collect();
+	lines.reverse();
+	lines
+}
+
+/// Every directive parse_directive knows
+const DIRECTIVES: [&str; 31] = [
+	".text", ".data", ".bss", ".section", ".global", ".globl", ".extern", ".word", ".hword", ".short", ".byte",
+	".ascii", ".asciz", ".string", ".space", ".skip", ".align", ".balign", ".equ", ".set", ".include",
+	".if", ".ifdef", ".ifndef", ".elseif", ".else", ".endif", ".error", ".warning", ".ltorg", ".pool",
+];
+
+/// The largest boundary .align and .balign can ask for, 2^16 bytes
+const MAX_ALIGN: u32 = 1 << 16;
+
+/// Directives still looked at inside a block that is skipped
+const CONDITIONALS: [&str; 6] = [".if", ".ifdef", ".ifndef", ".elseif", ".else", ".endif"];
+
+/// An .if block being parsed
+struct Condition {
+	/// Lines are being kept, the branch they are in holds
+	active: bool,
+	/// A branch of the block has held, so the rest are skipped
+	taken: bool,
+	/// Index of the .else line once it is seen
+	else_line: Option<i32>,
+	/// Index of the .if line
+	line: i32,
+}
+
+/// A directive line, as found by the line regexes
+struct Directive<'a> {
+	name: &'a str,
+	column: usize,
+	/// Operand text and the column it starts at
+	operands: Option<(&'a str, usize)>,
+	label: &'a str,
+	line: i32,
+}
+
+/// The label number and direction of a numeric local reference, `1b` is (1, false) and `2f` is (2, true)
+pub(crate) fn local_reference(name: &str) -> Option<(&str, bool)> {
+	let (number, forward) = match name.as_bytes().last() {
+		Some(b'b') => (&name[..name.len() - 1], false),
+		Some(b'f') => (&name[..name.len() - 1], true),
+		_ => return None,
+	};
+	(!number.is_empty() && number.bytes().all(|b| b.is_ascii_digit())).then_some((number, forward))
+}
+
+/// Split comma separated operands, keeping commas inside strings and character literals.
+/// Returns each trimmed operand with the range of text it covers
+pub(crate) fn split_operands(text: &str) -> Vec<(&str, Range<usize>)> {
+	let mut operands = Vec::new();
+	let mut start = 0;
+	let mut quote = None;
+	let mut escaped = false;
+	for (i, c) in text.char_indices().chain(std::iter::once((text.len(), ','))) {
+		match c {
+			_ if escaped => escaped = false,
+			'\\' if quote.is_some() => escaped = true,
+			'"' | '\'' if quote.is_none() => quote = Some(c),
+			_ if quote == Some(c) => quote = None,
+			',' if quote.is_none() => {
+				let part = &text[start..i];
+				let offset = start + part.len() - part.trim_start().len();
+				let part = part.trim();
+				if !part.is_empty() {
+					operands.push((part, offset..offset + part.len()));
 				}
+				start = i + 1;
+			}
+			_ => {}
+		}
+	}
+	operands
+}
+
+/// Turn the escapes in the text of a quoted string into the bytes they stand for
+pub fn unescape(text: &str) -> Result<Vec<u8>, String> {
+	let mut bytes = Vec::new();
+	let mut chars = text.chars();
+	while let Some(c) = chars.next() {
+		if c != '\\' {
+			let mut buffer = [0; 4];
+			bytes.extend(c.encode_utf8(&mut buffer).bytes());
+			continue;
+		}
+		match chars.next() {
+			Some('n') => bytes.push(b'\n'),
+			Some('t') => bytes.push(b'\t'),
+			Some('r') => bytes.push(b'\r'),
+			Some('0') => bytes.push(0),
+			Some('\\') => bytes.push(b'\\'),
+			Some('"') => bytes.push(b'"'),
+			Some('\'') => bytes.push(b'\''),
+			Some('x') => {
+				let hex: String = chars.clone().take(2).collect();
+				let value = u8::from_str_radix(&hex, 16).map_err(|_| format!("\\x{} is not a valid escape", hex))?;
+				chars.nth(hex.len() - 1);
+				bytes.push(value);
 			}
+			Some(other) => return Err(format!("\\{} is not a valid escape", other)),
+			None => return Err("string ends with a \\".to_string()),
+		}
+	}
+	Ok(bytes)
+}
+
+/// The candidate closest to word, if it is about one edit in three characters away
+pub(crate) fn closest<'a>(word: &str, candidates: impl Iterator<Item = &'a str>) -> Option<&'a str> {
+	let word = word.to_lowercase();
+	let most = (word.len() / 3).max(1);
+	candidates
+		.map(|c| (edit_distance(&word, &c.to_lowercase()), c))
+		.filter(|(d, _)| *d <= most)
+		.min_by_key(|(d, _)| *d)
+		.map(|(_, c)| c)
+}
+
+/// Number of single character inserts, deletes or swaps to turn a into b
+fn edit_distance(a: &str, b: &str) -> usize {
+	let b: Vec<char> = b.chars().collect();
+	let mut row: Vec<usize> = (0..=b.len()).collect();
+	for (i, ca) in a.chars().enumerate() {
+		let mut previous = row[0];
+		row[0] = i + 1;
+		for j in 0..b.len() {
+			let next = (previous + (ca != b[j]) as usize).min(row[j] + 1).min(row[j + 1] + 1);
+			previous = row[j + 1];
+			row[j + 1] = next;
+		}
+	}
+	row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::error::Severity;
+
+	#[test]
+	fn bad_commands_are_reported() {
+		let def: language::LanguageDefinition = Default::default();
+		let mut code = ParsedCode { file_name: "test.s".to_string(), ..Default::default() };
+		let error = code.parse_from_str("add r1, r2\n\tfoo r1, r2\nadd r1, r2, r3 junk\nmov r1, #1", &def).unwrap_err();
+
+		assert_eq!(error.diagnostics(), &[
+			Diagnostic::new("test.s", 0, 0..10, "invalid operands for ADD: \"add r1, r2\"".to_string())
+				.with_code(ErrorCode::InvalidOperands),
+			Diagnostic::new("test.s", 1, 1..4, "unknown mnemonic \"foo\"".to_string())
+				.with_code(ErrorCode::UnknownMnemonic),
+			Diagnostic::new("test.s", 2, 0..19, "invalid operands for ADD: \"add r1, r2, r3 junk\"".to_string())
+				.with_code(ErrorCode::InvalidOperands),
+		]);
+		assert_eq!(error.to_string().lines().nth(1), Some("test.s:2:2: E0002: unknown mnemonic \"foo\""));
+		// The good line is still parsed
+		assert_eq!(code.commands.len(), 1);
+		assert_eq!(code.commands[0].span, 0..10);
+	}
+
+	#[test]
+	fn hints() {
+		let def: language::LanguageDefinition = Default::default();
+		let mut code: ParsedCode = Default::default();
+		let error = code.parse_from_str("sbb r1, r2\nadd r1, r12, r3", &def).unwrap_err();
+		let diagnostics = error.diagnostics();
+		assert_eq!(diagnostics[0].hints, vec!["did you mean `sbc`?".to_string()]);
+		assert_eq!(diagnostics[1].span, 8..11);
+		assert_eq!(diagnostics[1].hints[1], "where a hi register is allowed r12 is written `h4`");
+	}
+
+	#[test]
+	fn broken_regex() {
+		let mut def: language::LanguageDefinition = Default::default();
+		def.commands[1].1[0].regex = "(add".to_string();
+		let mut code: ParsedCode = Default::default();
+		let error = code.parse_from_str("mov r1, #1\nadd r1, r2, r3", &def).unwrap_err();
+		// The regexes are compiled once before any line, so there is one diagnostic rather than one for each line
+		assert_eq!(error.diagnostics().len(), 1);
+		assert_eq!(error.diagnostics()[0].code, Some(ErrorCode::Definition));
+		assert!(error.diagnostics()[0].message.starts_with("regex for ADD does not compile"));
+		assert!(code.lines.is_empty());
+	}
+
+	#[test]
+	fn regexes_follow_the_definition() {
+		let def: language::LanguageDefinition = Default::default();
+		let mut renamed = def.clone();
+		let add = renamed.commands.iter().position(|c| c.0 == "ADD").unwrap();
+		for version in &mut renamed.commands[add].1 {
+			version.regex = version.regex.replacen("[aA][dD][dD]", "[pP][lL][uU][sS]", 1);
+		}
+		let mut code: ParsedCode = Default::default();
+		code.parse_from_str("add r1, r2, r3", &def).unwrap();
+		// The regexes kept from the first definition are not used for the second, where ADD is written plus
+		let error = code.parse_from_str("add r1, r2, r3", &renamed).unwrap_err();
+		assert_eq!(error.diagnostics()[0].code, Some(ErrorCode::InvalidOperands));
+		code.parse_from_str("plus r1, r2, r3", &renamed).unwrap();
+		assert!(code.regexes.as_ref().is_some_and(|r| r.matches(&renamed) && !r.matches(&def)));
+	}
+
+	#[test]
+	fn directives() {
+		let def: language::LanguageDefinition = Default::default();
+		let mut code: ParsedCode = Default::default();
+		code.parse_from_str(".global _start, main\n.data\ntable: .word 1, -2, table @ three\n\t.asciz \"a, \\\"b\\\"\\n\"\n\t.balign 8\n\t.space 3, 0xff", &def).unwrap();
+		assert_eq!(code.globals, vec!["_start".to_string(), "main".to_string()]);
+		let vars: Vec<(VarKind, i32, &str, &str)> = code.vars.iter().map(|v| (v.kind, v.size, v.value.as_str(), v.section.as_str())).collect();
+		assert_eq!(vars, vec![
+			(VarKind::Value, 4, "1", ".data"),
+			(VarKind::Value, 4, "-2", ".data"),
+			(VarKind::Value, 4, "table", ".data"),
+			(VarKind::Text, 8, "\"a, \\\"b\\\"\\n\"", ".data"),
+			(VarKind::Align, 0, "8", ".data"),
+			(VarKind::Fill, 3, "0xff", ".data"),
+		]);
+		assert_eq!((code.vars[2].label.as_str(), code.vars[2].span.clone()), ("table", 20..25));
+
+		let error = code.parse_from_str(".wrd 4\n.ascii hello\n.align 3, 0\n.balign 3\n.space", &def).unwrap_err();
+		let codes: Vec<Option<ErrorCode>> = error.diagnostics().iter().map(|d| d.code).collect();
+		assert_eq!(codes, vec![Some(ErrorCode::UnknownDirective), Some(ErrorCode::BadOperand), Some(ErrorCode::BadOperand), Some(ErrorCode::BadOperand)]);
+		assert_eq!(error.diagnostics()[0].hints, vec!["did you mean `.word`?".to_string()]);
+	}
+
+	#[test]
+	fn macros() {
+		let def: language::LanguageDefinition = Default::default();
+		let mut code: ParsedCode = Default::default();
+		code.parse_from_str(concat!(
+			".macro delay count=10, reg:req\n",
+			"\tmov \\reg, #\\count\n",
+			"wait\\@:\tsub \\reg, #1\n",
+			"\tbne wait\\@\n",
+			".endm\n",
+			".macro twice reg\n",
+			"\tdelay 2, \\reg\n",
+			"\tdelay reg=\\reg\n",
+			".endm\n",
+			"start:\tdelay 5, r0\n",
+			"\ttwice r1\n",
+		), &def).unwrap();
+		let commands: Vec<(&str, Vec<&str>, i32)> = code.commands.iter()
+			.map(|c| (c.op_code.as_str(), c.operands.iter().skip(1).map(|o| o.1.as_str()).collect(), code.source_line(c.line)))
+			.collect();
+		// \@ counts expansions from 0 like GNU as, the first one is wait0
+		assert_eq!(&commands[..3], &[("MOV", vec!["r0", "5"], 1), ("SUB", vec!["r0", "1"], 2), ("BNE", vec!["wait0"], 3)]);
+		assert_eq!(commands[6], ("MOV", vec!["r1", "10"], 1));
+		let labels: Vec<&str> = code.labels.iter().map(|l| l.name.as_str()).collect();
+		assert_eq!(labels, vec!["start", "wait0", "wait2", "wait3"]);
+
+		let mut code: ParsedCode = Default::default();
+		let error = code.parse_from_str(concat!(
+			".macro set a:req\n",
+			"\tmvo \\a, #1\n",
+			".endm\n",
+			"\tset r0\n",
+			"\tset\n",
+			".macro again\n",
+			"\tagain\n",
+			".endm\n",
+			"\tagain\n",
+			".endm\n",
+			".macro open\n",
+		), &def).unwrap_err();
+		let found: Vec<(i32, Range<usize>, Option<ErrorCode>)> = error.diagnostics().iter().map(|d| (d.line, d.span.clone(), d.code)).collect();
+		assert_eq!(found, vec![
+			(1, 1..4, Some(ErrorCode::UnknownMnemonic)),
+			(4, 4..4, Some(ErrorCode::Macro)),
+			(6, 1..6, Some(ErrorCode::MacroRecursion)),
+			(9, 0..5, Some(ErrorCode::Macro)),
+			(10, 0..11, Some(ErrorCode::Macro)),
+		]);
+		assert_eq!(error.diagnostics()[0].notes, vec!["the line expands to `mvo r0, #1`", "in expansion of macro `set` invoked on line 4"]);
+		assert_eq!(error.diagnostics()[2].notes, vec![
+			format!("in expansion of macro `again` invoked on line 7 ({} times)", macros::MAX_DEPTH - 1),
+			"in expansion of macro `again` invoked on line 9".to_string(),
+		]);
+	}
+
+	#[test]
+	fn includes() {
+		let def: language::LanguageDefinition = Default::default();
+		let dir = std::env::temp_dir().join("kgemu_includes");
+		let shared = dir.join("shared");
+		fs::create_dir_all(&shared).unwrap();
+		fs::write(dir.join("main.s"), ".include \"regs.s\"\nstart:\tmov r0, #COUNT\n").unwrap();
+		fs::write(shared.join("regs.s"), ".equ COUNT, 3\n.include \"more.s\"\n").unwrap();
+		fs::write(shared.join("more.s"), "\tadd r1, #1\n").unwrap();
+		fs::write(dir.join("loop.s"), ".include \"loop2.s\"\n").unwrap();
+		fs::write(dir.join("loop2.s"), "\n.include \"loop.s\"\n").unwrap();
+		fs::write(dir.join("bad.s"), "\tadd r1, #1\n.include \"broken.s\"\n.include \"gone.s\"\n").unwrap();
+		fs::write(dir.join("broken.s"), "\n\tmvo r0, #1\n").unwrap();
+		let main = dir.join("main.s").display().to_string();
+
+		let mut code: ParsedCode = Default::default();
+		code.include_paths.push(shared.display().to_string());
+		code.parse_from_file(&main, &def).unwrap();
+		let origins: Vec<(&str, i32, i32)> = code.commands.iter()
+			.map(|c| code.lines.iter().find(|l| l.index == c.line).unwrap())
+			.map(|l| (l.origin.file_name.rsplit('/').next().unwrap(), l.origin.line, l.origin.includes.len() as i32))
+			.collect();
+		assert_eq!(origins, vec![("more.s", 0, 2), ("main.s", 1, 0)]);
+		assert_eq!(code.constant("COUNT", 0).map(|c| c.value), Some(Some(3)));
+
+		// Without the include path regs.s is not found
+		let mut code: ParsedCode = Default::default();
+		let error = code.parse_from_file(&main, &def).unwrap_err();
+		assert_eq!(error.diagnostics()[0].code, Some(ErrorCode::Include));
+		assert_eq!(error.diagnostics()[0].span, 9..17);
+
+		let mut code: ParsedCode = Default::default();
+		let error = code.parse_from_file(&dir.join("loop.s").display().to_string(), &def).unwrap_err();
+		let cycle = &error.diagnostics()[0];
+		assert_eq!((cycle.file_name.rsplit('/').next(), cycle.line, cycle.code), (Some("loop2.s"), 1, Some(ErrorCode::Include)));
+		let (first, second) = (dir.join("loop.s").display().to_string(), dir.join("loop2.s").display().to_string());
+		assert_eq!(cycle.hints, vec![format!("the include cycle is {} -> {} -> {}", first, second, first)]);
+
+		let mut code: ParsedCode = Default::default();
+		let error = code.parse_from_file(&dir.join("bad.s").display().to_string(), &def).unwrap_err();
+		let found: Vec<(&str, i32, Option<ErrorCode>)> = error.diagnostics().iter()
+			.map(|d| (d.file_name.rsplit('/').next().unwrap(), d.line, d.code))
+			.collect();
+		assert_eq!(found, vec![("broken.s", 1, Some(ErrorCode::UnknownMnemonic)), ("bad.s", 2, Some(ErrorCode::Include))]);
+		assert_eq!(error.diagnostics()[0].notes, vec![format!("included from {}:2", dir.join("bad.s").display())]);
+		fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn conditionals() {
+		let def: language::LanguageDefinition = Default::default();
+		let mut code: ParsedCode = Default::default();
+		code.defines.push(("BOARD".to_string(), 2));
+		code.parse_from_str(concat!(
+			".equ FAST, 1\n",
+			".if BOARD == 1\n",
+			"\tmov r0, #1\n",
+			".elseif BOARD == 2 && FAST\n",
+			"\tmov r0, #2\n",
+			"\t.ifdef SLOW\n",
+			"\tmov r1, #1\n",
+			"\t.else\n",
+			"\tmov r1, #2\n",
+			"\t.endif\n",
+			".else\n",
+			"\tbogus line\n",
+			".if 1\n",
+			".endif\n",
+			".endif\n",
+			".ifndef LIMIT\n",
+			".equ LIMIT, BOARD * 8\n",
+			".endif\n",
+			".warning \"LIMIT is \\\"default\\\"\"\n",
+		), &def).unwrap();
+		let commands: Vec<(&str, Vec<&str>)> = code.commands.iter()
+			.map(|c| (c.op_code.as_str(), c.operands.iter().skip(1).map(|o| o.1.as_str()).collect()))
+			.collect();
+		assert_eq!(commands, vec![("MOV", vec!["r0", "2"]), ("MOV", vec!["r1", "2"])]);
+		assert_eq!(code.constant("LIMIT", 20).and_then(|c| c.value), Some(16));
+		let warnings: Vec<(i32, &str, Severity)> = code.warnings.iter().map(|d| (d.line, d.message.as_str(), d.severity)).collect();
+		assert_eq!(warnings, vec![(18, "LIMIT is \"default\"", Severity::Warning)]);
+
+		let mut code: ParsedCode = Default::default();
+		let error = code.parse_from_str(concat!(
+			".endif\n",
+			".if 1\n",
+			".else\n",
+			".else\n",
+			".endif\n",
+			".if missing\n",
+			".endif\n",
+			".error \"unsupported board\"\n",
+			".ifdef 4\n",
+			".endif\n",
+			"\t.if 0\n",
+		), &def).unwrap_err();
+		let found: Vec<(i32, Range<usize>, Option<ErrorCode>)> = error.diagnostics().iter().map(|d| (d.line, d.span.clone(), d.code)).collect();
+		assert_eq!(found, vec![
+			(0, 0..6, Some(ErrorCode::Conditional)),
+			(3, 0..5, Some(ErrorCode::Conditional)),
+			(5, 4..11, Some(ErrorCode::UndefinedLabel)),
+			(7, 7..26, Some(ErrorCode::User)),
+			(8, 7..8, Some(ErrorCode::BadOperand)),
+			(10, 1..6, Some(ErrorCode::Conditional)),
+		]);
+		assert_eq!(error.diagnostics()[1].hints, vec!["the .else is on line 3"]);
+		assert_eq!(error.diagnostics()[3].message, "unsupported board");
+	}
+
+	#[test]
+	fn missing_file() {
+		let def: language::LanguageDefinition = Default::default();
+		let mut code: ParsedCode = Default::default();
+		match code.parse_from_file("does/not/exist.thumb", &def) {
+			Err(KgemuError::Io { file_name, .. }) => assert_eq!(file_name, "does/not/exist.thumb"),
+			_ => panic!("expected an io error"),
 		}
 	}
 }
@@ -0,0 +1,113 @@
+//! Every name the code defines, with its value, for dumps and for other tools
+
+use std::fmt;
+
+use super::parse_code::ParsedCode;
+use crate::prelude::{DeviceDefinition, Region};
+
+/// What defined a symbol
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolKind {
+	/// A label only used in its own file
+	Label,
+	/// A value named with .equ or .set
+	Constant,
+	/// A label named by .global
+	Global,
+	/// A .L label or a numeric label like `1:`, never written out for other files
+	Local,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+	pub name: String,
+	/// Address of a label or value of a constant
+	pub value: i64,
+	pub kind: SymbolKind,
+	/// Section a label is in, empty for constants
+	pub section: String,
+	/// Index of the line that defines it, starting at 0
+	pub line: i32,
+	/// Named by .global, a constant can be global too
+	pub global: bool,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub struct SymbolTable {
+	/// Symbols sorted by value and then by name like nm sorts them, so symbols of included files are not mixed in by line
+	pub symbols: Vec<Symbol>,
+}
+
+impl SymbolTable {
+	/// Collect the labels and constants of parsed code once addresses are assigned.
+	/// A constant given several values with .set has its last one
+	pub fn new(code: &ParsedCode) -> Self {
+		let global = |name: &str| code.globals.iter().any(|g| g == name);
+		let mut symbols: Vec<Symbol> = code.labels.iter().map(|label| Symbol {
+			name: label.name.clone(),
+			value: label.address as i64,
+			kind: if is_local(&label.name) { SymbolKind::Local } else if global(&label.name) { SymbolKind::Global } else { SymbolKind::Label },
+			section: label.section.clone(),
+			line: code.source_line(label.line),
+			global: global(&label.name),
+		}).collect();
+		for constant in &code.constants {
+			let symbol = Symbol {
+				name: constant.name.clone(),
+				value: constant.value.unwrap_or(0),
+				kind: SymbolKind::Constant,
+				section: String::new(),
+				line: code.source_line(constant.line),
+				global: global(&constant.name),
+			};
+			match symbols.iter_mut().find(|s| s.kind == SymbolKind::Constant && s.name == constant.name) {
+				Some(existing) => *existing = symbol,
+				None => symbols.push(symbol),
+			}
+		}
+		symbols.sort_by(|a, b| (a.value as u32).cmp(&(b.value as u32)).then(a.name.cmp(&b.name)));
+		SymbolTable { symbols }
+	}
+
+	pub fn get(&self, name: &str) -> Option<&Symbol> {
+		self.symbols.iter().find(|s| s.name == name)
+	}
+
+	/// One line per symbol like `nm -n` prints, "00000004 T main", sorted by value.
+	/// The letter is t for code, r for read only data, d for data, b for zeroed data and a for a constant,
+	/// in upper case when the symbol is global
+	pub fn to_nm(&self, device: &DeviceDefinition) -> String {
+		self.symbols.iter().filter(|s| s.kind != SymbolKind::Local).map(|symbol| {
+			let placement = device.placement(&symbol.section);
+			let letter = match symbol.section.as_str() {
+				"" => 'a',
+				".rodata" => 'r',
+				_ if placement.uninitialized => 'b',
+				_ if placement.region == Region::Ram => 'd',
+				_ => 't',
+			};
+			let letter = if symbol.global { letter.to_ascii_uppercase() } else { letter };
+			format!("{:08x} {} {}\n", symbol.value as u32, letter, symbol.name)
+		}).collect()
+	}
+}
+
+/// Labels only used in their own file, `.L` names and numeric labels
+pub fn is_local(name: &str) -> bool {
+	name.starts_with(".L") || (!name.is_empty() && name.bytes().all(|b| b.is_ascii_digit()))
+}
+
+/// A table with a row per symbol, line numbers counted from 1
+impl fmt::Display for SymbolTable {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let width = self.symbols.iter().map(|s| s.name.len()).max().unwrap_or(0).max(4);
+		writeln!(f, "{:<width$}  {:<10}  {:<8}  {:<10}  line", "name", "value", "kind", "section")?;
+		for symbol in &self.symbols {
+			let kind = format!("{:?}", symbol.kind).to_lowercase();
+			// A symbol defined on the command line has no line
+			let line = if symbol.line < 0 { "-".to_string() } else { (symbol.line + 1).to_string() };
+			writeln!(f, "{:<width$}  0x{:08x}  {:<8}  {:<10}  {}", symbol.name, symbol.value as u32, kind, symbol.section, line)?;
+		}
+		Ok(())
+	}
+}
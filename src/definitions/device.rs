@@ -1,3 +1,76 @@
+// Hardware the code runs on, where its memory is and which sections go where
+
+use serde::{Deserialize, Serialize};
+
 pub mod prelude {
-	
-}
\ No newline at end of file
+	pub use super::{DeviceDefinition, Region, SectionPlacement};
+}
+
+/// A block of memory a section can be placed in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Region {
+	Rom,
+	Ram,
+}
+
+/// Where the assembler puts one output section
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SectionPlacement {
+	pub name: String,
+	pub region: Region,
+	/// The section starts on a multiple of this many bytes
+	pub align: u32,
+	/// Only reserves space, like .bss, nothing is stored in rom for it and it starts zeroed
+	#[serde(default)]
+	pub uninitialized: bool,
+}
+
+/// Memory map of a device.
+/// Rom sections are placed first, then ram sections whose initial data is stored in rom after them
+/// and copied to ram at reset
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeviceDefinition {
+	pub name: String,
+	pub rom_start: u32,
+	pub rom_size: u32,
+	pub ram_start: u32,
+	pub ram_size: u32,
+	/// Placement of each known section in order, any other section goes in rom after them
+	pub sections: Vec<SectionPlacement>,
+}
+
+impl DeviceDefinition {
+	/// Placement of a section, sections that are not listed go in rom aligned to 4
+	pub fn placement(&self, name: &str) -> SectionPlacement {
+		self.sections.iter().find(|s| s.name == name).cloned()
+			.unwrap_or(SectionPlacement { name: name.to_string(), region: Region::Rom, align: 4, uninitialized: false })
+	}
+
+	/// First address and size of a region
+	pub fn region(&self, region: Region) -> (u32, u32) {
+		match region {
+			Region::Rom => (self.rom_start, self.rom_size),
+			Region::Ram => (self.ram_start, self.ram_size),
+		}
+	}
+}
+
+/// 64KiB of rom at 0 and 64KiB of ram at 0x2000_0000, like a small Cortex-M part
+impl Default for DeviceDefinition {
+	fn default() -> Self {
+		let section = |name: &str, region: Region, uninitialized: bool| SectionPlacement { name: name.to_string(), region, align: 4, uninitialized };
+		DeviceDefinition {
+			name: "Generic Thumb".to_string(),
+			rom_start: 0,
+			rom_size: 0x10000,
+			ram_start: 0x2000_0000,
+			ram_size: 0x10000,
+			sections: vec![
+				section(".text", Region::Rom, false),
+				section(".rodata", Region::Rom, false),
+				section(".data", Region::Ram, false),
+				section(".bss", Region::Ram, true),
+			],
+		}
+	}
+}
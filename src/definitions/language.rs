@@ -1,7 +1,10 @@
+use serde::{Deserialize, Serialize};
+
 use super::processor::{ProcessorDefinition, SegType};
 
 
 pub mod prelude {
+	pub use super::AliasDefinition;
 	pub use super::CommandDefinition;
 	pub use super::LanguageDefinition;
 }
@@ -34,15 +37,36 @@ struct CaptureGroup {
 
  */
 
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct CommandDefinition {
 	pub regex: String,
 	pub segments: Vec<(SegType, String)>,
 	pub format_index: i32,
+	/// Immediate and offset operands are stored divided by 2^scale (word and halfword aligned values)
+	#[serde(default)]
+	pub scale: i32,
+	/// Spread the offset over this many instructions of the format, high bits first,
+	/// each with its first Flag set to its place in the run (the BL high/low pair)
+	#[serde(default)]
+	pub split: i32,
+}
+
+/// A pseudo instruction, written in the code like a command and replaced by real commands before they are parsed
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AliasDefinition {
+	/// Matched against the whole command, like the regex of a command
+	pub regex: String,
+	/// Commands written in its place, `${name}` is replaced by the text of the capture group name
+	pub expansion: Vec<String>,
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct LanguageDefinition { 
 	pub processor_def: ProcessorDefinition,
 	pub regex_list: Vec<String>,
 	//pub capture_groups:Vec<VecTree<CaptureGroup>>,
 	pub commands: Vec<(String,Vec<CommandDefinition>)>,
+	/// Tried before the commands, so an alias can also stand in for operands a command does not take
+	#[serde(default)]
+	pub aliases: Vec<(String, AliasDefinition)>,
 }
\ No newline at end of file
@@ -0,0 +1,126 @@
+// Reading and writing definitions as json files
+
+use std::{fmt, fs};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use super::{device::DeviceDefinition, language::LanguageDefinition, processor::ProcessorDefinition};
+
+pub mod prelude {
+	pub use super::DefinitionError;
+}
+
+/// Why a definition file could not be loaded or saved
+#[derive(Debug)]
+pub enum DefinitionError {
+	/// The file could not be read or written
+	Io { file_name: String, error: std::io::Error },
+	/// The file is not valid json or does not match the definition structs
+	Json { file_name: String, error: serde_json::Error },
+	/// The file loaded but the definition failed validation
+	Invalid { file_name: String, problems: Vec<String> },
+}
+
+impl fmt::Display for DefinitionError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			DefinitionError::Io { file_name, error } => write!(f, "{}: {}", file_name, error),
+			DefinitionError::Json { file_name, error } => write!(f, "{}:{}:{}: {}", file_name, error.line(), error.column(), error),
+			DefinitionError::Invalid { file_name, problems } => write!(f, "{}: {}", file_name, problems.join("\n")),
+		}
+	}
+}
+
+impl std::error::Error for DefinitionError {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self {
+			DefinitionError::Io { error, .. } => Some(error),
+			DefinitionError::Json { error, .. } => Some(error),
+			DefinitionError::Invalid { .. } => None,
+		}
+	}
+}
+
+fn from_file<T: DeserializeOwned>(file_name: &str) -> Result<T, DefinitionError> {
+	let contents = fs::read_to_string(file_name)
+		.map_err(|error| DefinitionError::Io { file_name: file_name.to_string(), error })?;
+	serde_json::from_str(&contents)
+		.map_err(|error| DefinitionError::Json { file_name: file_name.to_string(), error })
+}
+
+fn to_file<T: Serialize>(value: &T, file_name: &str) -> Result<(), DefinitionError> {
+	let contents = serde_json::to_string_pretty(value)
+		.map_err(|error| DefinitionError::Json { file_name: file_name.to_string(), error })?;
+	fs::write(file_name, contents)
+		.map_err(|error| DefinitionError::Io { file_name: file_name.to_string(), error })
+}
+
+impl LanguageDefinition {
+	/// Load a language, and the processor it is written for, from a json file
+	pub fn from_file(file_name: &str) -> Result<Self, DefinitionError> {
+		from_file(file_name)
+	}
+
+	pub fn to_file(&self, file_name: &str) -> Result<(), DefinitionError> {
+		to_file(self, file_name)
+	}
+}
+
+impl ProcessorDefinition {
+	/// Load a processor from a json file
+	pub fn from_file(file_name: &str) -> Result<Self, DefinitionError> {
+		from_file(file_name)
+	}
+
+	pub fn to_file(&self, file_name: &str) -> Result<(), DefinitionError> {
+		to_file(self, file_name)
+	}
+}
+
+impl DeviceDefinition {
+	/// Load a memory map from a json file
+	pub fn from_file(file_name: &str) -> Result<Self, DefinitionError> {
+		from_file(file_name)
+	}
+
+	pub fn to_file(&self, file_name: &str) -> Result<(), DefinitionError> {
+		to_file(self, file_name)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const THUMB: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/definitions/thumb.json");
+
+	#[test]
+	fn shipped_thumb_matches_default() {
+		let loaded = LanguageDefinition::from_file(THUMB).unwrap();
+		let default: LanguageDefinition = Default::default();
+		assert_eq!(loaded, default);
+	}
+
+	#[test]
+	fn device_round_trip() {
+		let path = std::env::temp_dir().join("kgemu_device.json");
+		let device: DeviceDefinition = Default::default();
+		device.to_file(path.to_str().unwrap()).unwrap();
+		assert_eq!(DeviceDefinition::from_file(path.to_str().unwrap()).unwrap(), device);
+	}
+
+	#[test]
+	fn bad_files() {
+		match LanguageDefinition::from_file("does/not/exist.json") {
+			Err(DefinitionError::Io { file_name, .. }) => assert_eq!(file_name, "does/not/exist.json"),
+			_ => panic!("expected an io error"),
+		}
+
+		let path = std::env::temp_dir().join("kgemu_bad_definition.json");
+		fs::write(&path, "{\"name\": \"broken\", \"num_register\": 16,\n\"formats\": 3}").unwrap();
+		match ProcessorDefinition::from_file(path.to_str().unwrap()) {
+			Err(DefinitionError::Json { error, .. }) => assert_eq!(error.line(), 2),
+			_ => panic!("expected a json error"),
+		}
+	}
+}
@@ -4,6 +4,8 @@
 pub mod processor;
 pub mod language;
 pub mod device;
+pub mod load;
+pub mod validate;
 
 pub mod thumb_default;	
 
@@ -13,4 +15,5 @@ pub mod prelude {
 	pub use super::language::prelude::*;
 	pub use super::processor::prelude::*;
 	pub use super::device::prelude::*;
+	pub use super::load::prelude::*;
 }
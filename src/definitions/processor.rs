@@ -1,3 +1,5 @@
+use serde::{Deserialize, Serialize};
+
 pub mod prelude {
 	pub use super::SegType;
 	pub use super::OperationSeg;
@@ -5,7 +7,7 @@ pub mod prelude {
 	pub use super::ProcessorDefinition;
 }
 
-#[derive(Default, Debug, Clone, Copy)]
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum SegType {
 	#[default]
 	Main,
@@ -15,23 +17,235 @@ pub enum SegType {
 	Destination,
 	Offset,
 	Immediate,
-	Condition
+	Condition,
+	RegisterList,
+	/// A value kept in a literal pool, encoded in the Immediate segment as the distance from PC to its pool entry
+	Literal,
 }
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct OperationSeg {
+	#[serde(default)]
 	pub name: Option<String>,
 	pub mask: Vec<u8>,
 	pub seg_type: SegType,
+	#[serde(default)]
 	pub values: Option<Vec<Vec<u8>>>
 }
+
+impl OperationSeg {
+	/// Number of bits the mask covers
+	pub fn width(&self) -> u32 {
+		self.mask.iter().map(|m| m.count_ones()).sum()
+	}
+
+	/// Scatter the bits of value into the set bits of the mask, lowest bit first.
+	/// The mask does not need to be contiguous and may span several bytes.
+	pub fn encode(&self, value: u32, out: &mut [u8]) -> Result<(), String> {
+		let width = self.width();
+		if width > 32 {
+			return Err(format!("the {} field is {} bits wide, more than the 32 of a value", self.name.clone().unwrap_or(format!("{:?}", self.seg_type)), width));
+		}
+		if width < 32 && value >> width != 0 {
+			return Err(format!("{} does not fit in the {} bit {} field", value, width, self.name.clone().unwrap_or(format!("{:?}", self.seg_type))));
+		}
+		if out.len() < self.mask.len() {
+			return Err(format!("{} byte instruction is too short for a {} byte mask", out.len(), self.mask.len()));
+		}
+
+		let mut bit = 0;
+		for i in (0..self.mask.len()).rev() {
+			for b in 0..8 {
+				if self.mask[i] & (1 << b) != 0 {
+					if value & (1 << bit) != 0 {
+						out[i] |= 1 << b;
+					}
+					bit += 1;
+				}
+			}
+		}
+		Ok(())
+	}
+
+	/// Gather the bits under the mask back into a value, the reverse of encode.
+	/// Bits past the 32 of a value are dropped
+	pub fn decode(&self, bytes: &[u8]) -> u32 {
+		let mut value = 0;
+		let mut bit = 0;
+		for i in (0..self.mask.len().min(bytes.len())).rev() {
+			for b in 0..8 {
+				if self.mask[i] & (1 << b) != 0 {
+					if bytes[i] & (1 << b) != 0 {
+						value |= 1u32.checked_shl(bit).unwrap_or(0);
+					}
+					bit += 1;
+				}
+			}
+		}
+		value
+	}
+
+	/// Index of the entry in the values list that the masked bits of bytes equal
+	pub fn match_value(&self, bytes: &[u8]) -> Option<usize> {
+		self.values.as_ref()?.iter().position(|value| {
+			self.mask.iter().zip(value).zip(bytes).all(|((m, v), b)| b & m == v & m)
+		})
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Format {
 	pub id: i32,
 	pub name: String,
 	pub segments: Vec<OperationSeg>,
 }
 
+impl Format {
+	/// Number of bytes in one instruction of this format, taken from the widest segment mask
+	pub fn size(&self) -> usize {
+		self.segments.iter().map(|s| s.mask.len()).max().unwrap_or(0)
+	}
+
+	/// Encode an instruction from a list of field values.
+	/// The Main segment is always applied, the nth field of a type goes in the nth segment of that type.
+	/// Segments with a values list take the field as an index into that list.
+	pub fn encode(&self, fields: &[(SegType, u32)]) -> Result<Vec<u8>, String> {
+		let mut out = vec![0; self.size()];
+
+		for seg in self.segments.iter().filter(|s| s.seg_type == SegType::Main) {
+			if let Some(value) = seg.values.as_ref().and_then(|v| v.first()) {
+				for (o, v) in out.iter_mut().zip(value) {
+					*o |= v;
+				}
+			}
+		}
+
+		for (i, field) in fields.iter().enumerate() {
+			let nth = fields[..i].iter().filter(|f| f.0 == field.0).count();
+			let seg = self.segments.iter().filter(|s| s.seg_type == field.0).nth(nth)
+				.ok_or(format!("format {} has no place for a {:?} operand", self.id, field.0))?;
+
+			match &seg.values {
+				Some(values) => {
+					let positioned = values.get(field.1 as usize)
+						.ok_or(format!("{} is not a valid value for {}", field.1, seg.name.clone().unwrap_or(format!("{:?}", seg.seg_type))))?;
+					for (o, v) in out.iter_mut().zip(positioned) {
+						*o |= v;
+					}
+				}
+				None => seg.encode(field.1, &mut out)?,
+			}
+		}
+		Ok(out)
+	}
+
+	/// Decode an instruction into the value of every segment except Main, in segment order.
+	/// Returns None when the Main segment does not match or a value is not in a segment's values list.
+	pub fn decode(&self, bytes: &[u8]) -> Option<Vec<(SegType, u32)>> {
+		if bytes.len() < self.size() {
+			return None;
+		}
+		let mut fields = Vec::new();
+		for seg in &self.segments {
+			match (seg.seg_type, &seg.values) {
+				(SegType::Main, Some(_)) => { seg.match_value(bytes)?; }
+				(SegType::Main, None) => {}
+				(_, Some(_)) => fields.push((seg.seg_type, seg.match_value(bytes)? as u32)),
+				(_, None) => fields.push((seg.seg_type, seg.decode(bytes))),
+			}
+		}
+		Some(fields)
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ProcessorDefinition {
 	pub name: String,
 	pub num_register: i32,
 	pub register_size: i32,
+	/// Instructions and data are stored least significant byte first
+	#[serde(default)]
+	pub little_endian: bool,
+	/// How far past an instruction's address PC reads while it runs, PC-relative offsets count from here
+	#[serde(default)]
+	pub pc_offset: i32,
 	pub formats: Vec<Format>,
-}
\ No newline at end of file
+}
+
+impl ProcessorDefinition {
+	/// Find a format by its id
+	pub fn get_format(&self, id: i32) -> Option<&Format> {
+		self.formats.iter().find(|f| f.id == id)
+	}
+
+	/// Find the first format an instruction belongs to and decode its fields.
+	/// bytes are in mask order, already swapped for endianness.
+	pub fn decode(&self, bytes: &[u8]) -> Option<(&Format, Vec<(SegType, u32)>)> {
+		self.formats.iter().find_map(|f| f.decode(bytes).map(|fields| (f, fields)))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn shift_format() -> Format {
+		Format {
+			id: 1,
+			name: "move shifted register".to_string(),
+			segments: vec![
+				OperationSeg { name: None, mask: vec![0b11100000,0], seg_type: SegType::Main, values: Some(vec![vec![0,0]])},
+				OperationSeg { name: Some("Op".to_string()), mask: vec![0b00011000,0], seg_type: SegType::Op, values: Some(vec![vec![0,0], vec![0b00001000,0], vec![0b00010000, 0]])},
+				OperationSeg { name: Some("Offset5".to_string()), mask: vec![0b00000111,0b11000000], seg_type: SegType::Immediate, values: None},
+				OperationSeg { name: Some("Rs".to_string()), mask: vec![0,0b00111000], seg_type: SegType::Source, values: None},
+				OperationSeg { name: Some("Rd".to_string()), mask: vec![0,0b00000111], seg_type: SegType::Destination, values: None},
+			]
+		}
+	}
+
+	#[test]
+	fn encode_split_mask() {
+		// lsr r3, r0, #31
+		let bytes = shift_format().encode(&[(SegType::Op, 1), (SegType::Destination, 3), (SegType::Source, 0), (SegType::Immediate, 31)]).unwrap();
+		assert_eq!(bytes, vec![0b00001111, 0b11000011]);
+	}
+
+	#[test]
+	fn encode_value_too_wide() {
+		let err = shift_format().encode(&[(SegType::Immediate, 32)]).unwrap_err();
+		assert!(err.contains("Offset5"));
+		assert!(shift_format().encode(&[(SegType::Op, 3)]).is_err());
+		assert!(shift_format().encode(&[(SegType::Flag, 1)]).is_err());
+	}
+
+	#[test]
+	fn encode_wide_format() {
+		// A made up 24 bit instruction with a field spread over all three bytes
+		let format = Format {
+			id: 1,
+			name: "wide".to_string(),
+			segments: vec![
+				OperationSeg { name: None, mask: vec![0b11000000,0,0], seg_type: SegType::Main, values: Some(vec![vec![0b10000000,0,0]])},
+				OperationSeg { name: Some("Imm".to_string()), mask: vec![0b00000001,0b11111111,0b10000000], seg_type: SegType::Immediate, values: None},
+			]
+		};
+		assert_eq!(format.encode(&[(SegType::Immediate, 0x3ff)]).unwrap(), vec![0b10000001, 0xff, 0b10000000]);
+	}
+
+	#[test]
+	fn field_wider_than_a_value() {
+		// A 40 bit field can not hold a 32 bit value, it is an error rather than a shift overflow
+		let seg = OperationSeg { name: Some("Imm".to_string()), mask: vec![0xff; 5], seg_type: SegType::Immediate, values: None };
+		assert_eq!(seg.encode(1, &mut [0; 5]), Err("the Imm field is 40 bits wide, more than the 32 of a value".to_string()));
+		assert_eq!(seg.decode(&[0xff, 0, 0, 0, 1]), 1);
+	}
+
+	#[test]
+	fn decode_reverses_encode() {
+		let fields = vec![(SegType::Op, 2), (SegType::Immediate, 27), (SegType::Source, 5), (SegType::Destination, 2)];
+		let bytes = shift_format().encode(&fields).unwrap();
+		assert_eq!(shift_format().decode(&bytes), Some(fields));
+		// Op 11 is not in the values list so this is not a shift
+		assert_eq!(shift_format().decode(&[0b00011000, 0]), None);
+		assert_eq!(shift_format().decode(&[0b00100000, 0]), None);
+	}
+}
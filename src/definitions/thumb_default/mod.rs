@@ -6,9 +6,8 @@ impl Default for LanguageDefinition {
 			
 			processor_def: Default::default(),
 			regex_list: vec![
-				r"^(?:[ \t]*)(?:(?P<label>[a-zA-Z_][a-zA-Z0-9_]*):)?(?:[ \t]*)(?P<command>[a-zA-Z][a-zA-Z0-9# \t,\[\]-\{\}!]*)?(?:[ \t]*)(?P<comment>@.*)?$".to_string(),
-				r"^(?:[ \t]*)(?P<compliemark>.[a-zA-Z]*)(?:[ \t]*)(?P<literal>[a-zA-Z_]+)?(?:[ \t]*)(?P<comment>@.*)?$".to_string(),
-				r##"^(?:[ \t]*)(?:(?P<label>[a-zA-Z_][a-zA-Z0-9_]*):)(?:[ \t]*)(?P<compliemark>.[a-zA-Z]*)(?:[ \t]*)(?P<literal>[#0-9xbn]+|"[\w\s]*")(?:[ \t]*)(?P<comment>@.*)?$"##.to_string(),
+				r"^(?:[ \t]*)(?:(?P<label>[a-zA-Z_.][a-zA-Z0-9_.$]*|[0-9]+):)?(?:[ \t]*)(?P<command>[a-zA-Z](?:[a-zA-Z0-9_# \t,\[\]\{\}!+*/%&|^~<>().=-]|'(?:[^'\\]|\\.)')*)?(?:[ \t]*)(?P<comment>@.*)?$".to_string(),
+				r##"^(?:[ \t]*)(?:(?P<label>[a-zA-Z_.][a-zA-Z0-9_.$]*|[0-9]+):)?(?:[ \t]*)(?P<compliemark>\.[a-zA-Z_][a-zA-Z0-9_]*)(?:[ \t]+(?P<literal>(?:[^@"' \t]|"(?:[^"\\]|\\.)*"|'(?:[^'\\]|\\.)')(?:[^@"']|"(?:[^"\\]|\\.)*"|'(?:[^'\\]|\\.)')*?))?(?:[ \t]*)(?P<comment>@.*)?$"##.to_string(),
 			],
 			/*/
 			capture_groups:vec![VecTree{
@@ -37,510 +36,697 @@ impl Default for LanguageDefinition {
 					CommandDefinition{
 						regex: r"^[aA][dD][cC][ \t]+(?P<destination>[rR][0-7]),[ \t]+(?P<source>[rR][0-7])".to_string(),
 						segments: vec![(SegType::Op,"5".to_string()),(SegType::Destination,"destination".to_string()),(SegType::Source,"source".to_string())],
-						format_index: 4
+						format_index: 4,
+						scale: 0,
+						split: 0
 					},
 				]),
 				("ADD".to_string(), vec![
 					CommandDefinition{
 						regex: r"^[aA][dD][dD][ \t]+(?P<destination>[rR][0-7]),[ \t]+(?P<source>[rR][0-7]),[ \t]+(?P<immediate>[rR][0-7])".to_string(),
 						segments: vec![(SegType::Flag,"0".to_string()),(SegType::Op,"0".to_string()),(SegType::Destination,"destination".to_string()),(SegType::Source,"source".to_string()),(SegType::Immediate,"immediate".to_string())],
-						format_index: 2
+						format_index: 2,
+						scale: 0,
+						split: 0
 					},
 					CommandDefinition{
-						regex: r"^[aA][dD][dD][ \t]+(?P<destination>[rR][0-7]),[ \t]+(?P<source>[rR][0-7]),[ \t]+#(?P<immediate>0x[0-9a-fA-F]+|[0-9]+)".to_string(),
+						regex: r"^[aA][dD][dD][ \t]+(?P<destination>[rR][0-7]),[ \t]+(?P<source>[rR][0-7]),[ \t]+#(?P<immediate>(?:[ \t]*(?:'(?:[^'\\]|\\.)'|[^,'\[\]\{\}\s]))+)".to_string(),
 						segments: vec![(SegType::Flag,"1".to_string()),(SegType::Op,"0".to_string()),(SegType::Destination,"destination".to_string()),(SegType::Source,"source".to_string()),(SegType::Immediate,"immediate".to_string())],
-						format_index: 2
+						format_index: 2,
+						scale: 0,
+						split: 0
 					},
 					CommandDefinition{
-						regex: r"^[aA][dD][dD][ \t]+(?P<destination>[rR][0-7]),[ \t]+#(?P<offset>0x[0-9a-fA-F]+|[0-9]+)".to_string(),
+						regex: r"^[aA][dD][dD][ \t]+(?P<destination>[rR][0-7]),[ \t]+#(?P<offset>(?:[ \t]*(?:'(?:[^'\\]|\\.)'|[^,'\[\]\{\}\s]))+)".to_string(),
 						segments: vec![(SegType::Op,"2".to_string()),(SegType::Destination,"destination".to_string()),(SegType::Immediate,"offset".to_string())],
-						format_index: 3
+						format_index: 3,
+						scale: 0,
+						split: 0
 					},
 					CommandDefinition{
 						regex: r"^[aA][dD][dD][ \t]+(?P<destination>[rR][0-7]),[ \t]+(?P<source>[hH][0-7])".to_string(),
 						segments: vec![(SegType::Op,"0".to_string()),(SegType::Flag,"0".to_string()),(SegType::Flag,"1".to_string()),(SegType::Destination,"destination".to_string()),(SegType::Source,"source".to_string())],
-						format_index: 5
+						format_index: 5,
+						scale: 0,
+						split: 0
 					},
 					CommandDefinition{
 						regex: r"^[aA][dD][dD][ \t]+(?P<destination>[hH][0-7]),[ \t]+(?P<source>[rR][0-7])".to_string(),
 						segments: vec![(SegType::Op,"0".to_string()),(SegType::Flag,"1".to_string()),(SegType::Flag,"0".to_string()),(SegType::Destination,"destination".to_string()),(SegType::Source,"source".to_string())],
-						format_index: 5
+						format_index: 5,
+						scale: 0,
+						split: 0
 					},
 					CommandDefinition{
 						regex: r"^[aA][dD][dD][ \t]+(?P<destination>[hH][0-7]),[ \t]+(?P<source>[hH][0-7])".to_string(),
 						segments: vec![(SegType::Op,"0".to_string()),(SegType::Flag,"1".to_string()),(SegType::Flag,"1".to_string()),(SegType::Destination,"destination".to_string()),(SegType::Source,"source".to_string())],
-						format_index: 5
+						format_index: 5,
+						scale: 0,
+						split: 0
 					},
 					CommandDefinition{
-						regex: r"^[aA][dD][dD][ \t]+(?P<destination>[rR][0-7]),[ \t]+[pP][cC],[ \t]+#(?P<immediate>0x[0-9a-fA-F]+|[0-9]+)".to_string(),
+						regex: r"^[aA][dD][dD][ \t]+(?P<destination>[rR][0-7]),[ \t]+[pP][cC],[ \t]+#(?P<immediate>(?:[ \t]*(?:'(?:[^'\\]|\\.)'|[^,'\[\]\{\}\s]))+)".to_string(),
 						segments: vec![(SegType::Flag,"0".to_string()),(SegType::Destination,"destination".to_string()),(SegType::Immediate,"immediate".to_string())],
-						format_index: 12
+						format_index: 12,
+						scale: 2,
+						split: 0
 					},
 					CommandDefinition{
-						regex: r"^[aA][dD][dD][ \t]+(?P<destination>[rR][0-7]),[ \t]+[sS][pP],[ \t]+#(?P<immediate>0x[0-9a-fA-F]+|[0-9]+)".to_string(),
+						regex: r"^[aA][dD][dD][ \t]+(?P<destination>[rR][0-7]),[ \t]+[sS][pP],[ \t]+#(?P<immediate>(?:[ \t]*(?:'(?:[^'\\]|\\.)'|[^,'\[\]\{\}\s]))+)".to_string(),
 						segments: vec![(SegType::Flag,"1".to_string()),(SegType::Destination,"destination".to_string()),(SegType::Immediate,"immediate".to_string())],
-						format_index: 12
-					},
-					CommandDefinition{
-						regex: r"^[aA][dD][dD][ \t]+[sS][pP],[ \t]+#(?P<immediate>0x[0-9a-fA-F]+|[0-9]+)".to_string(),
-						segments: vec![(SegType::Flag,"0".to_string()),(SegType::Immediate,"immediate".to_string())],
-						format_index: 13
+						format_index: 12,
+						scale: 2,
+						split: 0
 					},
 					CommandDefinition{
 						regex: r"^[aA][dD][dD][ \t]+[sS][pP],[ \t]+#-(?P<immediate>0x[0-9a-fA-F]+|[0-9]+)".to_string(),
 						segments: vec![(SegType::Flag,"1".to_string()),(SegType::Immediate,"immediate".to_string())],
-						format_index: 13
+						format_index: 13,
+						scale: 2,
+						split: 0
+					},
+					CommandDefinition{
+						regex: r"^[aA][dD][dD][ \t]+[sS][pP],[ \t]+#(?P<immediate>(?:[ \t]*(?:'(?:[^'\\]|\\.)'|[^,'\[\]\{\}\s]))+)".to_string(),
+						segments: vec![(SegType::Flag,"0".to_string()),(SegType::Immediate,"immediate".to_string())],
+						format_index: 13,
+						scale: 2,
+						split: 0
 					},
 				]),
 				("AND".to_string(), vec![
 					CommandDefinition{
 						regex: r"^[aA][nN][dD][ \t]+(?P<destination>[rR][0-7]),[ \t]+(?P<source>[rR][0-7])".to_string(),
 						segments: vec![(SegType::Op,"0".to_string()),(SegType::Destination,"destination".to_string()),(SegType::Source,"source".to_string())],
-						format_index: 4
+						format_index: 4,
+						scale: 0,
+						split: 0
 					},
 				]),
 				("ASR".to_string(), vec![
 					CommandDefinition{
-						regex: r"^[aA][sS][rR][ \t]+(?P<destination>[rR][0-7]),[ \t]+(?P<source>[rR][0-7]),[ \t]+#(?P<offset>0x[0-9a-fA-F]+|[0-9]+)".to_string(),
+						regex: r"^[aA][sS][rR][ \t]+(?P<destination>[rR][0-7]),[ \t]+(?P<source>[rR][0-7]),[ \t]+#(?P<offset>(?:[ \t]*(?:'(?:[^'\\]|\\.)'|[^,'\[\]\{\}\s]))+)".to_string(),
 						segments: vec![(SegType::Op,"2".to_string()),(SegType::Destination,"destination".to_string()),(SegType::Source,"source".to_string()),(SegType::Immediate,"offset".to_string())],
-						format_index: 1
+						format_index: 1,
+						scale: 0,
+						split: 0
 					},
 					CommandDefinition{
 						regex: r"^[aA][sS][rR][ \t]+(?P<destination>[rR][0-7]),[ \t]+(?P<source>[rR][0-7])".to_string(),
 						segments: vec![(SegType::Op,"4".to_string()),(SegType::Destination,"destination".to_string()),(SegType::Source,"source".to_string())],
-						format_index: 4
+						format_index: 4,
+						scale: 0,
+						split: 0
 					},
 				]),
 				("B".to_string(), vec![
 					CommandDefinition{
-						regex: r"^[bB][ \t]+(?P<label>[a-zA-Z_][a-zA-Z0-9_]*)".to_string(),
+						regex: r"^[bB][ \t]+(?P<label>(?:[ \t]*(?:'(?:[^'\\]|\\.)'|[^,'\[\]\{\}\s]))+)".to_string(),
 						segments: vec![(SegType::Offset,"label".to_string())],
-						format_index: 18
+						format_index: 18,
+						scale: 1,
+						split: 0
 					}
 				]),
 				("BEQ".to_string(), vec![
 					CommandDefinition{
-						regex: r"^[bB][eE][qQ][ \t]+(?P<label>[a-zA-Z_][a-zA-Z0-9_]*)".to_string(),
-						segments: vec![(SegType::Op,  "0".to_string()),(SegType::Offset, "label".to_string())],
-						format_index: 16
+						regex: r"^[bB][eE][qQ][ \t]+(?P<label>(?:[ \t]*(?:'(?:[^'\\]|\\.)'|[^,'\[\]\{\}\s]))+)".to_string(),
+						segments: vec![(SegType::Condition,  "0".to_string()),(SegType::Offset, "label".to_string())],
+						format_index: 16,
+						scale: 1,
+						split: 0
 					}
 				]),
 				("BNE".to_string(), vec![
 					CommandDefinition{
-						regex: r"^[bB][nN][eE][ \t]+(?P<label>[a-zA-Z_][a-zA-Z0-9_]*)".to_string(),
-						segments: vec![(SegType::Op,  "1".to_string()),(SegType::Offset, "label".to_string())],
-						format_index: 16
+						regex: r"^[bB][nN][eE][ \t]+(?P<label>(?:[ \t]*(?:'(?:[^'\\]|\\.)'|[^,'\[\]\{\}\s]))+)".to_string(),
+						segments: vec![(SegType::Condition,  "1".to_string()),(SegType::Offset, "label".to_string())],
+						format_index: 16,
+						scale: 1,
+						split: 0
 					}
 				]),
 				("BCS".to_string(), vec![
 					CommandDefinition{
-						regex: r"^[bB][cC][sS][ \t]+(?P<label>[a-zA-Z_][a-zA-Z0-9_]*)".to_string(),
-						segments: vec![(SegType::Op,  "2".to_string()),(SegType::Offset, "label".to_string())],
-						format_index: 16
+						regex: r"^[bB][cC][sS][ \t]+(?P<label>(?:[ \t]*(?:'(?:[^'\\]|\\.)'|[^,'\[\]\{\}\s]))+)".to_string(),
+						segments: vec![(SegType::Condition,  "2".to_string()),(SegType::Offset, "label".to_string())],
+						format_index: 16,
+						scale: 1,
+						split: 0
 					}
 				]),
 				("BCC".to_string(), vec![
 					CommandDefinition{
-						regex: r"^[bB][cC][cC][ \t]+(?P<label>[a-zA-Z_][a-zA-Z0-9_]*)".to_string(),
-						segments: vec![(SegType::Op,  "3".to_string()),(SegType::Offset, "label".to_string())],
-						format_index: 16
+						regex: r"^[bB][cC][cC][ \t]+(?P<label>(?:[ \t]*(?:'(?:[^'\\]|\\.)'|[^,'\[\]\{\}\s]))+)".to_string(),
+						segments: vec![(SegType::Condition,  "3".to_string()),(SegType::Offset, "label".to_string())],
+						format_index: 16,
+						scale: 1,
+						split: 0
 					}
 				]),
 				("BMI".to_string(), vec![
 					CommandDefinition{
-						regex: r"^[bB][mM][iI][ \t]+(?P<label>[a-zA-Z_][a-zA-Z0-9_]*)".to_string(),
-						segments: vec![(SegType::Op,  "4".to_string()),(SegType::Offset, "label".to_string())],
-						format_index: 16
+						regex: r"^[bB][mM][iI][ \t]+(?P<label>(?:[ \t]*(?:'(?:[^'\\]|\\.)'|[^,'\[\]\{\}\s]))+)".to_string(),
+						segments: vec![(SegType::Condition,  "4".to_string()),(SegType::Offset, "label".to_string())],
+						format_index: 16,
+						scale: 1,
+						split: 0
 					}
 				]),
 				("BPL".to_string(), vec![
 					CommandDefinition{
-						regex: r"^[bB][pP][lL][ \t]+(?P<label>[a-zA-Z_][a-zA-Z0-9_]*)".to_string(),
-						segments: vec![(SegType::Op,  "5".to_string()),(SegType::Offset, "label".to_string())],
-						format_index: 16
+						regex: r"^[bB][pP][lL][ \t]+(?P<label>(?:[ \t]*(?:'(?:[^'\\]|\\.)'|[^,'\[\]\{\}\s]))+)".to_string(),
+						segments: vec![(SegType::Condition,  "5".to_string()),(SegType::Offset, "label".to_string())],
+						format_index: 16,
+						scale: 1,
+						split: 0
 					}
 				]),
 				("BVS".to_string(), vec![
 					CommandDefinition{
-						regex: r"^[bB][vV][sS][ \t]+(?P<label>[a-zA-Z_][a-zA-Z0-9_]*)".to_string(),
-						segments: vec![(SegType::Op,  "6".to_string()),(SegType::Offset, "label".to_string())],
-						format_index: 16
+						regex: r"^[bB][vV][sS][ \t]+(?P<label>(?:[ \t]*(?:'(?:[^'\\]|\\.)'|[^,'\[\]\{\}\s]))+)".to_string(),
+						segments: vec![(SegType::Condition,  "6".to_string()),(SegType::Offset, "label".to_string())],
+						format_index: 16,
+						scale: 1,
+						split: 0
 					}
 				]),
 				("BVC".to_string(), vec![
 					CommandDefinition{
-						regex: r"^[bB][vV][cC][ \t]+(?P<label>[a-zA-Z_][a-zA-Z0-9_]*)".to_string(),
-						segments: vec![(SegType::Op,  "7".to_string()),(SegType::Offset, "label".to_string())],
-						format_index: 16
+						regex: r"^[bB][vV][cC][ \t]+(?P<label>(?:[ \t]*(?:'(?:[^'\\]|\\.)'|[^,'\[\]\{\}\s]))+)".to_string(),
+						segments: vec![(SegType::Condition,  "7".to_string()),(SegType::Offset, "label".to_string())],
+						format_index: 16,
+						scale: 1,
+						split: 0
 					}
 				]),
 				("BHI".to_string(), vec![
 					CommandDefinition{
-						regex: r"^[bB][hH][iI][ \t]+(?P<label>[a-zA-Z_][a-zA-Z0-9_]*)".to_string(),
-						segments: vec![(SegType::Op,  "8".to_string()),(SegType::Offset, "label".to_string())],
-						format_index: 16
+						regex: r"^[bB][hH][iI][ \t]+(?P<label>(?:[ \t]*(?:'(?:[^'\\]|\\.)'|[^,'\[\]\{\}\s]))+)".to_string(),
+						segments: vec![(SegType::Condition,  "8".to_string()),(SegType::Offset, "label".to_string())],
+						format_index: 16,
+						scale: 1,
+						split: 0
 					}
 				]),
 				("BLS".to_string(), vec![
 					CommandDefinition{
-						regex: r"^[bB][lL][sS][ \t]+(?P<label>[a-zA-Z_][a-zA-Z0-9_]*)".to_string(),
-						segments: vec![(SegType::Op,  "9".to_string()),(SegType::Offset, "label".to_string())],
-						format_index: 16
+						regex: r"^[bB][lL][sS][ \t]+(?P<label>(?:[ \t]*(?:'(?:[^'\\]|\\.)'|[^,'\[\]\{\}\s]))+)".to_string(),
+						segments: vec![(SegType::Condition,  "9".to_string()),(SegType::Offset, "label".to_string())],
+						format_index: 16,
+						scale: 1,
+						split: 0
 					}
 				]),
 				("BGE".to_string(), vec![
 					CommandDefinition{
-						regex: r"^[bB][gG][eE][ \t]+(?P<label>[a-zA-Z_][a-zA-Z0-9_]*)".to_string(),
-						segments: vec![(SegType::Op,  "10".to_string()),(SegType::Offset, "label".to_string())],
-						format_index: 16
+						regex: r"^[bB][gG][eE][ \t]+(?P<label>(?:[ \t]*(?:'(?:[^'\\]|\\.)'|[^,'\[\]\{\}\s]))+)".to_string(),
+						segments: vec![(SegType::Condition,  "10".to_string()),(SegType::Offset, "label".to_string())],
+						format_index: 16,
+						scale: 1,
+						split: 0
 					}
 				]),
 				("BLT".to_string(), vec![
 					CommandDefinition{
-						regex: r"^[bB][lL][tT][ \t]+(?P<label>[a-zA-Z_][a-zA-Z0-9_]*)".to_string(),
-						segments: vec![(SegType::Op,  "11".to_string()),(SegType::Offset, "label".to_string())],
-						format_index: 16
+						regex: r"^[bB][lL][tT][ \t]+(?P<label>(?:[ \t]*(?:'(?:[^'\\]|\\.)'|[^,'\[\]\{\}\s]))+)".to_string(),
+						segments: vec![(SegType::Condition,  "11".to_string()),(SegType::Offset, "label".to_string())],
+						format_index: 16,
+						scale: 1,
+						split: 0
 					}
 				]),
 				("BGT".to_string(), vec![
 					CommandDefinition{
-						regex: r"^[bB][gG][tT][ \t]+(?P<label>[a-zA-Z_][a-zA-Z0-9_]*)".to_string(),
-						segments: vec![(SegType::Op,  "12".to_string()),(SegType::Offset, "label".to_string())],
-						format_index: 16
+						regex: r"^[bB][gG][tT][ \t]+(?P<label>(?:[ \t]*(?:'(?:[^'\\]|\\.)'|[^,'\[\]\{\}\s]))+)".to_string(),
+						segments: vec![(SegType::Condition,  "12".to_string()),(SegType::Offset, "label".to_string())],
+						format_index: 16,
+						scale: 1,
+						split: 0
 					}
 				]),
 				("BLE".to_string(), vec![
 					CommandDefinition{
-						regex: r"^[bB][lL][eE][ \t]+(?P<label>[a-zA-Z_][a-zA-Z0-9_]*)".to_string(),
-						segments: vec![(SegType::Op,  "13".to_string()),(SegType::Offset, "label".to_string())],
-						format_index: 16
+						regex: r"^[bB][lL][eE][ \t]+(?P<label>(?:[ \t]*(?:'(?:[^'\\]|\\.)'|[^,'\[\]\{\}\s]))+)".to_string(),
+						segments: vec![(SegType::Condition,  "13".to_string()),(SegType::Offset, "label".to_string())],
+						format_index: 16,
+						scale: 1,
+						split: 0
 					}
 				]),
 				("BIC".to_string(), vec![
 					CommandDefinition{
 						regex: r"^[bB][iI][cC][ \t]+(?P<destination>[rR][0-7]),[ \t]+(?P<source>[rR][0-7])".to_string(),
 						segments: vec![(SegType::Op,"14".to_string()),(SegType::Destination,"destination".to_string()),(SegType::Source,"source".to_string())],
-						format_index: 4
+						format_index: 4,
+						scale: 0,
+						split: 0
 					},
 				]),
 				("BL".to_string(), vec![
 					CommandDefinition{
-						regex: r"^[bB][lL][ \t](?P<label>[a-zA-Z_][a-zA-Z0-9_]*)".to_string(),
+						regex: r"^[bB][lL][ \t](?P<label>(?:[ \t]*(?:'(?:[^'\\]|\\.)'|[^,'\[\]\{\}\s]))+)".to_string(),
 						segments: vec![(SegType::Offset,"label".to_string())],
-						format_index: 19
+						format_index: 19,
+						scale: 1,
+						split: 2
 					}
 				]),
 				("BX".to_string(), vec![
 					CommandDefinition{
 						regex: r"^[bB][xX][ \t]+(?P<source>[rR][0-7])".to_string(),
 						segments: vec![(SegType::Op,"3".to_string()),(SegType::Flag,"0".to_string()),(SegType::Flag,"0".to_string()),(SegType::Source,"source".to_string())],
-						format_index: 5
+						format_index: 5,
+						scale: 0,
+						split: 0
 					},
 					CommandDefinition{
 						regex: r"^[bB][xX][ \t]+(?P<source>[hH][0-7])".to_string(),
 						segments: vec![(SegType::Op,"3".to_string()),(SegType::Flag,"0".to_string()),(SegType::Flag,"1".to_string()),(SegType::Source,"source".to_string())],
-						format_index: 5
+						format_index: 5,
+						scale: 0,
+						split: 0
 					},
 				]),
 				("CMN".to_string(), vec![
 					CommandDefinition{
 						regex: r"^[cC][mM][nN][ \t]+(?P<destination>[rR][0-7]),[ \t]+(?P<source>[rR][0-7])".to_string(),
 						segments: vec![(SegType::Op,"11".to_string()),(SegType::Destination,"destination".to_string()),(SegType::Source,"source".to_string())],
-						format_index: 4
+						format_index: 4,
+						scale: 0,
+						split: 0
 					},
 				]),
 				("CMP".to_string(), vec![
 					CommandDefinition{
-						regex: r"^[cC][mM][pP][ \t]+(?P<destination>[rR][0-7]),[ \t]+#(?P<offset>0x[0-9a-fA-F]+|[0-9]+)".to_string(),
+						regex: r"^[cC][mM][pP][ \t]+(?P<destination>[rR][0-7]),[ \t]+#(?P<offset>(?:[ \t]*(?:'(?:[^'\\]|\\.)'|[^,'\[\]\{\}\s]))+)".to_string(),
 						segments: vec![(SegType::Op,"1".to_string()),(SegType::Destination,"destination".to_string()),(SegType::Immediate,"offset".to_string())],
-						format_index: 3
+						format_index: 3,
+						scale: 0,
+						split: 0
 					},
 					CommandDefinition{
 						regex: r"^[cC][mM][pP][ \t]+(?P<destination>[rR][0-7]),[ \t]+(?P<source>[rR][0-7])".to_string(),
 						segments: vec![(SegType::Op,"10".to_string()),(SegType::Destination,"destination".to_string()),(SegType::Source,"source".to_string())],
-						format_index: 4
+						format_index: 4,
+						scale: 0,
+						split: 0
 					},
 					CommandDefinition{
 						regex: r"^[cC][mM][pP][ \t]+(?P<destination>[rR][0-7]),[ \t]+(?P<source>[hH][0-7])".to_string(),
 						segments: vec![(SegType::Op,"1".to_string()),(SegType::Flag,"0".to_string()),(SegType::Flag,"1".to_string()),(SegType::Destination,"destination".to_string()),(SegType::Source,"source".to_string())],
-						format_index: 5
+						format_index: 5,
+						scale: 0,
+						split: 0
 					},
 					CommandDefinition{
 						regex: r"^[cC][mM][pP][ \t]+(?P<destination>[hH][0-7]),[ \t]+(?P<source>[rR][0-7])".to_string(),
 						segments: vec![(SegType::Op,"1".to_string()),(SegType::Flag,"1".to_string()),(SegType::Flag,"0".to_string()),(SegType::Destination,"destination".to_string()),(SegType::Source,"source".to_string())],
-						format_index: 5
+						format_index: 5,
+						scale: 0,
+						split: 0
 					},
 					CommandDefinition{
 						regex: r"^[cC][mM][pP][ \t]+(?P<destination>[hH][0-7]),[ \t]+(?P<source>[hH][0-7])".to_string(),
 						segments: vec![(SegType::Op,"1".to_string()),(SegType::Flag,"1".to_string()),(SegType::Flag,"1".to_string()),(SegType::Destination,"destination".to_string()),(SegType::Source,"source".to_string())],
-						format_index: 5
+						format_index: 5,
+						scale: 0,
+						split: 0
 					},
 				]),
 				("EOR".to_string(), vec![
 					CommandDefinition{
 						regex: r"^[eE][oO][rR][ \t]+(?P<destination>[rR][0-7]),[ \t]+(?P<source>[rR][0-7])".to_string(),
 						segments: vec![(SegType::Op,"1".to_string()),(SegType::Destination,"destination".to_string()),(SegType::Source,"source".to_string())],
-						format_index: 4
+						format_index: 4,
+						scale: 0,
+						split: 0
 					},
 				]),
 				("LDMIA".to_string(), vec![
 					CommandDefinition{
 						regex: r"^[lL][dD][mM][iI][aA][ \t]+(?P<base>[rR][0-7])!,[ \t]+\{[ \t]*(?P<list>[rR][0-7]|(?:[rR][0-7],[ \t]+){1,7}[rR][0-7])[ \t]*\}".to_string(),
-						segments: vec![(SegType::Flag, "1".to_string()),(SegType::Source, "base".to_string()),(SegType::Immediate,"list".to_string())],
-						format_index: 15
+						segments: vec![(SegType::Flag, "1".to_string()),(SegType::Source, "base".to_string()),(SegType::RegisterList,"list".to_string())],
+						format_index: 15,
+						scale: 0,
+						split: 0
 					},
 				]),
 				("LDR".to_string(), vec![
 					CommandDefinition{
-						regex: r"^[lL][dD][rR][ \t]+(?P<destination>[rR][0-7]),[ \t]+\[[ \t]*[pP][cC],[ \t]+#(?P<offset>0x[0-9a-fA-F]+|[0-9]+)\]".to_string(),
+						regex: r"^[lL][dD][rR][ \t]+(?P<destination>[rR][0-7]),[ \t]+\[[ \t]*[pP][cC],[ \t]+#(?P<offset>(?:[ \t]*(?:'(?:[^'\\]|\\.)'|[^,'\[\]\{\}\s]))+)\]".to_string(),
 						segments: vec![(SegType::Destination,"destination".to_string()),(SegType::Immediate,"offset".to_string())],
-						format_index: 6
+						format_index: 6,
+						scale: 2,
+						split: 0
+					},
+					CommandDefinition{
+						regex: r"^[lL][dD][rR][ \t]+(?P<destination>[rR][0-7]),[ \t]*=(?P<value>(?:[ \t]*(?:'(?:[^'\\]|\\.)'|[^,'\[\]\{\}\s]))+)".to_string(),
+						segments: vec![(SegType::Destination,"destination".to_string()),(SegType::Literal,"value".to_string())],
+						format_index: 6,
+						scale: 2,
+						split: 0
 					},
 					CommandDefinition{
 						regex: r"^[lL][dD][rR][ \t]+(?P<destination>[rR][0-7]),[ \t]+\[[ \t]*(?P<base>[rR][0-7]),[ \t]+(?P<offset>[rR][0-7])[ \t]*\]".to_string(),
 						segments: vec![(SegType::Flag,"1".to_string()),(SegType::Flag,"0".to_string()),(SegType::Destination,"destination".to_string()),(SegType::Source,"base".to_string()),(SegType::Offset,"offset".to_string())],
-						format_index: 7
+						format_index: 7,
+						scale: 0,
+						split: 0
 					},
 					CommandDefinition{
-						regex: r"^[lL][dD][rR][ \t]+(?P<destination>[rR][0-7]),[ \t]+\[[ \t]*(?P<base>[rR][0-7]),[ \t]+#(?P<offset>0x[0-9a-fA-F]+|[0-9]+)[ \t]*\]".to_string(),
+						regex: r"^[lL][dD][rR][ \t]+(?P<destination>[rR][0-7]),[ \t]+\[[ \t]*(?P<base>[rR][0-7]),[ \t]+#(?P<offset>(?:[ \t]*(?:'(?:[^'\\]|\\.)'|[^,'\[\]\{\}\s]))+)[ \t]*\]".to_string(),
 						segments: vec![(SegType::Flag,"0".to_string()),(SegType::Flag,"1".to_string()),(SegType::Destination,"destination".to_string()),(SegType::Source,"base".to_string()),(SegType::Offset,"offset".to_string())],
-						format_index: 9
+						format_index: 9,
+						scale: 2,
+						split: 0
 					},
 					CommandDefinition{
-						regex: r"^[lL][dD][rR][ \t]+(?P<destination>[rR][0-7]),[ \t]+\[[ \t]*[sS][pP],[ \t]+#(?P<offset>0x[0-9a-fA-F]+|[0-9]+)[ \t]*\]".to_string(),
+						regex: r"^[lL][dD][rR][ \t]+(?P<destination>[rR][0-7]),[ \t]+\[[ \t]*[sS][pP],[ \t]+#(?P<offset>(?:[ \t]*(?:'(?:[^'\\]|\\.)'|[^,'\[\]\{\}\s]))+)[ \t]*\]".to_string(),
 						segments: vec![(SegType::Flag,"1".to_string()),(SegType::Destination,"destination".to_string()),(SegType::Offset,"offset".to_string())],
-						format_index: 11
+						format_index: 11,
+						scale: 2,
+						split: 0
 					},
 				]),
 				("LDRB".to_string(), vec![
 					CommandDefinition{
 						regex: r"^[lL][dD][rR][bB][ \t]+(?P<destination>[rR][0-7]),[ \t]+\[[ \t]*(?P<base>[rR][0-7]),[ \t]+(?P<offset>[rR][0-7])[ \t]*\]".to_string(),
 						segments: vec![(SegType::Flag,"1".to_string()),(SegType::Flag,"1".to_string()),(SegType::Destination,"destination".to_string()),(SegType::Source,"base".to_string()),(SegType::Offset,"offset".to_string())],
-						format_index: 7
+						format_index: 7,
+						scale: 0,
+						split: 0
 					},
 					CommandDefinition{
-						regex: r"^[lL][dD][rR][bB][ \t]+(?P<destination>[rR][0-7]),[ \t]+\[[ \t]*(?P<base>[rR][0-7]),[ \t]+#(?P<offset>0x[0-9a-fA-F]+|[0-9])[ \t]*\]".to_string(),
+						regex: r"^[lL][dD][rR][bB][ \t]+(?P<destination>[rR][0-7]),[ \t]+\[[ \t]*(?P<base>[rR][0-7]),[ \t]+#(?P<offset>(?:[ \t]*(?:'(?:[^'\\]|\\.)'|[^,'\[\]\{\}\s]))+)[ \t]*\]".to_string(),
 						segments: vec![(SegType::Flag,"1".to_string()),(SegType::Flag,"1".to_string()),(SegType::Destination,"destination".to_string()),(SegType::Source,"base".to_string()),(SegType::Offset,"offset".to_string())],
-						format_index: 9
+						format_index: 9,
+						scale: 0,
+						split: 0
 					},
 				]),
 				("LDRH".to_string(), vec![
 					CommandDefinition{
 						regex: r"^[lL][dD][rR][hH][ \t]+(?P<destination>[rR][0-7]),[ \t]+\[[ \t]*(?P<base>[rR][0-7]),[ \t]+(?P<offset>[rR][0-7])[ \t]*\]".to_string(),
 						segments: vec![(SegType::Flag,"1".to_string()),(SegType::Flag,"0".to_string()),(SegType::Destination,"destination".to_string()),(SegType::Source,"base".to_string()),(SegType::Offset,"offset".to_string())],
-						format_index: 8
+						format_index: 8,
+						scale: 0,
+						split: 0
 					},
 					CommandDefinition{
-						regex: r"^[lL][dD][rR][hH][ \t]+(?P<destination>[rR][0-7]),[ \t]+\[[ \t]*(?P<base>[rR][0-7]),[ \t]+#(?P<offset>0x[0-9a-fA-F]+|[0-9])[ \t]*\]".to_string(),
+						regex: r"^[lL][dD][rR][hH][ \t]+(?P<destination>[rR][0-7]),[ \t]+\[[ \t]*(?P<base>[rR][0-7]),[ \t]+#(?P<offset>(?:[ \t]*(?:'(?:[^'\\]|\\.)'|[^,'\[\]\{\}\s]))+)[ \t]*\]".to_string(),
 						segments: vec![(SegType::Flag,"1".to_string()),(SegType::Destination,"destination".to_string()),(SegType::Source,"base".to_string()),(SegType::Offset,"offset".to_string())],
-						format_index: 10
+						format_index: 10,
+						scale: 1,
+						split: 0
 					},
 				]),
 				("LSL".to_string(), vec![
 					CommandDefinition{
-						regex: r"^[lL][sS][lL][ \t]+(?P<destination>[rR][0-7]),[ \t]+(?P<source>[rR][0-7]),[ \t]+#(?P<offset>0x[0-9a-fA-F]+|[0-9]+)".to_string(),
+						regex: r"^[lL][sS][lL][ \t]+(?P<destination>[rR][0-7]),[ \t]+(?P<source>[rR][0-7]),[ \t]+#(?P<offset>(?:[ \t]*(?:'(?:[^'\\]|\\.)'|[^,'\[\]\{\}\s]))+)".to_string(),
 						segments: vec![(SegType::Op,"0".to_string()),(SegType::Destination,"destination".to_string()),(SegType::Source,"source".to_string()),(SegType::Immediate,"offset".to_string())],
-						format_index: 1
+						format_index: 1,
+						scale: 0,
+						split: 0
 					},
 					CommandDefinition{
 						regex: r"^[lL][sS][lL][ \t]+(?P<destination>[rR][0-7]),[ \t]+(?P<source>[rR][0-7])".to_string(),
 						segments: vec![(SegType::Op,"2".to_string()),(SegType::Destination,"destination".to_string()),(SegType::Source,"source".to_string())],
-						format_index: 4
+						format_index: 4,
+						scale: 0,
+						split: 0
 					},
 				]),
 				("LDSB".to_string(), vec![
 					CommandDefinition{
 						regex: r"^[lL][dD][sS][bB][ \t]+(?P<destination>[rR][0-7]),[ \t]+\[[ \t]*(?P<base>[rR][0-7]),[ \t]+(?P<offset>[rR][0-7])[ \t]*\]".to_string(),
 						segments: vec![(SegType::Flag,"0".to_string()),(SegType::Flag,"1".to_string()),(SegType::Destination,"destination".to_string()),(SegType::Source,"base".to_string()),(SegType::Offset,"offset".to_string())],
-						format_index: 8
+						format_index: 8,
+						scale: 0,
+						split: 0
 					},
 				]),
 				("LDSH".to_string(), vec![
 					CommandDefinition{
 						regex: r"^[lL][dD][sS][hH][ \t]+(?P<destination>[rR][0-7]),[ \t]+\[[ \t]*(?P<base>[rR][0-7]),[ \t]+(?P<offset>[rR][0-7])[ \t]*\]".to_string(),
 						segments: vec![(SegType::Flag,"1".to_string()),(SegType::Flag,"1".to_string()),(SegType::Destination,"destination".to_string()),(SegType::Source,"base".to_string()),(SegType::Offset,"offset".to_string())],
-						format_index: 8
+						format_index: 8,
+						scale: 0,
+						split: 0
 					},
 				]),
 				("LSR".to_string(), vec![
 					CommandDefinition{
-						regex: r"^[lL][sS][rR][ \t]+(?P<destination>[rR][0-7]),[ \t]+(?P<source>[rR][0-7]),[ \t]+#(?P<offset>0x[0-9a-fA-F]+|[0-9]+)".to_string(),
+						regex: r"^[lL][sS][rR][ \t]+(?P<destination>[rR][0-7]),[ \t]+(?P<source>[rR][0-7]),[ \t]+#(?P<offset>(?:[ \t]*(?:'(?:[^'\\]|\\.)'|[^,'\[\]\{\}\s]))+)".to_string(),
 						segments: vec![(SegType::Op,"1".to_string()),(SegType::Destination,"destination".to_string()),(SegType::Source,"source".to_string()),(SegType::Immediate,"offset".to_string())],
-						format_index: 1
+						format_index: 1,
+						scale: 0,
+						split: 0
 					},
 					CommandDefinition{
 						regex: r"^[lL][sS][rR][ \t]+(?P<destination>[rR][0-7]),[ \t]+(?P<source>[rR][0-7])".to_string(),
 						segments: vec![(SegType::Op,"3".to_string()),(SegType::Destination,"destination".to_string()),(SegType::Source,"source".to_string())],
-						format_index: 4
+						format_index: 4,
+						scale: 0,
+						split: 0
 					},
 				]),
 				("MOV".to_string(), vec![
 					CommandDefinition{
-						regex: r"^[mM][oO][vV][ \t]+(?P<destination>[rR][0-7]),[ \t]+#(?P<offset>0x[0-9a-fA-F]+|[0-9]+)".to_string(),
+						regex: r"^[mM][oO][vV][ \t]+(?P<destination>[rR][0-7]),[ \t]+#(?P<offset>(?:[ \t]*(?:'(?:[^'\\]|\\.)'|[^,'\[\]\{\}\s]))+)".to_string(),
 						segments: vec![(SegType::Op,"0".to_string()),(SegType::Destination,"destination".to_string()),(SegType::Immediate,"offset".to_string())],
-						format_index: 3
+						format_index: 3,
+						scale: 0,
+						split: 0
 					},
 					CommandDefinition{
 						regex: r"^[mM][oO][vV][ \t]+(?P<destination>[rR][0-7]),[ \t]+(?P<source>[hH][0-7])".to_string(),
 						segments: vec![(SegType::Op,"2".to_string()),(SegType::Flag,"0".to_string()),(SegType::Flag,"1".to_string()),(SegType::Destination,"destination".to_string()),(SegType::Source,"source".to_string())],
-						format_index: 5
+						format_index: 5,
+						scale: 0,
+						split: 0
 					},
 					CommandDefinition{
 						regex: r"^[mM][oO][vV][ \t]+(?P<destination>[hH][0-7]),[ \t]+(?P<source>[rR][0-7])".to_string(),
 						segments: vec![(SegType::Op,"2".to_string()),(SegType::Flag,"1".to_string()),(SegType::Flag,"0".to_string()),(SegType::Destination,"destination".to_string()),(SegType::Source,"source".to_string())],
-						format_index: 5
+						format_index: 5,
+						scale: 0,
+						split: 0
 					},
 					CommandDefinition{
 						regex: r"^[mM][oO][vV][ \t]+(?P<destination>[hH][0-7]),[ \t]+(?P<source>[hH][0-7])".to_string(),
 						segments: vec![(SegType::Op,"2".to_string()),(SegType::Flag,"1".to_string()),(SegType::Flag,"1".to_string()),(SegType::Destination,"destination".to_string()),(SegType::Source,"source".to_string())],
-						format_index: 5
+						format_index: 5,
+						scale: 0,
+						split: 0
 					},
 				]),
 				("MUL".to_string(), vec![
 					CommandDefinition{
 						regex: r"^[mM][uU][lL][ \t]+(?P<destination>[rR][0-7]),[ \t]+(?P<source>[rR][0-7])".to_string(),
 						segments: vec![(SegType::Op,"13".to_string()),(SegType::Destination,"destination".to_string()),(SegType::Source,"source".to_string())],
-						format_index: 4
+						format_index: 4,
+						scale: 0,
+						split: 0
 					},
 				]),
 				("MVN".to_string(), vec![
 					CommandDefinition{
 						regex: r"^[mM][vV][nN][ \t]+(?P<destination>[rR][0-7]),[ \t]+(?P<source>[rR][0-7])".to_string(),
 						segments: vec![(SegType::Op,"15".to_string()),(SegType::Destination,"destination".to_string()),(SegType::Source,"source".to_string())],
-						format_index: 4
+						format_index: 4,
+						scale: 0,
+						split: 0
 					},
 				]),
 				("NEG".to_string(), vec![
 					CommandDefinition{
 						regex: r"^[nN][eE][gG][ \t]+(?P<destination>[rR][0-7]),[ \t]+(?P<source>[rR][0-7])".to_string(),
 						segments: vec![(SegType::Op,"9".to_string()),(SegType::Destination,"destination".to_string()),(SegType::Source,"source".to_string())],
-						format_index: 4
+						format_index: 4,
+						scale: 0,
+						split: 0
 					},
 				]),
 				("ORR".to_string(), vec![
 					CommandDefinition{
 						regex: r"^[oO][rR][rR][ \t]+(?P<destination>[rR][0-7]),[ \t]+(?P<source>[rR][0-7])".to_string(),
 						segments: vec![(SegType::Op,"12".to_string()),(SegType::Destination,"destination".to_string()),(SegType::Source,"source".to_string())],
-						format_index: 4
+						format_index: 4,
+						scale: 0,
+						split: 0
 					},
 				]),
 				("POP".to_string(), vec![
 					CommandDefinition{
 						regex: r"^[pP][oO][pP][ \t]+\{[ \t]*(?P<list>[rR][0-7]|(?:[rR][0-7],[ \t]+){1,7}[rR][0-7])[ \t]*\}".to_string(),
-						segments: vec![(SegType::Flag, "1".to_string()),(SegType::Flag, "0".to_string()),(SegType::Immediate,"list".to_string())],
-						format_index: 14
+						segments: vec![(SegType::Flag, "1".to_string()),(SegType::Flag, "0".to_string()),(SegType::RegisterList,"list".to_string())],
+						format_index: 14,
+						scale: 0,
+						split: 0
 					},
 					CommandDefinition{
 						regex: r"^[pP][oO][pP][ \t]+\{[ \t]*(?P<list>(?:[rR][0-7],[ \t]+){1,8})[pP][cC][ \t]*\}".to_string(),
-						segments: vec![(SegType::Flag, "1".to_string()),(SegType::Flag, "1".to_string()),(SegType::Immediate,"list".to_string())],
-						format_index: 14
+						segments: vec![(SegType::Flag, "1".to_string()),(SegType::Flag, "1".to_string()),(SegType::RegisterList,"list".to_string())],
+						format_index: 14,
+						scale: 0,
+						split: 0
 					},
 				]),
 				("PUSH".to_string(), vec![
 					CommandDefinition{
 						regex: r"^[pP][uU][sS][hH][ \t]+\{[ \t]*(?P<list>[rR][0-7]|(?:[rR][0-7],[ \t]+){1,7}[rR][0-7])[ \t]*\}".to_string(),
-						segments: vec![(SegType::Flag, "0".to_string()),(SegType::Flag, "0".to_string()),(SegType::Immediate,"list".to_string())],
-						format_index: 14
+						segments: vec![(SegType::Flag, "0".to_string()),(SegType::Flag, "0".to_string()),(SegType::RegisterList,"list".to_string())],
+						format_index: 14,
+						scale: 0,
+						split: 0
 					},
 					CommandDefinition{
 						regex: r"^[pP][uU][sS][hH][ \t]+\{[ \t]*(?P<list>(?:[rR][0-7],[ \t]+){1,8})[lL][rR][ \t]*\}".to_string(),
-						segments: vec![(SegType::Flag, "0".to_string()),(SegType::Flag, "1".to_string()),(SegType::Immediate,"list".to_string())],
-						format_index: 14
+						segments: vec![(SegType::Flag, "0".to_string()),(SegType::Flag, "1".to_string()),(SegType::RegisterList,"list".to_string())],
+						format_index: 14,
+						scale: 0,
+						split: 0
 					},
 				]),
 				("ROR".to_string(), vec![
 					CommandDefinition{
 						regex: r"^[rR][oO][rR][ \t]+(?P<destination>[rR][0-7]),[ \t]+(?P<source>[rR][0-7])".to_string(),
 						segments: vec![(SegType::Op,"7".to_string()),(SegType::Destination,"destination".to_string()),(SegType::Source,"source".to_string())],
-						format_index: 4
+						format_index: 4,
+						scale: 0,
+						split: 0
 					},
 				]),
 				("SBC".to_string(), vec![
 					CommandDefinition{
 						regex: r"^[sS][bB][cC][ \t]+(?P<destination>[rR][0-7]),[ \t]+(?P<source>[rR][0-7])".to_string(),
 						segments: vec![(SegType::Op,"6".to_string()),(SegType::Destination,"destination".to_string()),(SegType::Source,"source".to_string())],
-						format_index: 4
+						format_index: 4,
+						scale: 0,
+						split: 0
 					},
 				]),
 				("STMIA".to_string(), vec![
 					CommandDefinition{
 						regex: r"^[sS][tT][mM][iI][aA][ \t]+(?P<base>[rR][0-7])!,[ \t]+\{[ \t]*(?P<list>[rR][0-7]|(?:[rR][0-7],[ \t]+){1,7}[rR][0-7])[ \t]*\}".to_string(),
-						segments: vec![(SegType::Flag, "0".to_string()),(SegType::Source, "base".to_string()),(SegType::Immediate,"list".to_string())],
-						format_index: 15
+						segments: vec![(SegType::Flag, "0".to_string()),(SegType::Source, "base".to_string()),(SegType::RegisterList,"list".to_string())],
+						format_index: 15,
+						scale: 0,
+						split: 0
 					},
 				]),
 				("STR".to_string(), vec![
 					CommandDefinition{
 						regex: r"^[sS][tT][rR][ \t]+(?P<destination>[rR][0-7]),[ \t]+\[[ \t]*(?P<base>[rR][0-7]),[ \t]+(?P<offset>[rR][0-7])[ \t]*\]".to_string(),
 						segments: vec![(SegType::Flag,"0".to_string()),(SegType::Flag,"0".to_string()),(SegType::Destination,"destination".to_string()),(SegType::Source,"base".to_string()),(SegType::Offset,"offset".to_string())],
-						format_index: 7
+						format_index: 7,
+						scale: 0,
+						split: 0
 					},
 					CommandDefinition{
-						regex: r"^[sS][tT][rR][ \t]+(?P<destination>[rR][0-7]),[ \t]+\[[ \t]*(?P<base>[rR][0-7]),[ \t]+#(?P<offset>0x[0-9a-fA-F]+|[0-9]+)[ \t]*\]".to_string(),
+						regex: r"^[sS][tT][rR][ \t]+(?P<destination>[rR][0-7]),[ \t]+\[[ \t]*(?P<base>[rR][0-7]),[ \t]+#(?P<offset>(?:[ \t]*(?:'(?:[^'\\]|\\.)'|[^,'\[\]\{\}\s]))+)[ \t]*\]".to_string(),
 						segments: vec![(SegType::Flag,"0".to_string()),(SegType::Flag,"0".to_string()),(SegType::Destination,"destination".to_string()),(SegType::Source,"base".to_string()),(SegType::Offset,"offset".to_string())],
-						format_index: 9
+						format_index: 9,
+						scale: 2,
+						split: 0
 					},
 					CommandDefinition{
-						regex: r"^[sS][tT][rR][ \t]+(?P<destination>[rR][0-7]),[ \t]+\[[ \t]*[sS][pP],[ \t]+#(?P<offset>0x[0-9a-fA-F]+|[0-9]+)[ \t]*\]".to_string(),
+						regex: r"^[sS][tT][rR][ \t]+(?P<destination>[rR][0-7]),[ \t]+\[[ \t]*[sS][pP],[ \t]+#(?P<offset>(?:[ \t]*(?:'(?:[^'\\]|\\.)'|[^,'\[\]\{\}\s]))+)[ \t]*\]".to_string(),
 						segments: vec![(SegType::Flag,"0".to_string()),(SegType::Destination,"destination".to_string()),(SegType::Offset,"offset".to_string())],
-						format_index: 11
+						format_index: 11,
+						scale: 2,
+						split: 0
 					},
 				]),
 				("STRB".to_string(), vec![
 					CommandDefinition{
 						regex: r"^[sS][tT][rR][bB][ \t]+(?P<destination>[rR][0-7]),[ \t]+\[[ \t]*(?P<base>[rR][0-7]),[ \t]+(?P<offset>[rR][0-7])[ \t]*\]".to_string(),
 						segments: vec![(SegType::Flag,"0".to_string()),(SegType::Flag,"1".to_string()),(SegType::Destination,"destination".to_string()),(SegType::Source,"base".to_string()),(SegType::Offset,"offset".to_string())],
-						format_index: 7
+						format_index: 7,
+						scale: 0,
+						split: 0
 					},
 					CommandDefinition{
-						regex: r"^[sS][tT][rR][bB][ \t]+(?P<destination>[rR][0-7]),[ \t]+\[[ \t]*(?P<base>[rR][0-7]),[ \t]+#(?P<offset>0x[0-9a-fA-F]+|[0-9]+)[ \t]*\]".to_string(),
+						regex: r"^[sS][tT][rR][bB][ \t]+(?P<destination>[rR][0-7]),[ \t]+\[[ \t]*(?P<base>[rR][0-7]),[ \t]+#(?P<offset>(?:[ \t]*(?:'(?:[^'\\]|\\.)'|[^,'\[\]\{\}\s]))+)[ \t]*\]".to_string(),
 						segments: vec![(SegType::Flag,"1".to_string()),(SegType::Flag,"0".to_string()),(SegType::Destination,"destination".to_string()),(SegType::Source,"base".to_string()),(SegType::Offset,"offset".to_string())],
-						format_index: 9
+						format_index: 9,
+						scale: 0,
+						split: 0
 					},
 				]),
 				("STRH".to_string(), vec![
 					CommandDefinition{
 						regex: r"^[sS][tT][rR][hH][ \t]+(?P<destination>[rR][0-7]),[ \t]+\[[ \t]*(?P<base>[rR][0-7]),[ \t]+(?P<offset>[rR][0-7])[ \t]*\]".to_string(),
 						segments: vec![(SegType::Flag,"0".to_string()),(SegType::Flag,"0".to_string()),(SegType::Destination,"destination".to_string()),(SegType::Source,"base".to_string()),(SegType::Offset,"offset".to_string())],
-						format_index: 8
+						format_index: 8,
+						scale: 0,
+						split: 0
 					},
 					CommandDefinition{
-						regex: r"^[sS][tT][rR][hH][ \t]+(?P<destination>[rR][0-7]),[ \t]+\[[ \t]*(?P<base>[rR][0-7]),[ \t]+#(?P<offset>0x[0-9a-fA-F]+|[0-9])[ \t]*\]".to_string(),
+						regex: r"^[sS][tT][rR][hH][ \t]+(?P<destination>[rR][0-7]),[ \t]+\[[ \t]*(?P<base>[rR][0-7]),[ \t]+#(?P<offset>(?:[ \t]*(?:'(?:[^'\\]|\\.)'|[^,'\[\]\{\}\s]))+)[ \t]*\]".to_string(),
 						segments: vec![(SegType::Flag,"0".to_string()),(SegType::Destination,"destination".to_string()),(SegType::Source,"base".to_string()),(SegType::Offset,"offset".to_string())],
-						format_index: 10
+						format_index: 10,
+						scale: 1,
+						split: 0
 					},
 				]),
 				("SWI".to_string(), vec![
 					CommandDefinition{
 						regex: r"^[sS][wW][iI][ \t]+(?P<comment>[0-9]+)".to_string(),
 						segments: vec![(SegType::Immediate, "comment".to_string())],
-						format_index: 17
+						format_index: 17,
+						scale: 0,
+						split: 0
 					}
 				]),
 				("SUB".to_string(), vec![
 					CommandDefinition{
 						regex: r"^[sS][uU][bB][ \t]+(?P<destination>[rR][0-7]),[ \t]+(?P<source>[rR][0-7]),[ \t]+(?P<immediate>[rR][0-7])".to_string(),
 						segments: vec![(SegType::Flag,"0".to_string()),(SegType::Op,"1".to_string()),(SegType::Destination,"destination".to_string()),(SegType::Source,"source".to_string()),(SegType::Immediate,"immediate".to_string())],
-						format_index: 2
+						format_index: 2,
+						scale: 0,
+						split: 0
 					},
 					CommandDefinition{
-						regex: r"^[sS][uU][bB][ \t]+(?P<destination>[rR][0-7]),[ \t]+(?P<source>[rR][0-7]),[ \t]+#(?P<immediate>0x[0-9a-fA-F]+|[0-9]+)".to_string(),
+						regex: r"^[sS][uU][bB][ \t]+(?P<destination>[rR][0-7]),[ \t]+(?P<source>[rR][0-7]),[ \t]+#(?P<immediate>(?:[ \t]*(?:'(?:[^'\\]|\\.)'|[^,'\[\]\{\}\s]))+)".to_string(),
 						segments: vec![(SegType::Flag,"1".to_string()),(SegType::Op,"1".to_string()),(SegType::Destination,"destination".to_string()),(SegType::Source,"source".to_string()),(SegType::Immediate,"immediate".to_string())],
-						format_index: 2
+						format_index: 2,
+						scale: 0,
+						split: 0
 					},
 					CommandDefinition{
-						regex: r"^[sS][uU][bB][ \t]+(?P<destination>[rR][0-7]),[ \t]+#(?P<offset>0x[0-9a-fA-F]+|[0-9]+)".to_string(),
+						regex: r"^[sS][uU][bB][ \t]+(?P<destination>[rR][0-7]),[ \t]+#(?P<offset>(?:[ \t]*(?:'(?:[^'\\]|\\.)'|[^,'\[\]\{\}\s]))+)".to_string(),
 						segments: vec![(SegType::Op,"3".to_string()),(SegType::Destination,"destination".to_string()),(SegType::Immediate,"offset".to_string())],
-						format_index: 3
+						format_index: 3,
+						scale: 0,
+						split: 0
 					}
 				]),
 				("TST".to_string(), vec![
 					CommandDefinition{
 						regex: r"^[tT][sS][tT][ \t]+(?P<destination>[rR][0-7]),[ \t]+(?P<source>[rR][0-7])".to_string(),
 						segments: vec![(SegType::Op,"8".to_string()),(SegType::Destination,"destination".to_string()),(SegType::Source,"source".to_string())],
-						format_index: 4
+						format_index: 4,
+						scale: 0,
+						split: 0
 					},
 				]),
+			],
+			aliases: vec![
+				("NOP".to_string(), AliasDefinition {
+					regex: r"^[nN][oO][pP]".to_string(),
+					expansion: vec!["mov h0, h0".to_string()],
+				}),
+				// Thumb has no move between low registers, adding 0 sets the flags like a move would
+				("MOV".to_string(), AliasDefinition {
+					regex: r"^[mM][oO][vV][ \t]+(?P<destination>[rR][0-7]),[ \t]+(?P<source>[rR][0-7])".to_string(),
+					expansion: vec!["add ${destination}, ${source}, #0".to_string()],
+				}),
+				// The address is worked out from PC rounded down to a word, like the processor does
+				("ADR".to_string(), AliasDefinition {
+					regex: r"^[aA][dD][rR][ \t]+(?P<destination>[rR][0-7]),[ \t]+(?P<label>(?:[ \t]*(?:'(?:[^'\\]|\\.)'|[^,'\[\]\{\}\s]))+)".to_string(),
+					expansion: vec!["add ${destination}, pc, #(${label}) - ((. + 4) & ~3)".to_string()],
+				}),
 			]
 		}
 	}
@@ -553,6 +739,8 @@ impl Default for ProcessorDefinition {
 			name: "ARM Thumbv1".to_string(),
 			num_register: 16,
 			register_size: 16,
+			little_endian: true,
+			pc_offset: 4,
 			formats: vec![
 				Format {
 					id: 1,
@@ -576,9 +764,9 @@ impl Default for ProcessorDefinition {
 					segments: vec![
 						// Main: Mask: 1111 1000 0000 0000 Values: [0]: 0001 1
 						OperationSeg { name: None, mask: vec![0b11111000,0], seg_type: SegType::Main, values: Some(vec![vec![0b00011000,0]])},
-						// Immediate flag: Mask: 0000 0010 0000 0000 Values: all  [ADD]: 0, [SUB]: 1
+						// Immediate flag: Mask: 0000 0100 0000 0000 Values: all [Register]: 0, [Immediate]: 1
 						OperationSeg { name: Some("I".to_string()), mask: vec![0b00000100,0], seg_type: SegType::Flag, values: None},
-						// OP: Mask: 0000 0010 0000 0000 Values: all [Register]: 0, [Immediate]: 1
+						// OP: Mask: 0000 0010 0000 0000 Values: all [ADD]: 0, [SUB]: 1
 						OperationSeg { name: Some("Op".to_string()), mask: vec![0b00000010,0], seg_type: SegType::Op, values: None},
 						// Rn/Offset3: Mask: 0000 0001 1100 0000 Values: Any
 						OperationSeg { name: Some("Rn/Offset3".to_string()), mask: vec![0b00000001,0b11000000], seg_type: SegType::Immediate, values: None},
@@ -609,7 +797,7 @@ impl Default for ProcessorDefinition {
 						// Main: Mask: 1111 1100 0000 0000 Values: [0]: 0100 00
 						OperationSeg { name: None, mask: vec![0b11111100,0], seg_type: SegType::Main, values: Some(vec![vec![0b01000000,0]])},
 						// OP: Mask: 0000 0011 1100 0000 Values: Any
-						OperationSeg { name: Some("OP".to_string()), mask: vec![0b00000011,0b1100000], seg_type: SegType::Op, values: None},
+						OperationSeg { name: Some("OP".to_string()), mask: vec![0b00000011,0b11000000], seg_type: SegType::Op, values: None},
 						// Source register 2: 0000 0000 0011 1000 Values: Any
 						OperationSeg { name: Some("Rs".to_string()), mask: vec![0,0b00111000], seg_type: SegType::Source, values: None},
 						// Source/destination register: Mask: 0000 0000 0000 0111 Values: Any
@@ -625,9 +813,9 @@ impl Default for ProcessorDefinition {
 						// OP: Mask: 0000 0011 0000 0000 Values: all [ADD]: 00, [CMP]: 01, [MOV]: 10, [BX]: 11
 						OperationSeg { name: Some("Op".to_string()), mask: vec![0b00000011,0], seg_type: SegType::Op, values: None},
 						// Hi operand flag 1: Mask: 0000 0000 1000 0000 Values: any [LOW]: 0, [HI]: 1
-						OperationSeg { name: Some("H1".to_string()), mask: vec![0,0b1000000], seg_type: SegType::Flag, values: None},
+						OperationSeg { name: Some("H1".to_string()), mask: vec![0,0b10000000], seg_type: SegType::Flag, values: None},
 						// Hi operand flag 2: Mask: 0000 0000 0100 0000 Values: Any [LOW]: 0, [HI]: 1
-						OperationSeg { name: Some("H2".to_string()), mask: vec![0,0b01000000], seg_type: SegType::Immediate, values: None},
+						OperationSeg { name: Some("H2".to_string()), mask: vec![0,0b01000000], seg_type: SegType::Flag, values: None},
 						// Source register: Mask: 0000 0000 0011 1000 Values: Any
 						OperationSeg { name: Some("Rs/Hs".to_string()), mask: vec![0,0b00111000], seg_type: SegType::Source, values: None},
 						// Destination register: Mask: 0000 0000 0000 0111 Values: Any
@@ -707,9 +895,9 @@ impl Default for ProcessorDefinition {
 						// Main: Mask: 1111 0000 0000 0000 Values: [0]: 1000 
 						OperationSeg { name: None, mask: vec![0b11110000,0], seg_type: SegType::Main, values: Some(vec![vec![0b10000000,0]])},
 						// Load/Store flag: Mask: 0000 1000 0000 0000 Values: all [Store]: 0, [Load]: 1
-						OperationSeg { name: Some("L".to_string()), mask: vec![0b00000100,0], seg_type: SegType::Flag, values: None},
+						OperationSeg { name: Some("L".to_string()), mask: vec![0b00001000,0], seg_type: SegType::Flag, values: None},
 						// Immediate value: Mask: 0000 0111 1100 0000 Values: Any 
-						OperationSeg { name: Some("Offset5".to_string()), mask: vec![0b00000111,0b11000000], seg_type: SegType::Immediate, values: None},
+						OperationSeg { name: Some("Offset5".to_string()), mask: vec![0b00000111,0b11000000], seg_type: SegType::Offset, values: None},
 						// Base register: Mask: 0000 0000 0011 1000 Values: Any
 						OperationSeg { name: Some("Rb".to_string()), mask: vec![0,0b00111000], seg_type: SegType::Source, values: None},
 						// Destination register: Mask: 0000 0000 0000 0111 Values: Any
@@ -723,11 +911,11 @@ impl Default for ProcessorDefinition {
 						// Main: Mask: 1111 0000 0000 0000 Values: [0]: 1001 
 						OperationSeg { name: None, mask: vec![0b11110000,0], seg_type: SegType::Main, values: Some(vec![vec![0b10010000,0]])},
 						// Load/Store flag: Mask: 0000 1000 0000 0000 Values: all [Store]: 0, [Load]: 1
-						OperationSeg { name: Some("L".to_string()), mask: vec![0b00000100,0], seg_type: SegType::Flag, values: None},
+						OperationSeg { name: Some("L".to_string()), mask: vec![0b00001000,0], seg_type: SegType::Flag, values: None},
 						// Destination register: Mask: 0000 0111 0000 0000 Values: Any 
 						OperationSeg { name: Some("Rd".to_string()), mask: vec![0b00000111,0], seg_type: SegType::Destination, values: None},
 						// Immediate value: Mask: 0000 0000 1111 1111 Values: Any
-						OperationSeg { name: Some("Word8".to_string()), mask: vec![0,0b11111111], seg_type: SegType::Immediate, values: None},
+						OperationSeg { name: Some("Word8".to_string()), mask: vec![0,0b11111111], seg_type: SegType::Offset, values: None},
 					]
 				},
 				Format {
@@ -736,8 +924,8 @@ impl Default for ProcessorDefinition {
 					segments: vec![
 						// Main: Mask: 1111 0000 0000 0000 Values: [0]: 1010 
 						OperationSeg { name: None, mask: vec![0b11110000,0], seg_type: SegType::Main, values: Some(vec![vec![0b10100000,0]])},
-						// Source flag: Mask: 0000 1000 0000 0000 Values: all [Store]: 0, [Load]: 1
-						OperationSeg { name: Some("SP".to_string()), mask: vec![0b00000100,0], seg_type: SegType::Flag, values: None},
+						// Source flag: Mask: 0000 1000 0000 0000 Values: all [PC]: 0, [SP]: 1
+						OperationSeg { name: Some("SP".to_string()), mask: vec![0b00001000,0], seg_type: SegType::Flag, values: None},
 						// Destination register: Mask: 0000 0111 0000 0000 Values: Any 
 						OperationSeg { name: Some("Rd".to_string()), mask: vec![0b00000111,0], seg_type: SegType::Destination, values: None},
 						// 8-bit unsigned constant: 0000 0000 1111 1111 Values: Any
@@ -767,7 +955,7 @@ impl Default for ProcessorDefinition {
 						// PC/LR flag: Mask: 0000 0001 0000 0000 Values: all [do not store]: 0, [store]: 1
 						OperationSeg { name: Some("R".to_string()), mask: vec![0b00000001,0], seg_type: SegType::Flag, values: None},
 						// register list value: 0000 0000 1111 1111 Values: Any
-						OperationSeg { name: Some("Rlist".to_string()), mask: vec![0,0b11111111], seg_type: SegType::Immediate, values: None},
+						OperationSeg { name: Some("Rlist".to_string()), mask: vec![0,0b11111111], seg_type: SegType::RegisterList, values: None},
 					]
 				},
 				Format {
@@ -781,7 +969,7 @@ impl Default for ProcessorDefinition {
 						// Base register: Mask: 0000 0111 0000 0000 Values: any
 						OperationSeg { name: Some("Rb".to_string()), mask: vec![0b00000111,0], seg_type: SegType::Source, values: None},
 						// register list value: 0000 0000 1111 1111 Values: Any
-						OperationSeg { name: Some("Rlist".to_string()), mask: vec![0,0b11111111], seg_type: SegType::Immediate, values: None},
+						OperationSeg { name: Some("Rlist".to_string()), mask: vec![0,0b11111111], seg_type: SegType::RegisterList, values: None},
 						]
 				},
 				Format {
@@ -793,7 +981,7 @@ impl Default for ProcessorDefinition {
 						// Condition: Mask: 0000 1111 0000 0000 Values: most, not 1110 or 1111 // ToDO fix values
 						OperationSeg { name: Some("Cond".to_string()), mask: vec![0b00001111,0], seg_type: SegType::Condition, values: Some(vec![vec![0, 0],vec![0b00000001, 0],vec![0b00000010, 0],vec![0b00000011, 0],vec![0b00000100, 0],vec![0b00000101, 0],vec![0b00000110, 0],vec![0b00000111, 0],vec![0b00001000, 0],vec![0b00001001, 0],vec![0b00001010, 0],vec![0b00001011, 0],vec![0b00001100, 0],vec![0b00001101, 0],])},
 						// 8-bit signed immediate: Mask: 0000 0000 1111 1111 Values: Any
-						OperationSeg { name: Some("SOffset8".to_string()), mask: vec![0,0b11111111], seg_type: SegType::Immediate, values: None},
+						OperationSeg { name: Some("SOffset8".to_string()), mask: vec![0,0b11111111], seg_type: SegType::Offset, values: None},
 						]
 				},
 				Format {
@@ -813,7 +1001,7 @@ impl Default for ProcessorDefinition {
 						// Main: Mask: 1111 1000 0000 0000 Values: [0]: 1110 
 						OperationSeg { name: None, mask: vec![0b11111000,0], seg_type: SegType::Main, values: Some(vec![vec![0b11100000,0]])},
 						// Immediate value: Mask: 0000 0111 1111 1111 Values: Any
-						OperationSeg { name: Some("Offset11".to_string()), mask: vec![0b00000111,0b11111111], seg_type: SegType::Immediate, values: None},
+						OperationSeg { name: Some("Offset11".to_string()), mask: vec![0b00000111,0b11111111], seg_type: SegType::Offset, values: None},
 						]
 				},
 				Format {
@@ -821,11 +1009,11 @@ impl Default for ProcessorDefinition {
 					name: "long branch with link".to_string(),
 					segments: vec![
 						// Main: Mask: 1111 0000 0000 0000 Values: [0]: 1111 
-						OperationSeg { name: None, mask: vec![0b11110000,0], seg_type: SegType::Main, values: Some(vec![vec![0b11100000,0]])},
+						OperationSeg { name: None, mask: vec![0b11110000,0], seg_type: SegType::Main, values: Some(vec![vec![0b11110000,0]])},
 						// Low/High offset flag: Mask: 0000 1000 0000 0000 Values: all [high]: 0, [low]: 1
 						OperationSeg { name: Some("H".to_string()), mask: vec![0b00001000,0], seg_type: SegType::Flag, values: None},
 						// Long branch and link offset high/low: Mask: 0000 0111 1111 1111 Values: Any
-						OperationSeg { name: Some("Offset".to_string()), mask: vec![0b00000111,0b11111111], seg_type: SegType::Immediate, values: None},
+						OperationSeg { name: Some("Offset".to_string()), mask: vec![0b00000111,0b11111111], seg_type: SegType::Offset, values: None},
 						]
 				},
 			]
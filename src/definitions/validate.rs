@@ -0,0 +1,249 @@
+// Checks that a hand written definition is consistent before it is used
+
+use regex::Regex;
+
+use super::{language::LanguageDefinition, processor::{Format, ProcessorDefinition, SegType}};
+
+/// Every (mask, value) pair an instruction of the format can have in its fixed bits.
+/// The Main value and each segment with a values list constrain the instruction.
+fn patterns(format: &Format) -> Vec<(Vec<u8>, Vec<u8>)> {
+	let size = format.size();
+	let mut patterns = vec![(vec![0u8; size], vec![0u8; size])];
+	for seg in &format.segments {
+		let Some(values) = &seg.values else { continue };
+		let values: Vec<&Vec<u8>> = if seg.seg_type == SegType::Main { values.iter().take(1).collect() } else { values.iter().collect() };
+		let mut next = Vec::new();
+		for (mask, value) in &patterns {
+			for v in &values {
+				let mut mask = mask.clone();
+				let mut value = value.clone();
+				for i in 0..seg.mask.len().min(size) {
+					mask[i] |= seg.mask[i];
+					value[i] |= v.get(i).copied().unwrap_or(0) & seg.mask[i];
+				}
+				next.push((mask, value));
+			}
+		}
+		patterns = next;
+	}
+	patterns
+}
+
+impl ProcessorDefinition {
+	/// Check that every format covers each bit exactly once, that every field fits in a 32 bit value
+	/// and that no instruction can belong to two formats
+	pub fn validate(&self) -> Result<(), Vec<String>> {
+		let mut problems = Vec::new();
+
+		// The emulator keeps SP, LR and PC in registers 13 to 15
+		if self.num_register < 16 {
+			problems.push(format!("the processor has {} registers, it needs at least 16 for SP, LR and PC", self.num_register));
+		}
+
+		for (i, format) in self.formats.iter().enumerate() {
+			if self.formats[..i].iter().any(|f| f.id == format.id) {
+				problems.push(format!("format {} is defined more than once", format.id));
+			}
+
+			let size = format.size();
+			let mut covered = vec![0u8; size];
+			for seg in &format.segments {
+				for (b, m) in seg.mask.iter().enumerate() {
+					if covered[b] & m != 0 {
+						problems.push(format!("format {}: {} overlaps another segment in byte {} ({:08b})",
+							format.id, seg.name.clone().unwrap_or(format!("{:?}", seg.seg_type)), b, covered[b] & m));
+					}
+					covered[b] |= m;
+				}
+				if seg.values.is_none() && seg.width() > 32 {
+					problems.push(format!("format {}: {} is {} bits wide, a field can have at most 32",
+						format.id, seg.name.clone().unwrap_or(format!("{:?}", seg.seg_type)), seg.width()));
+				}
+				if let Some(values) = &seg.values {
+					for value in values {
+						if value.iter().zip(&seg.mask).any(|(v, m)| v & !m != 0) {
+							problems.push(format!("format {}: a value of {} sets bits outside its mask",
+								format.id, seg.name.clone().unwrap_or(format!("{:?}", seg.seg_type))));
+						}
+					}
+				}
+			}
+			if covered.iter().any(|c| *c != 0xff) {
+				let missing: Vec<String> = covered.iter().map(|c| format!("{:08b}", !c)).collect();
+				problems.push(format!("format {}: bits {} are not covered by any segment", format.id, missing.join(" ")));
+			}
+			if !format.segments.iter().any(|s| s.seg_type == SegType::Main && s.values.is_some()) {
+				problems.push(format!("format {} has no Main segment with a value", format.id));
+			}
+		}
+
+		for (i, a) in self.formats.iter().enumerate() {
+			let a_patterns = patterns(a);
+			for b in self.formats[i + 1..].iter().filter(|b| b.size() == a.size()) {
+				let overlap = a_patterns.iter().any(|(am, av)| patterns(b).iter().any(|(bm, bv)| {
+					am.iter().zip(av).zip(bm.iter().zip(bv)).all(|((am, av), (bm, bv))| (av ^ bv) & am & bm == 0)
+				}));
+				if overlap {
+					problems.push(format!("formats {} and {} can match the same instruction", a.id, b.id));
+				}
+			}
+		}
+
+		if problems.is_empty() { Ok(()) } else { Err(problems) }
+	}
+}
+
+impl LanguageDefinition {
+	/// Check the processor, then that every command points at a real format,
+	/// has a segment for each of its operands and captures every operand it names
+	pub fn validate(&self) -> Result<(), Vec<String>> {
+		let mut problems = self.processor_def.validate().err().unwrap_or_default();
+
+		for re in &self.regex_list {
+			if let Err(e) = Regex::new(re) {
+				problems.push(format!("line regex {} does not compile: {}", re, e));
+			}
+		}
+
+		for (name, versions) in &self.commands {
+			for (v, command) in versions.iter().enumerate() {
+				let place = format!("{} version {}", name, v + 1);
+				let format = self.processor_def.get_format(command.format_index);
+				if format.is_none() {
+					problems.push(format!("{}: format {} does not exist", place, command.format_index));
+				}
+
+				if let Some(format) = format {
+					if command.split > 1 && !format.segments.iter().any(|s| s.seg_type == SegType::Flag && s.values.is_none()) {
+						problems.push(format!("{}: split over {} instructions but format {} has no Flag to number them", place, command.split, format.id));
+					}
+				}
+
+				let regex = match Regex::new(&command.regex) {
+					Ok(regex) => Some(regex),
+					Err(e) => {
+						problems.push(format!("{}: regex does not compile: {}", place, e));
+						None
+					}
+				};
+
+				for (i, seg) in command.segments.iter().enumerate() {
+					if seg.0 == SegType::Main {
+						problems.push(format!("{}: the Main segment can not be an operand", place));
+						continue;
+					}
+
+					let nth = command.segments[..i].iter().filter(|s| s.0 == seg.0).count();
+					// A literal is encoded as the distance to its pool entry
+					let place_type = if seg.0 == SegType::Literal { SegType::Immediate } else { seg.0 };
+					if let Some(format) = format {
+						if format.segments.iter().filter(|s| s.seg_type == place_type).nth(nth).is_none() {
+							problems.push(format!("{}: format {} has no place for {:?} operand {}", place, format.id, seg.0, seg.1));
+						}
+					}
+
+					match seg.0 {
+						SegType::Op | SegType::Flag | SegType::Condition => {
+							if seg.1.parse::<u32>().is_err() {
+								problems.push(format!("{}: {:?} value {} is not a number", place, seg.0, seg.1));
+							}
+						}
+						_ => {
+							if let Some(regex) = &regex {
+								if !regex.capture_names().any(|n| n == Some(seg.1.as_str())) {
+									problems.push(format!("{}: capture group {} is not in the regex", place, seg.1));
+								}
+							}
+						}
+					}
+				}
+			}
+		}
+
+		for (name, alias) in &self.aliases {
+			let regex = match Regex::new(&alias.regex) {
+				Ok(regex) => regex,
+				Err(e) => {
+					problems.push(format!("alias {}: regex does not compile: {}", name, e));
+					continue;
+				}
+			};
+			if alias.expansion.is_empty() {
+				problems.push(format!("alias {}: expands to nothing", name));
+			}
+			for line in &alias.expansion {
+				for capture in line.split("${").skip(1).filter_map(|rest| rest.split_once('}')).map(|c| c.0) {
+					if !regex.capture_names().any(|n| n == Some(capture)) {
+						problems.push(format!("alias {}: capture group {} is not in the regex", name, capture));
+					}
+				}
+			}
+		}
+
+		if problems.is_empty() { Ok(()) } else { Err(problems) }
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::definitions::processor::OperationSeg;
+
+	#[test]
+	fn default_is_valid() {
+		let def: LanguageDefinition = Default::default();
+		assert_eq!(def.validate(), Ok(()));
+	}
+
+	#[test]
+	fn broken_formats() {
+		let mut def: ProcessorDefinition = Default::default();
+		// Make format 8 look like format 7
+		def.formats[7].segments[0].values = Some(vec![vec![0b01010000, 0]]);
+		// Widen the Rd mask of format 1 over Rs
+		def.formats[0].segments[4].mask = vec![0, 0b00001111];
+		// Drop the Rd segment of format 3
+		def.formats[2].segments.pop();
+
+		let problems = def.validate().unwrap_err();
+		assert!(problems.contains(&"formats 7 and 8 can match the same instruction".to_string()));
+		assert!(problems.iter().any(|p| p.starts_with("format 1: Rd overlaps")));
+		assert!(problems.iter().any(|p| p.starts_with("format 3: bits")));
+		assert_eq!(problems.len(), 3);
+
+		// A 40 bit immediate in a made up 48 bit format
+		let mut def: ProcessorDefinition = Default::default();
+		def.formats.push(Format { id: 30, name: "wide".to_string(), segments: vec![
+			OperationSeg { name: None, mask: vec![0xff, 0, 0, 0, 0, 0], seg_type: SegType::Main, values: Some(vec![vec![0xff, 0, 0, 0, 0, 0]]) },
+			OperationSeg { name: Some("Imm".to_string()), mask: vec![0, 0xff, 0xff, 0xff, 0xff, 0xff], seg_type: SegType::Immediate, values: None },
+		]});
+		assert_eq!(def.validate(), Err(vec!["format 30: Imm is 40 bits wide, a field can have at most 32".to_string()]));
+
+		let def = ProcessorDefinition { num_register: 8, ..Default::default() };
+		assert_eq!(def.validate(), Err(vec!["the processor has 8 registers, it needs at least 16 for SP, LR and PC".to_string()]));
+	}
+
+	#[test]
+	fn broken_commands() {
+		let mut def: LanguageDefinition = Default::default();
+		def.commands[0].1[0].format_index = 40;
+		def.commands[1].1[0].segments.push((SegType::Source, "base".to_string()));
+		def.commands[1].1[1].segments[0].1 = "one".to_string();
+		def.processor_def.formats.push(Format { id: 20, name: "empty".to_string(), segments: vec![
+			OperationSeg { name: None, mask: vec![0xff, 0xff], seg_type: SegType::Main, values: Some(vec![vec![0xdf, 0x00]]) },
+		]});
+		def.aliases[1].1.expansion[0] = "add ${destination}, ${src}, #0".to_string();
+		def.aliases[2].1.expansion.clear();
+
+		let problems = def.validate().unwrap_err();
+		assert_eq!(problems, vec![
+			"formats 17 and 20 can match the same instruction".to_string(),
+			"ADC version 1: format 40 does not exist".to_string(),
+			"ADD version 1: format 2 has no place for Source operand base".to_string(),
+			"ADD version 1: capture group base is not in the regex".to_string(),
+			"ADD version 2: Flag value one is not a number".to_string(),
+			"alias MOV: capture group src is not in the regex".to_string(),
+			"alias ADR: expands to nothing".to_string(),
+		]);
+	}
+}
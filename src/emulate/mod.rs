@@ -1,5 +1,4 @@
-/// Functions to run compiled code in an emulator
-/// 
+//! Functions to run compiled code in an emulator
 
 
 
@@ -0,0 +1,289 @@
+//! Errors reported while loading, parsing and compiling assembly code
+
+use std::{fmt, ops::Range};
+
+use crate::definitions::load::DefinitionError;
+
+pub mod prelude {
+	pub use super::{Diagnostic, ErrorCode, KgemuError, Severity};
+}
+
+/// Code shown with a diagnostic so each kind of problem can be looked up
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+	/// The line matched none of the line regexes
+	BadLine = 1,
+	/// No command has this name
+	UnknownMnemonic = 2,
+	/// The command exists but no version of it takes these operands
+	InvalidOperands = 3,
+	/// The value is too big for the field it is encoded into
+	OperandTooWide = 4,
+	/// The operand is not a register or number
+	BadOperand = 5,
+	/// The value is not a multiple of the field's scale
+	Misaligned = 6,
+	/// The language definition is broken
+	Definition = 7,
+	/// A label is used but never defined
+	UndefinedLabel = 8,
+	/// A label is defined twice
+	DuplicateLabel = 9,
+	/// A branch target is too far away to reach
+	OutOfRange = 10,
+	/// No directive has this name
+	UnknownDirective = 11,
+	/// Instructions or data that is not zero in a section that starts zeroed
+	DataInUninitialized = 12,
+	/// A constant expression does not fit in 32 bits
+	Overflow = 13,
+	/// A macro is defined or invoked wrongly
+	Macro = 14,
+	/// Macros invoke each other too deep, most likely without end
+	MacroRecursion = 15,
+	/// A .include file can not be found or includes itself
+	Include = 16,
+	/// A conditional block is not opened or closed right, or its condition can not be evaluated
+	Conditional = 17,
+	/// Raised by the code itself with .error
+	User = 18,
+	/// An address a linker has to fill in is used where a relocation can not describe it
+	Relocation = 19,
+	/// A linker script is written wrongly or a section is left out of it
+	LinkerScript = 20,
+	/// The program needs more memory than a region of the linker script or the device has
+	DoesNotFit = 21,
+}
+
+impl fmt::Display for ErrorCode {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "E{:04}", *self as u32)
+	}
+}
+
+/// Whether a diagnostic stops the code from being compiled
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+	#[default]
+	Error,
+	/// Reported but the code is still compiled
+	Warning,
+}
+
+/// A problem found at a place in a source file
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+	pub file_name: String,
+	/// Index of the line in the file, starting at 0, or -1 when the problem is with the whole file
+	pub line: i32,
+	/// Columns of the line the problem covers, starting at 0
+	pub span: Range<usize>,
+	pub message: String,
+	pub code: Option<ErrorCode>,
+	/// Suggestions on how to fix the problem
+	pub hints: Vec<String>,
+	/// Where the line came from, like the macro it was expanded from
+	pub notes: Vec<String>,
+	pub severity: Severity,
+}
+
+impl Diagnostic {
+	pub fn new(file_name: &str, line: i32, span: Range<usize>, message: String) -> Self {
+		Diagnostic { file_name: file_name.to_string(), line, span, message, ..Default::default() }
+	}
+
+	pub fn with_code(mut self, code: ErrorCode) -> Self {
+		self.code = Some(code);
+		self
+	}
+
+	pub fn with_hint(mut self, hint: String) -> Self {
+		self.hints.push(hint);
+		self
+	}
+
+	pub fn with_note(mut self, note: String) -> Self {
+		self.notes.push(note);
+		self
+	}
+
+	pub fn as_warning(mut self) -> Self {
+		self.severity = Severity::Warning;
+		self
+	}
+
+	/// Render like a compiler error, with the source line and a caret under the span.
+	/// color adds ansi escape codes for a terminal
+	pub fn render(&self, source_line: Option<&str>, color: bool) -> String {
+		let paint = |text: &str, style: &str| if color { format!("\x1b[{}m{}\x1b[0m", style, text) } else { text.to_string() };
+		let number = (self.line + 1).to_string();
+		let gutter = " ".repeat(number.len());
+		let bar = paint("|", "1;34");
+
+		let (title, style) = match self.severity {
+			Severity::Error => ("error", "1;31"),
+			Severity::Warning => ("warning", "1;33"),
+		};
+		let title = match self.code {
+			Some(code) => format!("{}[{}]", title, code),
+			None => title.to_string(),
+		};
+		let mut text = format!("{}{}\n", paint(&title, style), paint(&format!(": {}", self.message), "1"));
+		let file_name = if self.file_name.is_empty() { "<source>" } else { &self.file_name };
+		if self.line < 0 {
+			text.push_str(&format!("{}{} {}\n", gutter, paint("-->", "1;34"), file_name));
+		}
+		else {
+			text.push_str(&format!("{}{} {}:{}:{}\n", gutter, paint("-->", "1;34"), file_name, self.line + 1, self.span.start + 1));
+		}
+
+		if let Some(source_line) = source_line {
+			// Copy tabs from the source so the carets line up however wide a tab is shown.
+			// The span is in bytes, there is one space or caret for each character it starts before or covers
+			let padding: String = source_line.char_indices().take_while(|(i, _)| *i < self.span.start).map(|(_, c)| if c == '\t' { '\t' } else { ' ' }).collect();
+			let covered = source_line.char_indices().filter(|(i, _)| self.span.contains(i)).count();
+			let past_end = self.span.end.saturating_sub(source_line.len().max(self.span.start));
+			let carets = "^".repeat((covered + past_end).max(1));
+			text.push_str(&format!("{} {}\n", gutter, bar));
+			text.push_str(&format!("{} {} {}\n", paint(&number, "1;34"), bar, source_line));
+			text.push_str(&format!("{} {} {}{}\n", gutter, bar, padding, paint(&carets, style)));
+		}
+		for note in &self.notes {
+			text.push_str(&format!("{} {} {}\n", gutter, paint("= note:", "1"), note));
+		}
+		for hint in &self.hints {
+			text.push_str(&format!("{} {} {}\n", gutter, paint("= help:", "1"), hint));
+		}
+		text
+	}
+}
+
+impl fmt::Display for Diagnostic {
+	/// file:line:column: message, with line and column counted from 1
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let file_name = if self.file_name.is_empty() { "<source>" } else { &self.file_name };
+		if self.line < 0 {
+			write!(f, "{}: ", file_name)?;
+		}
+		else {
+			write!(f, "{}:{}:{}: ", file_name, self.line + 1, self.span.start + 1)?;
+		}
+		if self.severity == Severity::Warning {
+			write!(f, "warning: ")?;
+		}
+		if let Some(code) = self.code {
+			write!(f, "{}: ", code)?;
+		}
+		write!(f, "{}", self.message)
+	}
+}
+
+#[derive(Debug)]
+pub enum KgemuError {
+	/// A source file could not be read or an output file written
+	Io { file_name: String, error: std::io::Error },
+	/// A definition file could not be loaded
+	Definition(DefinitionError),
+	/// Everything wrong with the source, in the order it was found
+	Diagnostics(Vec<Diagnostic>),
+	/// A rom image is not in a format that can be read or written, or does not fit in rom
+	Image { file_name: String, message: String },
+}
+
+impl KgemuError {
+	/// The diagnostics of the error, empty if it is not about the source
+	pub fn diagnostics(&self) -> &[Diagnostic] {
+		match self {
+			KgemuError::Diagnostics(diagnostics) => diagnostics,
+			_ => &[],
+		}
+	}
+
+	/// Render every diagnostic like a compiler error, source_line finds the text of a diagnostic's line
+	pub fn render<'a>(&self, source_line: impl Fn(&Diagnostic) -> Option<&'a str>, color: bool) -> String {
+		let paint = |text: &str| if color { format!("\x1b[1;31merror\x1b[0m\x1b[1m: {}\x1b[0m\n", text) } else { format!("error: {}\n", text) };
+		match self {
+			KgemuError::Diagnostics(diagnostics) => {
+				let mut text: Vec<String> = diagnostics.iter().map(|d| d.render(source_line(d), color)).collect();
+				if diagnostics.len() > 1 {
+					text.push(paint(&format!("aborting due to {} previous errors", diagnostics.len())));
+				}
+				text.join("\n")
+			}
+			_ => paint(&self.to_string()),
+		}
+	}
+}
+
+impl fmt::Display for KgemuError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			KgemuError::Io { file_name, error } => write!(f, "{}: {}", file_name, error),
+			KgemuError::Definition(error) => write!(f, "{}", error),
+			KgemuError::Image { file_name, message } => write!(f, "{}: {}", file_name, message),
+			KgemuError::Diagnostics(diagnostics) => {
+				let lines: Vec<String> = diagnostics.iter().map(|d| d.to_string()).collect();
+				write!(f, "{}", lines.join("\n"))
+			}
+		}
+	}
+}
+
+impl std::error::Error for KgemuError {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self {
+			KgemuError::Io { error, .. } => Some(error),
+			KgemuError::Definition(error) => Some(error),
+			KgemuError::Diagnostics(_) | KgemuError::Image { .. } => None,
+		}
+	}
+}
+
+impl From<DefinitionError> for KgemuError {
+	fn from(error: DefinitionError) -> Self {
+		KgemuError::Definition(error)
+	}
+}
+
+impl From<Diagnostic> for KgemuError {
+	fn from(diagnostic: Diagnostic) -> Self {
+		KgemuError::Diagnostics(vec![diagnostic])
+	}
+}
+
+impl From<Vec<Diagnostic>> for KgemuError {
+	fn from(diagnostics: Vec<Diagnostic>) -> Self {
+		KgemuError::Diagnostics(diagnostics)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn render_plain() {
+		let diagnostic = Diagnostic::new("test.s", 9, 13..17, "immediate #222 exceeds 5-bit Offset5 field".to_string())
+			.with_code(ErrorCode::OperandTooWide)
+			.with_hint("the largest immediate LSL can take here is 31".to_string());
+		assert_eq!(diagnostic.render(Some("\tlsl r1, r3, #222"), false), concat!(
+			"error[E0004]: immediate #222 exceeds 5-bit Offset5 field\n",
+			"  --> test.s:10:14\n",
+			"   |\n",
+			"10 | \tlsl r1, r3, #222\n",
+			"   | \t            ^^^^\n",
+			"   = help: the largest immediate LSL can take here is 31\n",
+		));
+
+		// Text before the span that is not ASCII takes one column for each character, not each byte
+		let other = Diagnostic::new("test.s", 0, 12..14, "unexpected `\u{00e9}`".to_string());
+		assert!(other.render(Some("\tmov r0, #\u{00e9}\u{00e9}"), false).ends_with("\n  | \t          ^\n"));
+		let other = Diagnostic::new("test.s", 0, 10..14, "unexpected `\u{00e9}\u{00e9}`".to_string());
+		assert!(other.render(Some("\tmov r0, #\u{00e9}\u{00e9}"), false).ends_with("\n  | \t         ^^\n"));
+
+		let error = KgemuError::Diagnostics(vec![diagnostic.clone(), diagnostic]);
+		let text = error.render(|_| None, false);
+		assert!(text.ends_with("error: aborting due to 2 previous errors\n"));
+		assert!(!text.contains('|'));
+	}
+}
@@ -1,15 +1,19 @@
-/// kgemu is a crate for compileing and emulating assembly code
+//! kgemu is a crate for compileing and emulating assembly code
 
 
 pub mod compile;
 pub mod emulate;
 pub mod definitions;
 pub mod virtual_processor;
+pub mod error;
+pub mod link;
 
 pub mod prelude {
     pub use crate::compile::prelude::*;
     pub use crate::emulate::prelude::*;
     pub use crate::definitions::prelude::*;
+    pub use crate::error::prelude::*;
+    pub use crate::link::prelude::*;
 }
 
 
@@ -0,0 +1,704 @@
+//! Mod to link relocatable objects into one program, placing their sections by a linker script and filling in their relocations
+
+use std::fs;
+
+use script::{Assignment, LinkerScript, MemoryRegion, OutputCommand, SectionCommand};
+
+use crate::{compile::{elf::{ElfFile, ElfSection, ElfSymbol, RelocationKind, RelocationTarget}, expression, output::{self, OutputFormat}, parse_code, rom_image, Section}, prelude::{DefinitionError, DeviceDefinition, Diagnostic, ErrorCode, KgemuError}};
+
+pub mod script;
+
+pub mod prelude {
+	pub use super::{Linker, Placement};
+	pub use super::script::LinkerScript;
+}
+
+/// Where one input section of an object ended up
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Placement {
+	/// Object file the section is from
+	pub file: String,
+	pub section: String,
+	/// Output section it is part of
+	pub output: String,
+	pub address: u32,
+	pub size: u32,
+}
+
+#[derive(Default)]
+pub struct Linker {
+	device_def: DeviceDefinition,
+	/// Layout to follow, the device's memory map when None
+	script: Option<LinkerScript>,
+	objects: Vec<(String, ElfFile)>,
+	sections: Vec<ElfSection>,
+	placements: Vec<Placement>,
+	symbols: Vec<ElfSymbol>,
+	/// File each symbol comes from, the linker script's name for its assignments
+	symbol_files: Vec<String>,
+	/// Memory regions of the layout and how many bytes of each are used
+	regions: Vec<(MemoryRegion, u32)>,
+	entry: u32,
+	/// Address the rom image starts at, the origin of the region the first section stored in memory is in
+	image_start: u32,
+	linked_code: Vec<u8>,
+}
+
+impl Linker {
+	pub fn set_device(&mut self, device: DeviceDefinition) {
+		self.device_def = device;
+	}
+	/// Replace the memory map with one loaded from a json file, it is the layout when there is no linker script
+	pub fn load_device_from_file(&mut self, file_name: &str) -> Result<(), DefinitionError> {
+		self.device_def = DeviceDefinition::from_file(file_name)?;
+		Ok(())
+	}
+	pub fn set_script(&mut self, script: LinkerScript) {
+		self.script = Some(script);
+	}
+	pub fn load_script_from_file(&mut self, file_name: &str) -> Result<(), KgemuError> {
+		self.script = Some(LinkerScript::from_file(file_name)?);
+		Ok(())
+	}
+	/// Add a relocatable object, file_name is how errors and the linker script name it
+	pub fn add_object(&mut self, file_name: &str, object: ElfFile) -> Result<(), KgemuError> {
+		if !object.relocatable {
+			return Err(KgemuError::Image { file_name: file_name.to_string(), message: "only relocatable objects can be linked, not an executable".to_string() });
+		}
+		for relocation in &object.relocations {
+			let Some(section) = object.sections.iter().find(|s| s.section.name == relocation.section) else { continue };
+			if relocation.offset >= section.section.size {
+				return Err(KgemuError::Image {
+					file_name: file_name.to_string(),
+					message: format!("a relocation at {}+0x{:x} is past the end of the section, which is {} bytes", relocation.section, relocation.offset, section.section.size),
+				});
+			}
+		}
+		self.objects.push((file_name.to_string(), object));
+		Ok(())
+	}
+	/// Read a relocatable ELF object from a file and add it
+	pub fn add_object_file(&mut self, file_name: &str) -> Result<(), KgemuError> {
+		let data = fs::read(file_name).map_err(|error| KgemuError::Io { file_name: file_name.to_string(), error })?;
+		let object = ElfFile::parse(&data).map_err(|message| KgemuError::Image { file_name: file_name.to_string(), message })?;
+		self.add_object(file_name, object)
+	}
+
+	/// Place every section of every object, give each global symbol its address and fill in every relocation.
+	/// Every duplicate or undefined symbol and every relocation that can not be filled in is reported, not just the first
+	pub fn link(&mut self) -> Result<(), KgemuError> {
+		let names: Vec<String> = self.objects.iter().flat_map(|(_, o)| o.sections.iter().map(|s| s.section.name.clone())).collect();
+		let script = self.script.clone().unwrap_or_else(|| LinkerScript::from_device(&self.device_def, &names));
+		self.linked_code.clear();
+		let (placed, marks) = self.place(&script)?;
+
+		let mut diagnostics = self.check_fit();
+		let globals = self.resolve_symbols(&script, &placed, &marks, &mut diagnostics);
+		self.relocate(&placed, &globals, &mut diagnostics);
+
+		let entry = script.entry.clone().unwrap_or("_start".to_string());
+		self.entry = match globals.iter().find(|g| g.0 == entry) {
+			Some(global) => global.1,
+			None if script.entry.is_some() => {
+				diagnostics.push(Diagnostic::new(&script.file_name, -1, 0..0, format!("the entry symbol `{}` is not defined", entry)).with_code(ErrorCode::UndefinedLabel));
+				0
+			}
+			None => self.sections.iter().find(|s| s.section.load_address.is_some()).map_or(0, |s| s.section.address),
+		};
+		if !diagnostics.is_empty() {
+			self.sections.clear();
+			return Err(diagnostics.into());
+		}
+
+		let first = self.sections.iter().filter_map(|s| s.section.load_address).min().unwrap_or(0);
+		self.image_start = script.memory.iter().find(|m| (m.origin as u64..m.origin as u64 + m.length as u64).contains(&(first as u64))).map_or(first, |m| m.origin);
+		let sections: Vec<Section> = self.get_sections();
+		self.linked_code = rom_image(&sections, self.image_start);
+		Ok(())
+	}
+
+	/// Lay out the output sections of the script in their regions, each input section in the order the script's patterns take them.
+	/// Returns the index in placements of each section of each object, and the address each script assignment is written at
+	#[allow(clippy::type_complexity)]
+	fn place(&mut self, script: &LinkerScript) -> Result<(Vec<Vec<Option<usize>>>, Vec<(Assignment, u32)>), Vec<Diagnostic>> {
+		self.sections.clear();
+		self.placements.clear();
+		let mut placed: Vec<Vec<Option<usize>>> = self.objects.iter().map(|(_, o)| vec![None; o.sections.len()]).collect();
+		let mut marks = Vec::new();
+		// The next free address of each region, worked out past 32 bits so a layout that runs off the end of memory is caught
+		let mut next: Vec<u64> = script.memory.iter().map(|m| m.origin as u64).collect();
+		let align_up = |value: u64, align: u32| value.div_ceil(align.max(1) as u64) * align.max(1) as u64;
+		let past_end = |name: &str, address: u64, what: &str| vec![Diagnostic::new(&script.file_name, -1, 0..0, format!("section {} {} at 0x{:08x}, past the end of memory", name, what, address))
+			.with_code(ErrorCode::DoesNotFit)];
+		let region = |name: &str| script.memory.iter().position(|m| m.name == name).unwrap_or(0);
+		let mut current = 0;
+		let mut end = next.first().copied().unwrap_or(0) as u32;
+
+		for command in &script.sections {
+			let output = match command {
+				SectionCommand::Assign(assignment) => {
+					marks.push((assignment.clone(), end));
+					continue;
+				}
+				SectionCommand::Output(output) => output,
+			};
+			current = output.region.as_deref().map_or(current, region);
+			// Objects and their sections each pattern takes, in order, and where the assignments among them are
+			let mut inputs: Vec<Result<(usize, usize), &Assignment>> = Vec::new();
+			for content in &output.contents {
+				match content {
+					OutputCommand::Assign(assignment) => inputs.push(Err(assignment)),
+					OutputCommand::Input(pattern) => {
+						for (o, (file, object)) in self.objects.iter().enumerate() {
+							for (s, section) in object.sections.iter().enumerate() {
+								if placed[o][s].is_none() && !inputs.contains(&Ok((o, s))) && pattern.matches(file, &section.section.name) {
+									inputs.push(Ok((o, s)));
+								}
+							}
+						}
+					}
+				}
+			}
+			let sections: Vec<(usize, usize, &ElfSection)> = inputs.iter().filter_map(|i| i.ok()).map(|(o, s)| (o, s, &self.objects[o].1.sections[s])).collect();
+			let align = sections.iter().map(|s| s.2.section.align).fold(output.align, u32::max).max(1);
+			let start = align_up(next.get(current).copied().unwrap_or(0), align);
+			let zeroed = sections.iter().all(|s| s.2.section.load_address.is_none());
+			let mut size: u64 = 0;
+			let mut data = Vec::new();
+			let mut flags = 0;
+			for input in &inputs {
+				let (o, s) = match input {
+					Err(assignment) => {
+						marks.push(((*assignment).clone(), (start + size) as u32));
+						continue;
+					}
+					Ok(input) => *input,
+				};
+				let section = &self.objects[o].1.sections[s];
+				size = align_up(size, section.section.align);
+				placed[o][s] = Some(self.placements.len());
+				self.placements.push(Placement {
+					file: self.objects[o].0.clone(),
+					section: section.section.name.clone(),
+					output: output.name.clone(),
+					address: (start + size) as u32,
+					size: section.section.size,
+				});
+				if !zeroed {
+					data.resize(size as usize, 0);
+					data.extend(&section.section.data);
+				}
+				size += section.section.size as u64;
+				flags |= section.flags;
+			}
+			if start + size > 1 << 32 {
+				return Err(past_end(&output.name, start + size, "ends"));
+			}
+			if sections.is_empty() {
+				end = start as u32;
+				continue;
+			}
+			data.resize(if zeroed { 0 } else { size as usize }, 0);
+			if let Some(free) = next.get_mut(current) {
+				*free = start + size;
+			}
+			end = (start + size) as u32;
+			let load_address = match &output.load_region {
+				_ if zeroed => None,
+				Some(name) => {
+					let load = region(name);
+					let at = align_up(next[load], align);
+					if at + size > 1 << 32 {
+						return Err(past_end(&output.name, at + size, "is stored up to"));
+					}
+					next[load] = at + size;
+					Some(at as u32)
+				}
+				None => Some(start as u32),
+			};
+			self.sections.push(ElfSection { section: Section { name: output.name.clone(), address: start as u32, load_address, size: size as u32, data, align }, flags });
+		}
+
+		self.regions = script.memory.iter().zip(next).map(|(region, free)| (region.clone(), (free - region.origin as u64) as u32)).collect();
+
+		let mut diagnostics = Vec::new();
+		for (o, (file, object)) in self.objects.iter().enumerate() {
+			for (s, section) in object.sections.iter().enumerate().filter(|(s, _)| placed[o][*s].is_none()) {
+				diagnostics.push(Diagnostic::new(file, -1, 0..0, format!("section {} is not placed by the linker script", section.section.name))
+					.with_code(ErrorCode::LinkerScript)
+					.with_hint(format!("add `*({})` to an output section", object.sections[s].section.name)));
+			}
+		}
+		if diagnostics.is_empty() { Ok((placed, marks)) } else { Err(diagnostics) }
+	}
+
+	/// Bytes of rom and of ram the program uses. Rom holds every section that is stored,
+	/// ram every section that runs somewhere other than where it is stored or starts zeroed
+	fn usage(&self) -> (u32, u32) {
+		let rom = self.sections.iter().filter(|s| s.section.load_address.is_some()).map(|s| s.section.size).sum();
+		let ram = self.sections.iter().filter(|s| s.section.load_address != Some(s.section.address)).map(|s| s.section.size).sum();
+		(rom, ram)
+	}
+
+	/// Check the program fits in each region of the linker script, then in the rom and ram of the device it runs on
+	fn check_fit(&self) -> Vec<Diagnostic> {
+		let mut diagnostics = Vec::new();
+		let script = self.script.as_ref();
+		for (region, used) in self.regions.iter().filter(|(region, used)| *used > region.length && script.is_some()) {
+			let range = region.origin as u64..region.origin as u64 + *used as u64;
+			let inside: Vec<String> = self.sections.iter()
+				.filter(|s| range.contains(&(s.section.address as u64)) || s.section.load_address.is_some_and(|at| range.contains(&(at as u64))))
+				.map(|s| format!("{} ({} bytes)", s.section.name, s.section.size))
+				.collect();
+			diagnostics.push(Diagnostic::new(script.map_or("", |s| &s.file_name), -1, 0..0, format!("the program does not fit in {}, it needs {} bytes and {} holds {}", region.name, used, region.name, region.length))
+				.with_code(ErrorCode::DoesNotFit)
+				.with_hint(format!("{} holds {}", region.name, inside.join(", "))));
+		}
+		if !diagnostics.is_empty() {
+			return diagnostics;
+		}
+
+		let device = &self.device_def;
+		let (rom, ram) = self.usage();
+		let error = |message: String| Diagnostic::new(&device.name, -1, 0..0, message).with_code(ErrorCode::DoesNotFit);
+		for (memory, used, size) in [("rom", rom, device.rom_size), ("ram", ram, device.ram_size)].into_iter().filter(|m| m.1 > m.2) {
+			diagnostics.push(error(format!("the program does not fit in {}, it needs {} bytes and {} has {}", memory, used, device.name, size)));
+		}
+		if !diagnostics.is_empty() {
+			return diagnostics;
+		}
+		let inside = |start: u32, size: u32, address: u32, length: u32| address >= start && address as u64 + length as u64 <= start as u64 + size as u64;
+		for section in self.sections.iter().map(|s| &s.section) {
+			let (memory, start, size, address) = match section.load_address {
+				Some(at) if !inside(device.rom_start, device.rom_size, at, section.size) => ("stored", device.rom_start, device.rom_size, at),
+				at if at != Some(section.address) && !inside(device.ram_start, device.ram_size, section.address, section.size) => ("run", device.ram_start, device.ram_size, section.address),
+				_ => continue,
+			};
+			let region = if memory == "stored" { "rom" } else { "ram" };
+			diagnostics.push(error(format!("section {} is {} at 0x{:08x}, outside the {} of {} at 0x{:08x} to 0x{:08x}", section.name, memory, address, region, device.name, start, start as u64 + size as u64))
+				.with_hint("load a device whose memory map matches the linker script with --device".to_string()));
+		}
+		diagnostics
+	}
+
+	/// Address of a section of an object, 0 for an absolute symbol which has no section
+	fn section_address(&self, placed: &[Vec<Option<usize>>], object: usize, section: &str) -> Option<u32> {
+		if section.is_empty() {
+			return Some(0);
+		}
+		let index = self.objects[object].1.sections.iter().position(|s| s.section.name == section)?;
+		placed[object][index].map(|p| self.placements[p].address)
+	}
+
+	/// Give every symbol of every object its final address, then work out the symbols of the script.
+	/// Returns each global symbol with its address and the file that defines it
+	fn resolve_symbols(&mut self, script: &LinkerScript, placed: &[Vec<Option<usize>>], marks: &[(Assignment, u32)], diagnostics: &mut Vec<Diagnostic>) -> Vec<(String, u32, String)> {
+		let mut globals: Vec<(String, u32, String)> = Vec::new();
+		self.symbols.clear();
+		self.symbol_files.clear();
+		for (o, (file, object)) in self.objects.iter().enumerate() {
+			for symbol in object.symbols.iter().filter(|s| s.defined) {
+				let Some(base) = self.section_address(placed, o, &symbol.section) else { continue };
+				let output = self.placements.iter().find(|p| &p.file == file && p.section == symbol.section).map_or(String::new(), |p| p.output.clone());
+				let value = base.wrapping_add(symbol.value);
+				self.symbols.push(ElfSymbol { name: symbol.name.clone(), value, section: output, defined: true, global: symbol.global });
+				self.symbol_files.push(file.clone());
+				if !symbol.global {
+					continue;
+				}
+				match globals.iter().find(|g| g.0 == symbol.name) {
+					Some(first) => diagnostics.push(Diagnostic::new(file, -1, 0..0, format!("symbol `{}` is defined more than once", symbol.name))
+						.with_code(ErrorCode::DuplicateLabel)
+						.with_hint(format!("it is defined in {} and in {}", first.2, file))),
+					None => globals.push((symbol.name.clone(), value, file.clone())),
+				}
+			}
+		}
+		for (assignment, dot) in marks {
+			let lookup = |name: &str| match name {
+				"." => Some(*dot as i64),
+				_ => globals.iter().find(|g| g.0 == name).map(|g| g.1 as i64),
+			};
+			let error = |message: String, code: ErrorCode| Diagnostic::new(&script.file_name, assignment.line, assignment.span.clone(), message).with_code(code);
+			match expression::evaluate(&assignment.expression, &lookup) {
+				Err(e) => diagnostics.push(error(e.message, e.code)),
+				Ok(_) if globals.iter().any(|g| g.0 == assignment.name) => {
+					let first = globals.iter().find(|g| g.0 == assignment.name).map_or(String::new(), |g| g.2.clone());
+					diagnostics.push(error(format!("symbol `{}` is defined more than once", assignment.name), ErrorCode::DuplicateLabel)
+						.with_hint(format!("it is defined in {} and in the linker script", first)));
+				}
+				Ok(value) => {
+					globals.push((assignment.name.clone(), value as u32, "the linker script".to_string()));
+					self.symbols.push(ElfSymbol { name: assignment.name.clone(), value: value as u32, section: String::new(), defined: true, global: true });
+					self.symbol_files.push(script.file_name.clone());
+				}
+			}
+		}
+		globals
+	}
+
+	/// Fill in every relocation of every object in the output sections.
+	/// A symbol is looked for in the object that uses it first, then in the globals
+	fn relocate(&mut self, placed: &[Vec<Option<usize>>], globals: &[(String, u32, String)], diagnostics: &mut Vec<Diagnostic>) {
+		// Each symbol that is not defined and the files that use it
+		let mut undefined: Vec<(String, Vec<String>)> = Vec::new();
+		for (o, (file, object)) in self.objects.iter().enumerate() {
+			for relocation in &object.relocations {
+				// Relocations of sections that are not loaded, like debug information, are not needed
+				let Some(index) = object.sections.iter().position(|s| s.section.name == relocation.section) else { continue };
+				let Some(placement) = placed[o][index].map(|p| &self.placements[p]) else { continue };
+				let error = |message: String, code: ErrorCode| Diagnostic::new(file, -1, 0..0, format!("{} at {}+0x{:x}: {}", relocation.kind, relocation.section, relocation.offset, message)).with_code(code);
+				let target = match &relocation.target {
+					RelocationTarget::Section(name) => match self.section_address(placed, o, name) {
+						Some(address) => address,
+						None => {
+							diagnostics.push(error(format!("section {} is not loaded", name), ErrorCode::Relocation));
+							continue;
+						}
+					},
+					RelocationTarget::Symbol(name) => {
+						let local = object.symbols.iter().find(|s| &s.name == name && s.defined && !s.global)
+							.and_then(|s| self.section_address(placed, o, &s.section).map(|base| base.wrapping_add(s.value)));
+						match local.or(globals.iter().find(|g| &g.0 == name).map(|g| g.1)) {
+							Some(address) => address,
+							None => {
+								match undefined.iter_mut().find(|u| &u.0 == name) {
+									Some(used) if !used.1.contains(file) => used.1.push(file.clone()),
+									Some(_) => {}
+									None => undefined.push((name.clone(), vec![file.clone()])),
+								}
+								continue;
+							}
+						}
+					}
+				};
+				let place = placement.address.wrapping_add(relocation.offset);
+				let Some(output) = self.sections.iter_mut().find(|s| s.section.name == placement.output) else { continue };
+				if output.section.load_address.is_none() {
+					diagnostics.push(error(format!("{} starts zeroed, it can not hold an address", output.section.name), ErrorCode::DataInUninitialized));
+					continue;
+				}
+				let Some(bytes) = place.checked_sub(output.section.address).and_then(|at| output.section.data.get_mut(at as usize..)) else {
+					diagnostics.push(error(format!("the place is past the end of {}", output.section.name), ErrorCode::Relocation));
+					continue;
+				};
+				if let Err(message) = apply(relocation.kind, bytes, object.little_endian, target, place) {
+					diagnostics.push(error(message, ErrorCode::OutOfRange));
+				}
+			}
+		}
+		for (name, files) in undefined {
+			let mut diagnostic = Diagnostic::new(&files[0], -1, 0..0, format!("symbol `{}` is not defined in any object", name))
+				.with_code(ErrorCode::UndefinedLabel)
+				.with_hint(format!("it is used in {}", files.join(", ")));
+			if let Some(close) = parse_code::closest(&name, globals.iter().map(|g| g.0.as_str())) {
+				diagnostic = diagnostic.with_hint(format!("did you mean `{}`?", close));
+			}
+			diagnostics.push(diagnostic);
+		}
+	}
+
+	/// Every output section with its addresses and data, for loading into an emulator
+	pub fn get_sections(&self) -> Vec<Section> {
+		self.sections.iter().map(|s| s.section.clone()).collect()
+	}
+	/// Where each section of each object was placed, in address order within each output section
+	pub fn get_placements(&self) -> &[Placement] {
+		&self.placements
+	}
+	/// Every symbol of every object and of the script with its final address
+	pub fn get_symbols(&self) -> &[ElfSymbol] {
+		&self.symbols
+	}
+	/// Address the program starts running at, `_start` or the script's ENTRY
+	pub fn get_entry(&self) -> u32 {
+		self.entry
+	}
+	/// The rom image, everything stored in memory from the start of the region the first section is stored in
+	pub fn get_bin(&self) -> &Vec<u8> {
+		&self.linked_code
+	}
+	/// The linked program as an ELF executable
+	pub fn to_elf(&self) -> ElfFile {
+		ElfFile {
+			relocatable: false,
+			little_endian: self.objects.first().is_none_or(|o| o.1.little_endian),
+			entry: self.entry,
+			sections: self.sections.clone(),
+			symbols: self.symbols.clone(),
+			relocations: Vec::new(),
+		}
+	}
+	/// The linked program written in a format, ELF has every section and symbol rather than just the rom image
+	pub fn get_output(&self, format: OutputFormat) -> Vec<u8> {
+		match format {
+			OutputFormat::Elf => self.to_elf().to_bytes(),
+			_ => output::write(format, &self.linked_code, self.image_start),
+		}
+	}
+	/// Write the linked program to a file, in the format its extension names when none is given
+	pub fn write_output(&self, file_name: &str, format: Option<OutputFormat>) -> Result<(), KgemuError> {
+		let format = format.unwrap_or(OutputFormat::from_file_name(file_name));
+		fs::write(file_name, self.get_output(format)).map_err(|error| KgemuError::Io { file_name: file_name.to_string(), error })
+	}
+	/// A map of the linked program: how much of each memory it uses, where each section starts and ends
+	/// with the input sections it is made of, and the address of every symbol with the file it comes from
+	pub fn get_map(&self) -> String {
+		let device = &self.device_def;
+		let (rom, ram) = self.usage();
+		let percent = |used: u32, size: u32| if size == 0 { "-".to_string() } else { format!("{:.1}%", used as f64 * 100.0 / size as f64) };
+		let mut text = format!("kgemu map for {}, entry 0x{:08x}\n\nmemory  start       {:>10}  {:>10}  usage\n", device.name, self.entry, "used", "size");
+		text.push_str(&format!("rom     0x{:08x}  {:>10}  {:>10}  {}\n", device.rom_start, rom, device.rom_size, percent(rom, device.rom_size)));
+		text.push_str(&format!("ram     0x{:08x}  {:>10}  {:>10}  {}\n", device.ram_start, ram, device.ram_size, percent(ram, device.ram_size)));
+		// The regions of a linker script, without one they are the rom and ram above
+		if self.script.is_some() {
+			let width = self.regions.iter().map(|r| r.0.name.len()).max().unwrap_or(0).max(6);
+			text.push_str(&format!("\n{:<width$}  start       {:>10}  {:>10}  usage\n", "region", "used", "size"));
+			for (region, used) in &self.regions {
+				text.push_str(&format!("{:<width$}  0x{:08x}  {:>10}  {:>10}  {}\n", region.name, region.origin, used, region.length, percent(*used, region.length)));
+			}
+		}
+
+		let width = self.sections.iter().map(|s| s.section.name.len()).max().unwrap_or(0).max(7);
+		text.push_str(&format!("\n{:<width$}  start       end         {:>8}  stored at   from\n", "section", "size"));
+		for section in self.sections.iter().map(|s| &s.section) {
+			let stored = section.load_address.map_or("-".to_string(), |at| format!("0x{:08x}", at));
+			text.push_str(&format!("{:<width$}  0x{:08x}  0x{:08x}  {:>8}  {}\n", section.name, section.address, section.address as u64 + section.size as u64, section.size, stored));
+			for placement in self.placements.iter().filter(|p| p.output == section.name) {
+				text.push_str(&format!("{:<width$}  0x{:08x}  0x{:08x}  {:>8}  {:<10}  {}({})\n", "", placement.address, placement.address as u64 + placement.size as u64, placement.size, "", placement.file, placement.section));
+			}
+		}
+
+		// Mapping symbols only mark where code and data start
+		let mut symbols: Vec<(&ElfSymbol, &String)> = self.symbols.iter().zip(&self.symbol_files).filter(|(s, _)| !s.name.starts_with('$')).collect();
+		symbols.sort_by(|a, b| a.0.value.cmp(&b.0.value).then(a.0.name.cmp(&b.0.name)));
+		let width = symbols.iter().map(|s| s.0.name.len()).max().unwrap_or(0).max(6);
+		text.push_str(&format!("\naddress     {:<width$}  scope   section     file\n", "symbol"));
+		for (symbol, file) in symbols {
+			let scope = if symbol.global { "global" } else { "local" };
+			let row = format!("0x{:08x}  {:<width$}  {:<6}  {:<10}  {}", symbol.value, symbol.name, scope, symbol.section, file);
+			text.push_str(row.trim_end());
+			text.push('\n');
+		}
+		text
+	}
+	pub fn write_map(&self, file_name: &str) -> Result<(), KgemuError> {
+		fs::write(file_name, self.get_map()).map_err(|error| KgemuError::Io { file_name: file_name.to_string(), error })
+	}
+	/// Render an error from linking, showing the line of the linker script it is about
+	pub fn render_error(&self, error: &KgemuError, color: bool) -> String {
+		let script = self.script.as_ref();
+		error.render(|d| script.filter(|s| s.file_name == d.file_name && d.line >= 0).and_then(|s| s.text.lines().nth(d.line as usize)), color)
+	}
+}
+
+/// Fill in one relocation, bytes start at the place and already hold the addend.
+/// Thumb branches keep their distance in halfwords in the low bits of each instruction, a BL pair the high bits in the first
+fn apply(kind: RelocationKind, bytes: &mut [u8], little_endian: bool, target: u32, place: u32) -> Result<(), String> {
+	let size = match kind {
+		RelocationKind::Abs32 | RelocationKind::ThumbCall => 4,
+		RelocationKind::Abs16 | RelocationKind::ThumbJump11 | RelocationKind::ThumbJump8 => 2,
+		RelocationKind::Abs8 => 1,
+	};
+	let bytes = bytes.get_mut(..size).ok_or("the place is past the end of its section".to_string())?;
+	let read = |bytes: &[u8]| if little_endian {
+		bytes.iter().rev().fold(0u32, |value, b| value << 8 | *b as u32)
+	}
+	else {
+		bytes.iter().fold(0u32, |value, b| value << 8 | *b as u32)
+	};
+	let write = |bytes: &mut [u8], value: u32| {
+		let count = bytes.len();
+		for (i, b) in bytes.iter_mut().enumerate() {
+			let shift = if little_endian { i } else { count - 1 - i };
+			*b = (value >> (shift * 8)) as u8;
+		}
+	};
+
+	if !kind.pc_relative() {
+		let bits = size as u32 * 8;
+		let value = target as i64 + sign_extend(read(bytes), bits);
+		if bits < 32 && !(-(1i64 << (bits - 1))..1i64 << bits).contains(&value) {
+			return Err(format!("{:#x} does not fit in {} bits", value, bits));
+		}
+		write(bytes, value as u32);
+		return Ok(());
+	}
+	let field = if kind == RelocationKind::ThumbJump8 { 8 } else { 11 };
+	let mask = (1u32 << field) - 1;
+	let mut halfwords: Vec<u32> = bytes.chunks(2).map(read).collect();
+	let encoded = halfwords.iter().fold(0u32, |value, h| value << field | (h & mask));
+	let bits = field * halfwords.len() as u32 + 1;
+	let distance = target as i64 + sign_extend(encoded << 1, bits) - place as i64;
+	let (smallest, largest) = (-(1i64 << (bits - 1)), (1i64 << (bits - 1)) - 2);
+	if distance % 2 != 0 {
+		return Err(format!("the target is {} bytes away, not a multiple of 2", distance));
+	}
+	if !(smallest..=largest).contains(&distance) {
+		return Err(format!("the target is {} bytes away, the branch can reach {} to {}", distance, smallest, largest));
+	}
+	let count = halfwords.len() as u32;
+	for (i, halfword) in halfwords.iter_mut().enumerate() {
+		let shift = field * (count - 1 - i as u32);
+		*halfword = (*halfword & !mask) | ((distance >> 1) as u32 >> shift & mask);
+	}
+	for (chunk, halfword) in bytes.chunks_mut(2).zip(halfwords) {
+		write(chunk, halfword);
+	}
+	Ok(())
+}
+
+fn sign_extend(value: u32, bits: u32) -> i64 {
+	let shift = 64 - bits;
+	((value as i64) << shift) >> shift
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{compile::Complier, virtual_processor::VirtualProcessor};
+
+	fn object(source: &str) -> ElfFile {
+		let mut complier: Complier = Default::default();
+		complier.set_relocatable(true);
+		complier.compile_from_str(source).unwrap();
+		complier.to_elf()
+	}
+
+	const MAIN: &str = ".global _start\n_start:\tldr r0, =count\n\tldr r1, [r0, #0]\n\tbl add_one\n\tstr r1, [r0, #0]\n\tswi 2\n";
+	const LIBRARY: &str = ".global add_one, count\nadd_one:\tadd r1, #1\n\tbx h6\n.data\ncount:\t.word 41\n";
+
+	#[test]
+	fn link_two_objects() {
+		let mut linker: Linker = Default::default();
+		linker.add_object("main.o", object(MAIN)).unwrap();
+		linker.add_object("lib.o", object(LIBRARY)).unwrap();
+		linker.link().unwrap();
+
+		let placements: Vec<(&str, &str, u32, u32)> = linker.get_placements().iter().map(|p| (p.file.as_str(), p.section.as_str(), p.address, p.size)).collect();
+		assert_eq!(placements, vec![("main.o", ".text", 0, 16), ("lib.o", ".text", 16, 4), ("lib.o", ".data", 0x2000_0000, 4)]);
+		let symbol = |name: &str| linker.get_symbols().iter().find(|s| s.name == name).map(|s| (s.value, s.section.as_str()));
+		assert_eq!((symbol("add_one"), symbol("count")), (Some((16, ".text")), Some((0x2000_0000, ".data"))));
+		// The BL is 10 bytes before add_one, the pool entry holds the address of count
+		assert_eq!(&linker.get_bin()[4..8], &[0x00, 0xf0, 0x04, 0xf8]);
+		assert_eq!(&linker.get_bin()[12..16], &[0x00, 0x00, 0x00, 0x20]);
+
+		let mut processor: VirtualProcessor = Default::default();
+		processor.set_rom(linker.get_output(OutputFormat::Elf)).unwrap();
+		processor.run(20).unwrap();
+		assert_eq!((processor.get_register(1), processor.read_memory(0x2000_0000, 4)), (42, Ok(42)));
+	}
+
+	#[test]
+	fn symbol_errors() {
+		let mut linker: Linker = Default::default();
+		linker.add_object("main.o", object(MAIN)).unwrap();
+		linker.add_object("one.o", object(".global add_one\nadd_one:\tbx h6\n")).unwrap();
+		linker.add_object("two.o", object(".global add_one\nadd_one:\tbx h6\n\tbl add_on\n")).unwrap();
+		let error = linker.link().unwrap_err();
+		let found: Vec<(&str, &str, Option<ErrorCode>)> = error.diagnostics().iter().map(|d| (d.file_name.as_str(), d.message.as_str(), d.code)).collect();
+		assert_eq!(found, vec![
+			("two.o", "symbol `add_one` is defined more than once", Some(ErrorCode::DuplicateLabel)),
+			("main.o", "symbol `count` is not defined in any object", Some(ErrorCode::UndefinedLabel)),
+			("two.o", "symbol `add_on` is not defined in any object", Some(ErrorCode::UndefinedLabel)),
+		]);
+		assert_eq!(error.diagnostics()[0].hints, vec!["it is defined in one.o and in two.o"]);
+		assert_eq!(error.diagnostics()[2].hints, vec!["it is used in two.o", "did you mean `add_one`?"]);
+		assert_eq!(linker.render_error(&error, false).lines().nth(1), Some(" --> two.o"));
+
+		let mut linker: Linker = Default::default();
+		linker.add_object("main.o", object("\tbeq far\n")).unwrap();
+		linker.add_object("far.o", object(".global far\n\t.space 300\nfar:\tswi 0\n")).unwrap();
+		let error = linker.link().unwrap_err();
+		assert_eq!(error.diagnostics()[0].message, "R_ARM_THM_JUMP8 at .text+0x0: the target is 300 bytes away, the branch can reach -256 to 254");
+	}
+
+	#[test]
+	fn script_layout() {
+		let text = "MEMORY {\n\tFLASH : ORIGIN = 0x08000000, LENGTH = 64K\n\tSRAM : ORIGIN = 0x20000000, LENGTH = 8K\n}\nSECTIONS {\n\t.text : { main.o(.text) *(.text) } > FLASH\n\t.data ALIGN(16) : { *(.data) _edata = .; } > SRAM AT > FLASH\n\t_stack = 0x20000000 + 8192;\n}\n";
+		let mut linker: Linker = Default::default();
+		linker.set_device(DeviceDefinition { rom_start: 0x0800_0000, ..Default::default() });
+		linker.set_script(LinkerScript::parse("link.ld", text).unwrap());
+		linker.add_object("lib.o", object(LIBRARY)).unwrap();
+		linker.add_object("main.o", object(MAIN)).unwrap();
+		linker.link().unwrap();
+
+		let sections: Vec<(String, u32, Option<u32>, u32)> = linker.get_sections().into_iter().map(|s| (s.name, s.address, s.load_address, s.size)).collect();
+		assert_eq!(sections, vec![(".text".to_string(), 0x0800_0000, Some(0x0800_0000), 20), (".data".to_string(), 0x2000_0000, Some(0x0800_0020), 4)]);
+		let symbol = |name: &str| linker.get_symbols().iter().find(|s| s.name == name).map(|s| s.value);
+		assert_eq!((symbol("_start"), symbol("add_one"), symbol("_edata"), symbol("_stack")), (Some(0x0800_0000), Some(0x0800_0010), Some(0x2000_0004), Some(0x2000_2000)));
+		assert_eq!((linker.get_entry(), linker.get_bin().len()), (0x0800_0000, 0x24));
+
+		let mut linker: Linker = Default::default();
+		linker.set_script(LinkerScript::parse("link.ld", "MEMORY { ROM : ORIGIN = 0, LENGTH = 1K }\nSECTIONS { .text : { *(.text) } > ROM }\n").unwrap());
+		linker.add_object("lib.o", object(LIBRARY)).unwrap();
+		let error = linker.link().unwrap_err();
+		assert_eq!(error.diagnostics()[0].message, "section .data is not placed by the linker script");
+	}
+
+	#[test]
+	fn memory_map() {
+		let mut linker: Linker = Default::default();
+		linker.add_object("main.o", object(MAIN)).unwrap();
+		linker.add_object("lib.o", object(LIBRARY)).unwrap();
+		linker.link().unwrap();
+		let map = linker.get_map();
+		let lines: Vec<&str> = map.lines().collect();
+		assert_eq!(lines[2..5], [
+			"memory  start             used        size  usage",
+			"rom     0x00000000          24       65536  0.0%",
+			"ram     0x20000000           4       65536  0.0%",
+		]);
+		assert_eq!(lines[6..12], [
+			"section  start       end             size  stored at   from",
+			".text    0x00000000  0x00000014        20  0x00000000",
+			"         0x00000000  0x00000010        16              main.o(.text)",
+			"         0x00000010  0x00000014         4              lib.o(.text)",
+			".data    0x20000000  0x20000004         4  0x00000014",
+			"         0x20000000  0x20000004         4              lib.o(.data)",
+		]);
+		assert_eq!(lines[13..], [
+			"address     symbol   scope   section     file",
+			"0x00000000  _start   global  .text       main.o",
+			"0x00000010  add_one  global  .text       lib.o",
+			"0x20000000  count    global  .data       lib.o",
+		]);
+
+		// 24 bytes are stored in rom, the code and the start of count
+		let mut linker: Linker = Default::default();
+		linker.set_device(DeviceDefinition { rom_size: 16, ..Default::default() });
+		linker.add_object("main.o", object(MAIN)).unwrap();
+		linker.add_object("lib.o", object(LIBRARY)).unwrap();
+		let error = linker.link().unwrap_err();
+		assert_eq!(error.to_string(), "Generic Thumb: E0021: the program does not fit in rom, it needs 24 bytes and Generic Thumb has 16");
+
+		let mut linker: Linker = Default::default();
+		linker.set_script(LinkerScript::parse("link.ld", "MEMORY { ROM : ORIGIN = 0, LENGTH = 16 }\nSECTIONS { .text : { *(.text) } > ROM }\n").unwrap());
+		linker.add_object("main.o", object(MAIN)).unwrap();
+		linker.add_object("lib.o", object(".global add_one\nadd_one:\tbx h6\n")).unwrap();
+		let error = linker.link().unwrap_err();
+		assert_eq!(error.diagnostics()[0].message, "the program does not fit in ROM, it needs 18 bytes and ROM holds 16");
+		assert_eq!(error.diagnostics()[0].hints, vec!["ROM holds .text (18 bytes)"]);
+	}
+
+	#[test]
+	fn malformed_input() {
+		let mut linker: Linker = Default::default();
+		linker.set_script(LinkerScript::parse("link.ld", "MEMORY { ROM : ORIGIN = 0xfffffff0, LENGTH = 16 }\nSECTIONS { .text : { *(.text) } > ROM }\n").unwrap());
+		linker.add_object("main.o", object(".global _start\n_start:\t.space 32\n")).unwrap();
+		let error = linker.link().unwrap_err();
+		assert_eq!(error.to_string(), "link.ld: E0021: section .text ends at 0x100000010, past the end of memory");
+
+		// A relocation past the end of its section, from an object another tool wrote
+		let mut broken = object(MAIN);
+		broken.relocations[0].offset = 100;
+		let mut linker: Linker = Default::default();
+		let error = linker.add_object("main.o", broken.clone()).unwrap_err();
+		assert_eq!(error.to_string(), "main.o: a relocation at .text+0x64 is past the end of the section, which is 16 bytes");
+		linker.objects.push(("main.o".to_string(), broken));
+		linker.add_object("lib.o", object(LIBRARY)).unwrap();
+		let error = linker.link().unwrap_err();
+		assert_eq!(error.diagnostics()[0].code, Some(ErrorCode::Relocation));
+		assert!(error.diagnostics()[0].message.ends_with("the place is past the end of .text"));
+	}
+}
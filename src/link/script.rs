@@ -0,0 +1,429 @@
+//! A small linker script, written like a GNU ld script, saying which memory each output section goes in
+//!
+//! ```text
+//! MEMORY
+//! {
+//!     FLASH : ORIGIN = 0x08000000, LENGTH = 64K
+//!     SRAM : ORIGIN = 0x20000000, LENGTH = 20K
+//! }
+//! ENTRY(_start)
+//! SECTIONS
+//! {
+//!     .text : { *(.text) *(.rodata) } > FLASH
+//!     .data : { *(.data) } > SRAM AT > FLASH
+//!     .bss : { *(.bss) _ebss = .; } > SRAM
+//!     _stack_top = 0x20005000;
+//! }
+//! ```
+
+use std::{fs, ops::Range};
+
+use crate::{compile::expression, prelude::{DeviceDefinition, Diagnostic, ErrorCode, KgemuError, Region}};
+
+/// A named block of memory sections are placed in one after another
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryRegion {
+	pub name: String,
+	pub origin: u32,
+	pub length: u32,
+}
+
+/// Input sections an output section takes, like `*(.text .text.*)`.
+/// Both parts may use * for any run of characters
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InputPattern {
+	/// Object file name, * for every object
+	pub file: String,
+	pub sections: Vec<String>,
+}
+
+impl InputPattern {
+	pub fn matches(&self, file: &str, section: &str) -> bool {
+		let file_name = file.rsplit(['/', '\\']).next().unwrap_or(file);
+		(glob(&self.file, file) || glob(&self.file, file_name)) && self.sections.iter().any(|s| glob(s, section))
+	}
+}
+
+/// A symbol the script defines, `.` in the expression is the address where it is written
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Assignment {
+	pub name: String,
+	pub expression: String,
+	/// Index of the line in the script, starting at 0
+	pub line: i32,
+	/// Columns of the line the expression covers
+	pub span: Range<usize>,
+}
+
+/// What an output section holds, in order
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OutputCommand {
+	Input(InputPattern),
+	Assign(Assignment),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutputSection {
+	pub name: String,
+	/// Start on a multiple of this many bytes as well as the alignment of every input, 0 for none
+	pub align: u32,
+	pub contents: Vec<OutputCommand>,
+	/// Memory region the section runs in, the same as the section before when None
+	pub region: Option<String>,
+	/// Memory region its initial data is stored in when that differs from where it runs, like `AT > FLASH`
+	pub load_region: Option<String>,
+	pub line: i32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SectionCommand {
+	Output(OutputSection),
+	/// A symbol defined between output sections, `.` is the end of the section before
+	Assign(Assignment),
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub struct LinkerScript {
+	pub file_name: String,
+	/// The script as written, for showing the lines errors are on
+	pub text: String,
+	pub memory: Vec<MemoryRegion>,
+	/// Symbol the code starts running at, `_start` when None
+	pub entry: Option<String>,
+	pub sections: Vec<SectionCommand>,
+}
+
+/// A word or a single punctuation character of a script
+#[derive(Debug, Clone)]
+struct Token {
+	text: String,
+	line: i32,
+	span: Range<usize>,
+}
+
+impl LinkerScript {
+	/// The layout a device describes: a ROM and a RAM region, its sections in its order in the region they are placed in,
+	/// then each other section named in rom after them like the complier places them.
+	/// Ram sections that are not zeroed are stored in rom after every rom section
+	pub fn from_device(device: &DeviceDefinition, section_names: &[String]) -> Self {
+		let output = |name: &str, region: Region, load: bool| SectionCommand::Output(OutputSection {
+			name: name.to_string(),
+			align: device.placement(name).align,
+			contents: vec![OutputCommand::Input(InputPattern { file: "*".to_string(), sections: vec![name.to_string()] })],
+			region: Some(format!("{:?}", region).to_uppercase()),
+			load_region: load.then(|| "ROM".to_string()),
+			line: -1,
+		});
+		let listed = |name: &String| device.sections.iter().any(|s| &s.name == name);
+		let mut sections: Vec<SectionCommand> = device.sections.iter().filter(|s| s.region == Region::Rom).map(|s| output(&s.name, Region::Rom, false)).collect();
+		let others: Vec<&String> = section_names.iter().filter(|n| !listed(n)).collect();
+		sections.extend(others.iter().enumerate().filter(|(i, n)| !others[..*i].contains(n)).map(|(_, n)| output(n, Region::Rom, false)));
+		sections.extend(device.sections.iter().filter(|s| s.region == Region::Ram).map(|s| output(&s.name, Region::Ram, !s.uninitialized)));
+		LinkerScript {
+			file_name: String::new(),
+			text: String::new(),
+			memory: vec![
+				MemoryRegion { name: "ROM".to_string(), origin: device.rom_start, length: device.rom_size },
+				MemoryRegion { name: "RAM".to_string(), origin: device.ram_start, length: device.ram_size },
+			],
+			entry: None,
+			sections,
+		}
+	}
+
+	pub fn from_file(file_name: &str) -> Result<Self, KgemuError> {
+		let text = fs::read_to_string(file_name).map_err(|error| KgemuError::Io { file_name: file_name.to_string(), error })?;
+		LinkerScript::parse(file_name, &text)
+	}
+
+	/// Read a script, MEMORY, ENTRY and SECTIONS may come in any order
+	pub fn parse(file_name: &str, text: &str) -> Result<Self, KgemuError> {
+		let mut parser = Parser { file_name, tokens: tokenize(file_name, text)?, position: 0 };
+		let mut script = LinkerScript { file_name: file_name.to_string(), text: text.to_string(), ..Default::default() };
+		while let Some(token) = parser.next() {
+			match token.text.as_str() {
+				"MEMORY" => {
+					parser.expect("{")?;
+					while !parser.eat("}") {
+						script.memory.push(parser.region()?);
+					}
+				}
+				"ENTRY" => {
+					parser.expect("(")?;
+					script.entry = Some(parser.word()?.text);
+					parser.expect(")")?;
+				}
+				"SECTIONS" => {
+					parser.expect("{")?;
+					while !parser.eat("}") {
+						let name = parser.word()?;
+						if parser.eat("=") {
+							script.sections.push(SectionCommand::Assign(parser.assignment(name)?));
+						}
+						else {
+							script.sections.push(SectionCommand::Output(parser.output_section(name)?));
+						}
+					}
+				}
+				_ => return Err(parser.error(&token, format!("expected MEMORY, ENTRY or SECTIONS, found `{}`", token.text)).into()),
+			}
+		}
+		for section in script.sections.iter().filter_map(|s| match s { SectionCommand::Output(o) => Some(o), _ => None }) {
+			for region in section.region.iter().chain(&section.load_region) {
+				if !script.memory.iter().any(|m| &m.name == region) {
+					let line = text.lines().nth(section.line as usize).unwrap_or("");
+					let start = line.find(&format!("> {}", region)).map_or(0, |s| s + 2);
+					return Err(Diagnostic::new(file_name, section.line, start..start + region.len(), format!("there is no memory region `{}`", region))
+						.with_code(ErrorCode::LinkerScript)
+						.with_hint("regions are listed in MEMORY { NAME : ORIGIN = address, LENGTH = size }".to_string())
+						.into());
+				}
+			}
+		}
+		Ok(script)
+	}
+}
+
+/// Match text against a pattern where * stands for any run of characters
+fn glob(pattern: &str, text: &str) -> bool {
+	match pattern.split_once('*') {
+		None => pattern == text,
+		Some((start, rest)) => {
+			let Some(text) = text.strip_prefix(start) else { return false };
+			(0..=text.len()).filter(|i| text.is_char_boundary(*i)).any(|i| glob(rest, &text[i..]))
+		}
+	}
+}
+
+/// Split a script into words and punctuation, skipping /* */ comments
+fn tokenize(file_name: &str, text: &str) -> Result<Vec<Token>, Diagnostic> {
+	let word = |c: char| c.is_ascii_alphanumeric() || "_.*$/".contains(c);
+	let mut tokens = Vec::new();
+	let mut comment: Option<(i32, usize)> = None;
+	for (line, text) in text.lines().enumerate() {
+		let line = line as i32;
+		let mut chars = text.char_indices().peekable();
+		while let Some((i, c)) = chars.next() {
+			if comment.is_some() {
+				if c == '*' && chars.peek().is_some_and(|(_, n)| *n == '/') {
+					chars.next();
+					comment = None;
+				}
+				continue;
+			}
+			if c == '/' && chars.peek().is_some_and(|(_, n)| *n == '*') {
+				chars.next();
+				comment = Some((line, i));
+				continue;
+			}
+			if c.is_whitespace() {
+				continue;
+			}
+			let mut end = i + c.len_utf8();
+			if word(c) {
+				while let Some((j, n)) = chars.peek().copied().filter(|(_, n)| word(*n)) {
+					end = j + n.len_utf8();
+					chars.next();
+				}
+			}
+			tokens.push(Token { text: text[i..end].to_string(), line, span: i..end });
+		}
+	}
+	match comment {
+		Some((line, column)) => Err(Diagnostic::new(file_name, line, column..column + 2, "this comment is never closed".to_string()).with_code(ErrorCode::LinkerScript)),
+		None => Ok(tokens),
+	}
+}
+
+struct Parser<'a> {
+	file_name: &'a str,
+	tokens: Vec<Token>,
+	position: usize,
+}
+
+impl Parser<'_> {
+	fn next(&mut self) -> Option<Token> {
+		let token = self.tokens.get(self.position).cloned();
+		self.position += 1;
+		token
+	}
+
+	fn peek(&self) -> Option<&Token> {
+		self.tokens.get(self.position)
+	}
+
+	/// Move past the next token if it is text
+	fn eat(&mut self, text: &str) -> bool {
+		let found = self.peek().is_some_and(|t| t.text == text);
+		if found {
+			self.position += 1;
+		}
+		found
+	}
+
+	fn error(&self, token: &Token, message: String) -> Diagnostic {
+		Diagnostic::new(self.file_name, token.line, token.span.clone(), message).with_code(ErrorCode::LinkerScript)
+	}
+
+	/// An error at the next token, or at the end of the last one when the script ends
+	fn unexpected(&self, expected: &str) -> Diagnostic {
+		match self.peek().or(self.tokens.last()) {
+			Some(token) if self.peek().is_some() => self.error(token, format!("expected {}, found `{}`", expected, token.text)),
+			Some(token) => Diagnostic::new(self.file_name, token.line, token.span.end..token.span.end + 1, format!("expected {}, the script ends", expected)).with_code(ErrorCode::LinkerScript),
+			None => Diagnostic::new(self.file_name, 0, 0..0, format!("expected {}, the script is empty", expected)).with_code(ErrorCode::LinkerScript),
+		}
+	}
+
+	fn expect(&mut self, text: &str) -> Result<Token, Diagnostic> {
+		match self.peek() {
+			Some(token) if token.text == text => Ok(self.next().unwrap()),
+			_ => Err(self.unexpected(&format!("`{}`", text))),
+		}
+	}
+
+	fn word(&mut self) -> Result<Token, Diagnostic> {
+		match self.peek() {
+			Some(token) if token.text.starts_with(|c: char| c.is_ascii_alphanumeric() || "_.*$/".contains(c)) => Ok(self.next().unwrap()),
+			_ => Err(self.unexpected("a name")),
+		}
+	}
+
+	/// A number that may end in K or M for KiB or MiB
+	fn number(&mut self) -> Result<u32, Diagnostic> {
+		let token = self.word()?;
+		let (digits, scale) = match token.text.strip_suffix(['K', 'k']) {
+			Some(digits) => (digits, 1024),
+			None => token.text.strip_suffix(['M', 'm']).map_or((token.text.as_str(), 1), |d| (d, 1024 * 1024)),
+		};
+		expression::evaluate(digits, &|_| None).ok()
+			.and_then(|n| u32::try_from(n * scale).ok())
+			.ok_or(self.error(&token, format!("`{}` is not a number", token.text)))
+	}
+
+	/// `NAME (rx) : ORIGIN = number, LENGTH = number`, the attributes in brackets are skipped
+	fn region(&mut self) -> Result<MemoryRegion, Diagnostic> {
+		let name = self.word()?.text;
+		if self.eat("(") {
+			while !self.eat(")") {
+				self.next().ok_or(self.unexpected("`)`"))?;
+			}
+		}
+		self.expect(":")?;
+		let origin = self.field(["ORIGIN", "org", "o"])?;
+		self.eat(",");
+		let length = self.field(["LENGTH", "len", "l"])?;
+		Ok(MemoryRegion { name, origin, length })
+	}
+
+	/// `NAME = number` where the name may be any of names
+	fn field(&mut self, names: [&str; 3]) -> Result<u32, Diagnostic> {
+		if !self.peek().is_some_and(|t| names.contains(&t.text.as_str())) {
+			return Err(self.unexpected(&format!("`{}`", names[0])));
+		}
+		self.position += 1;
+		self.expect("=")?;
+		self.number()
+	}
+
+	/// The rest of `name = expression;`
+	fn assignment(&mut self, name: Token) -> Result<Assignment, Diagnostic> {
+		let mut parts: Vec<Token> = Vec::new();
+		while !self.eat(";") {
+			parts.push(self.next().ok_or(self.unexpected("`;`"))?);
+		}
+		let (Some(first), Some(last)) = (parts.first(), parts.last()) else { return Err(self.error(&name, format!("`{}` needs a value", name.text))) };
+		if name.text == "." {
+			return Err(self.error(&name, "`.` can not be moved, sections are placed one after another in their region".to_string()));
+		}
+		let end = if last.line == first.line { last.span.end } else { first.span.end };
+		Ok(Assignment {
+			name: name.text,
+			expression: parts.iter().map(|p| p.text.as_str()).collect::<Vec<&str>>().join(" "),
+			line: first.line,
+			span: first.span.start..end,
+		})
+	}
+
+	/// The rest of `name [ALIGN(n)] : { contents } [> REGION] [AT > REGION]`
+	fn output_section(&mut self, name: Token) -> Result<OutputSection, Diagnostic> {
+		let mut section = OutputSection { name: name.text.clone(), align: 0, contents: Vec::new(), region: None, load_region: None, line: name.line };
+		if self.eat("ALIGN") {
+			self.expect("(")?;
+			section.align = self.number()?;
+			self.expect(")")?;
+		}
+		self.expect(":")?;
+		self.expect("{")?;
+		while !self.eat("}") {
+			let word = self.word()?;
+			if self.eat("=") {
+				section.contents.push(OutputCommand::Assign(self.assignment(word)?));
+				continue;
+			}
+			// KEEP only stops GNU ld throwing sections away, which this linker never does
+			let keep = word.text == "KEEP" && self.eat("(");
+			let file = if keep { self.word()? } else { word };
+			self.expect("(")?;
+			let mut sections = Vec::new();
+			while !self.eat(")") {
+				sections.push(self.word()?.text);
+			}
+			if keep {
+				self.expect(")")?;
+			}
+			if sections.is_empty() {
+				return Err(self.error(&file, format!("`{}()` names no sections", file.text)));
+			}
+			section.contents.push(OutputCommand::Input(InputPattern { file: file.text, sections }));
+		}
+		if self.eat(">") {
+			section.region = Some(self.word()?.text);
+		}
+		if self.eat("AT") {
+			self.expect(">")?;
+			section.load_region = Some(self.word()?.text);
+		}
+		Ok(section)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn parse_script() {
+		let text = "/* two regions */\nMEMORY {\n\tFLASH (rx) : ORIGIN = 0x08000000, LENGTH = 64K\n\tSRAM : org = 0x20000000, len = 20K\n}\nENTRY(reset)\nSECTIONS\n{\n\t.text ALIGN(8) : { *(.text .text.*) KEEP(boot.o(.vectors)) } > FLASH\n\t.data : { *(.data) _edata = . ; } > SRAM AT > FLASH\n\t_stack = 0x20005000 - 4;\n}\n";
+		let script = LinkerScript::parse("link.ld", text).unwrap();
+		assert_eq!(script.memory, vec![
+			MemoryRegion { name: "FLASH".to_string(), origin: 0x0800_0000, length: 0x1_0000 },
+			MemoryRegion { name: "SRAM".to_string(), origin: 0x2000_0000, length: 0x5000 },
+		]);
+		assert_eq!(script.entry.as_deref(), Some("reset"));
+		let SectionCommand::Output(text_section) = &script.sections[0] else { panic!() };
+		assert_eq!((text_section.align, text_section.region.as_deref(), text_section.load_region.as_deref()), (8, Some("FLASH"), None));
+		assert_eq!(text_section.contents[1], OutputCommand::Input(InputPattern { file: "boot.o".to_string(), sections: vec![".vectors".to_string()] }));
+		let SectionCommand::Output(data_section) = &script.sections[1] else { panic!() };
+		assert_eq!(data_section.load_region.as_deref(), Some("FLASH"));
+		assert_eq!(data_section.contents[1], OutputCommand::Assign(Assignment { name: "_edata".to_string(), expression: ".".to_string(), line: 9, span: 29..30 }));
+		assert_eq!(script.sections[2], SectionCommand::Assign(Assignment { name: "_stack".to_string(), expression: "0x20005000 - 4".to_string(), line: 10, span: 10..24 }));
+
+		let pattern = InputPattern { file: "*".to_string(), sections: vec![".text.*".to_string()] };
+		assert!(pattern.matches("build/main.o", ".text.startup"));
+		assert!(!pattern.matches("build/main.o", ".text"));
+		assert!(InputPattern { file: "main.o".to_string(), sections: vec!["*".to_string()] }.matches("build/main.o", ".data"));
+	}
+
+	#[test]
+	fn bad_scripts() {
+		let error = |text: &str| match LinkerScript::parse("link.ld", text) {
+			Err(KgemuError::Diagnostics(d)) => (d[0].line, d[0].span.clone(), d[0].message.clone()),
+			other => panic!("{:?}", other),
+		};
+		assert_eq!(error("MEMORY { ROM : ORIGIN = 0, LENGTH = 1K }\nSECTIONS { .text : { *(.text) } > FLASH }"), (1, 34..39, "there is no memory region `FLASH`".to_string()));
+		assert_eq!(error("SECTIONS {\n\t.text : { *(.text) \n"), (1, 19..20, "expected a name, the script ends".to_string()));
+		assert_eq!(error("SECTIONS { . = 0x100; }"), (0, 11..12, "`.` can not be moved, sections are placed one after another in their region".to_string()));
+		assert_eq!(error("MEMORY { ROM : ORIGIN = 0, SIZE = 1K }"), (0, 27..31, "expected `LENGTH`, found `SIZE`".to_string()));
+		assert_eq!(error("/* open\n"), (0, 0..2, "this comment is never closed".to_string()));
+	}
+}
@@ -0,0 +1,532 @@
+// Execution of decoded Thumb instructions
+
+use crate::definitions::processor::SegType;
+
+use super::{StatusFlag, VirtualProcessor, LR, PC, SP};
+
+/// A decoded instruction that no longer borrows the language definition
+pub(super) struct Instruction {
+	pub op_code: String,
+	pub format: i32,
+	pub size: u32,
+	pub scale: i32,
+	pub fields: Vec<(SegType, u32)>,
+}
+
+impl Instruction {
+	/// Value of the nth field of a type, 0 when the format has no such field
+	fn field(&self, seg_type: SegType, nth: usize) -> u32 {
+		self.fields.iter().filter(|f| f.0 == seg_type).nth(nth).map(|f| f.1).unwrap_or(0)
+	}
+
+	/// Immediate or offset field multiplied back up by the command's scale
+	fn scaled(&self, seg_type: SegType) -> u32 {
+		self.field(seg_type, 0) << self.scale
+	}
+}
+
+/// Sign extend the low bits of value
+fn sign_extend(value: u32, bits: u32) -> u32 {
+	let shift = 32 - bits;
+	(((value << shift) as i32) >> shift) as u32
+}
+
+/// Shift value by an exact amount, returning the result and the carry out.
+/// A shift of 0 leaves the value alone and gives no carry, the flag keeps its old value.
+fn shift(op: &str, value: u32, amount: u32) -> (u32, Option<bool>) {
+	let bit = |n: u32| value & (1 << n) != 0;
+	match (op, amount) {
+		(_, 0) => (value, None),
+		("LSL", 1..=31) => (value << amount, Some(bit(32 - amount))),
+		("LSL", 32) => (0, Some(bit(0))),
+		("LSL", _) => (0, Some(false)),
+		("LSR", 1..=31) => (value >> amount, Some(bit(amount - 1))),
+		("LSR", 32) => (0, Some(bit(31))),
+		("LSR", _) => (0, Some(false)),
+		("ASR", 1..=31) => (((value as i32) >> amount) as u32, Some(bit(amount - 1))),
+		("ASR", _) => (((value as i32) >> 31) as u32, Some(bit(31))),
+		("ROR", _) if amount & 31 == 0 => (value, Some(bit(31))),
+		("ROR", _) => (value.rotate_right(amount & 31), Some(bit((amount & 31) - 1))),
+		_ => (value, None),
+	}
+}
+
+/// Registers set in a register list, lowest first
+fn list_registers(list: u32) -> Vec<usize> {
+	(0..8).filter(|r| list & (1 << r) != 0).collect()
+}
+
+impl VirtualProcessor {
+	/// Set N and Z from a result
+	fn set_nz(&mut self, result: u32) {
+		self.set_flag(StatusFlag::Negative, result & 0x8000_0000 != 0);
+		self.set_flag(StatusFlag::Zero, result == 0);
+	}
+
+	/// a + b + carry, setting all four flags
+	fn add_with_carry(&mut self, a: u32, b: u32, carry: bool) -> u32 {
+		let sum = a as u64 + b as u64 + carry as u64;
+		let result = sum as u32;
+		self.set_nz(result);
+		self.set_flag(StatusFlag::Carry, sum >> 32 != 0);
+		self.set_flag(StatusFlag::Overflow, (a ^ result) & (b ^ result) & 0x8000_0000 != 0);
+		result
+	}
+
+	/// a - b - !carry, carry is set when no borrow happened
+	fn subtract_with_carry(&mut self, a: u32, b: u32, carry: bool) -> u32 {
+		self.add_with_carry(a, !b, carry)
+	}
+
+	/// Shift and set N, Z and the carry out
+	fn shift_with_flags(&mut self, op: &str, value: u32, amount: u32) -> u32 {
+		let (result, carry) = shift(op, value, amount);
+		if let Some(carry) = carry {
+			self.set_flag(StatusFlag::Carry, carry);
+		}
+		self.set_nz(result);
+		result
+	}
+
+	/// Reading PC as an operand gives the address of the instruction plus 4
+	fn read_operand(&self, register: usize, address: u32) -> u32 {
+		if register == PC {
+			address.wrapping_add(4)
+		}
+		else {
+			self.get_register(register)
+		}
+	}
+
+	/// Writing PC branches, the lowest bit only selects the instruction set
+	fn write_result(&mut self, register: usize, value: u32) {
+		if register == PC {
+			self.set_register(PC, value & !1);
+		}
+		else {
+			self.set_register(register, value);
+		}
+	}
+
+	fn load(&mut self, register: usize, address: u32, size: usize, signed: bool) -> Result<(), String> {
+		let mut value = self.read_memory(address, size)?;
+		if signed {
+			value = sign_extend(value, size as u32 * 8);
+		}
+		self.write_result(register, value);
+		Ok(())
+	}
+
+	fn store(&mut self, register: usize, address: u32, size: usize) -> Result<(), String> {
+		self.write_memory(address, size, self.get_register(register))
+	}
+
+	/// Run one instruction that was fetched from address.
+	/// PC already holds the address of the next instruction.
+	pub(super) fn execute(&mut self, ins: &Instruction, address: u32) -> Result<(), String> {
+		let rd = ins.field(SegType::Destination, 0) as usize;
+		let rs = ins.field(SegType::Source, 0) as usize;
+		let op = ins.op_code.as_str();
+
+		match ins.format {
+			// move shifted register
+			1 => {
+				let value = self.get_register(rs);
+				let amount = match (op, ins.field(SegType::Immediate, 0)) {
+					// LSR and ASR by 0 encode a shift by 32, LSL by 0 leaves the carry alone
+					("LSR" | "ASR", 0) => 32,
+					(_, amount) => amount,
+				};
+				if !matches!(op, "LSL" | "LSR" | "ASR") {
+					return Err("not a shift".to_string());
+				}
+				let result = self.shift_with_flags(op, value, amount);
+				self.set_register(rd, result);
+			}
+			// add/subtract
+			2 => {
+				let operand = ins.field(SegType::Immediate, 0);
+				let operand = if ins.field(SegType::Flag, 0) == 1 { operand } else { self.get_register(operand as usize) };
+				let value = self.get_register(rs);
+				let result = if op == "SUB" { self.subtract_with_carry(value, operand, true) } else { self.add_with_carry(value, operand, false) };
+				self.set_register(rd, result);
+			}
+			// move/compare/add/subtract immediate
+			3 => {
+				let operand = ins.field(SegType::Immediate, 0);
+				let value = self.get_register(rd);
+				match op {
+					"MOV" => {
+						self.set_nz(operand);
+						self.set_register(rd, operand);
+					}
+					"ADD" => {
+						let result = self.add_with_carry(value, operand, false);
+						self.set_register(rd, result);
+					}
+					"SUB" => {
+						let result = self.subtract_with_carry(value, operand, true);
+						self.set_register(rd, result);
+					}
+					"CMP" => { self.subtract_with_carry(value, operand, true); }
+					_ => return Err("not an immediate operation".to_string()),
+				}
+			}
+			// ALU operations
+			4 => {
+				let a = self.get_register(rd);
+				let b = self.get_register(rs);
+				let carry = self.get_flag(StatusFlag::Carry);
+				let result = match op {
+					"AND" | "TST" => a & b,
+					"EOR" => a ^ b,
+					"ORR" => a | b,
+					"BIC" => a & !b,
+					"MVN" => !b,
+					"MUL" => a.wrapping_mul(b),
+					"LSL" | "LSR" | "ASR" | "ROR" => self.shift_with_flags(op, a, b & 0xff),
+					"ADC" | "CMN" => self.add_with_carry(a, b, if op == "ADC" { carry } else { false }),
+					"SBC" | "CMP" => self.subtract_with_carry(a, b, if op == "SBC" { carry } else { true }),
+					"NEG" => self.subtract_with_carry(0, b, true),
+					_ => return Err("not an ALU operation".to_string()),
+				};
+				// Logical operations and MUL only change N and Z
+				self.set_nz(result);
+				if !matches!(op, "TST" | "CMP" | "CMN") {
+					self.set_register(rd, result);
+				}
+			}
+			// hi register operations/branch exchange
+			5 => {
+				let rd = rd + 8 * ins.field(SegType::Flag, 0) as usize;
+				let rs = rs + 8 * ins.field(SegType::Flag, 1) as usize;
+				let value = self.read_operand(rs, address);
+				match op {
+					"ADD" => {
+						let result = self.read_operand(rd, address).wrapping_add(value);
+						self.write_result(rd, result);
+					}
+					"MOV" => self.write_result(rd, value),
+					"CMP" => {
+						let a = self.read_operand(rd, address);
+						self.subtract_with_carry(a, value, true);
+					}
+					"BX" => {
+						if value & 1 == 0 {
+							return Err("switching to ARM state is not supported".to_string());
+						}
+						self.set_register(PC, value & !1);
+					}
+					_ => return Err("not a hi register operation".to_string()),
+				}
+			}
+			// PC-relative load
+			6 => {
+				let base = address.wrapping_add(4) & !3;
+				self.load(rd, base.wrapping_add(ins.scaled(SegType::Immediate)), 4, false)?;
+			}
+			// load/store with register offset, load/store sign-extended byte/halfword
+			7 | 8 => {
+				let target = self.get_register(rs).wrapping_add(self.get_register(ins.field(SegType::Offset, 0) as usize));
+				match op {
+					"STR" => self.store(rd, target, 4)?,
+					"STRB" => self.store(rd, target, 1)?,
+					"STRH" => self.store(rd, target, 2)?,
+					"LDR" => self.load(rd, target, 4, false)?,
+					"LDRB" => self.load(rd, target, 1, false)?,
+					"LDRH" => self.load(rd, target, 2, false)?,
+					"LDSB" => self.load(rd, target, 1, true)?,
+					"LDSH" => self.load(rd, target, 2, true)?,
+					_ => return Err("not a load or store".to_string()),
+				}
+			}
+			// load/store with immediate offset, load/store halfword
+			9 | 10 => {
+				let target = self.get_register(rs).wrapping_add(ins.scaled(SegType::Offset));
+				match op {
+					"STR" => self.store(rd, target, 4)?,
+					"STRB" => self.store(rd, target, 1)?,
+					"STRH" => self.store(rd, target, 2)?,
+					"LDR" => self.load(rd, target, 4, false)?,
+					"LDRB" => self.load(rd, target, 1, false)?,
+					"LDRH" => self.load(rd, target, 2, false)?,
+					_ => return Err("not a load or store".to_string()),
+				}
+			}
+			// SP-relative load/store
+			11 => {
+				let target = self.get_register(SP).wrapping_add(ins.scaled(SegType::Offset));
+				match op {
+					"STR" => self.store(rd, target, 4)?,
+					"LDR" => self.load(rd, target, 4, false)?,
+					_ => return Err("not a load or store".to_string()),
+				}
+			}
+			// load address
+			12 => {
+				let base = if ins.field(SegType::Flag, 0) == 1 { self.get_register(SP) } else { address.wrapping_add(4) & !3 };
+				self.set_register(rd, base.wrapping_add(ins.scaled(SegType::Immediate)));
+			}
+			// add offset to stack pointer
+			13 => {
+				let offset = ins.scaled(SegType::Immediate);
+				let sp = self.get_register(SP);
+				let sp = if ins.field(SegType::Flag, 0) == 1 { sp.wrapping_sub(offset) } else { sp.wrapping_add(offset) };
+				self.set_register(SP, sp);
+			}
+			// push/pop registers
+			14 => {
+				let mut registers = list_registers(ins.field(SegType::RegisterList, 0));
+				let extra = ins.field(SegType::Flag, 1) == 1;
+				match op {
+					"PUSH" => {
+						if extra {
+							registers.push(LR);
+						}
+						let mut sp = self.get_register(SP).wrapping_sub(4 * registers.len() as u32);
+						self.set_register(SP, sp);
+						for r in registers {
+							self.store(r, sp, 4)?;
+							sp = sp.wrapping_add(4);
+						}
+					}
+					"POP" => {
+						if extra {
+							registers.push(PC);
+						}
+						let mut sp = self.get_register(SP);
+						for r in registers {
+							self.load(r, sp, 4, false)?;
+							sp = sp.wrapping_add(4);
+						}
+						self.set_register(SP, sp);
+					}
+					_ => return Err("not a push or pop".to_string()),
+				}
+			}
+			// multiple load/store
+			15 => {
+				let mut base = self.get_register(rs);
+				for r in list_registers(ins.field(SegType::RegisterList, 0)) {
+					match op {
+						"STMIA" => self.store(r, base, 4)?,
+						"LDMIA" => self.load(r, base, 4, false)?,
+						_ => return Err("not a multiple load or store".to_string()),
+					}
+					base = base.wrapping_add(4);
+				}
+				self.set_register(rs, base);
+			}
+			// conditional branch
+			16 => {
+				if self.condition_passed(ins.field(SegType::Condition, 0)) {
+					let offset = sign_extend(ins.field(SegType::Offset, 0), 8) << 1;
+					self.set_register(PC, address.wrapping_add(4).wrapping_add(offset));
+				}
+			}
+			// software interrupt
+			17 => {
+				self.interrupt = Some(ins.field(SegType::Immediate, 0));
+				self.halted = true;
+			}
+			// unconditional branch
+			18 => {
+				let offset = sign_extend(ins.field(SegType::Offset, 0), 11) << 1;
+				self.set_register(PC, address.wrapping_add(4).wrapping_add(offset));
+			}
+			// long branch with link
+			19 => {
+				let offset = ins.field(SegType::Offset, 0);
+				if ins.field(SegType::Flag, 0) == 0 {
+					self.set_register(LR, address.wrapping_add(4).wrapping_add(sign_extend(offset, 11) << 12));
+				}
+				else {
+					let target = self.get_register(LR).wrapping_add(offset << 1);
+					self.set_register(LR, address.wrapping_add(2) | 1);
+					self.set_register(PC, target);
+				}
+			}
+			_ => return Err(format!("format {} can not be executed", ins.format)),
+		}
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::{compile::{output::{self, OutputFormat}, Complier}, virtual_processor::{StatusFlag, VirtualProcessor, SP}};
+
+	fn run(source: &str) -> VirtualProcessor {
+		let mut complier: Complier = Default::default();
+		complier.compile_from_str(source).unwrap();
+		let mut processor: VirtualProcessor = Default::default();
+		processor.load_sections(complier.get_sections());
+		processor.run(1000).unwrap();
+		processor
+	}
+
+	#[test]
+	fn arithmetic() {
+		let processor = run("mov r0, #200\nmov r1, #100\nadd r2, r0, r1\nsub r3, r1, r0\nlsl r4, r0, #4\nasr r5, r3, #1\nmul r1, r0\nmvn r6, r0");
+		assert_eq!(processor.get_register(2), 300);
+		assert_eq!(processor.get_register(3), (-100i32) as u32);
+		assert_eq!(processor.get_register(4), 3200);
+		assert_eq!(processor.get_register(5), (-50i32) as u32);
+		assert_eq!(processor.get_register(1), 20000);
+		assert_eq!(processor.get_register(6), !200);
+		assert!(processor.is_halted());
+		assert_eq!(processor.get_steps(), 8);
+	}
+
+	#[test]
+	fn literal_loads() {
+		let processor = run("ldr r0, =0xDEADBEEF\nldr r1, =data\nldr r2, [r1, #0]\nswi 0\n.data\ndata: .word 42");
+		assert_eq!(processor.get_register(0), 0xdead_beef);
+		assert_eq!(processor.get_register(1), 0x2000_0000);
+		assert_eq!(processor.get_register(2), 42);
+	}
+
+	#[test]
+	fn memory_and_stack() {
+		let processor = run("mov r1, #32\nlsl r1, r1, #24\nmov r0, #0xab\nstr r0, [r1, #4]\nstrb r0, [r1, #9]\nldrh r2, [r1, #4]\nmov r4, #9\nldsb r3, [r1, r4]\nmov r4, #7\npush {r0, r4, lr}\npop {r5, r6}\nswi 3");
+		assert_eq!(&processor.get_ram()[4..10], &[0xab, 0, 0, 0, 0, 0xab]);
+		assert_eq!(processor.get_register(2), 0xab);
+		assert_eq!(processor.get_register(3), 0xffff_ffab);
+		assert_eq!((processor.get_register(5), processor.get_register(6)), (0xab, 7));
+		assert_eq!(processor.get_register(SP), 0x2000_fffc);
+		assert_eq!(processor.get_interrupt(), Some(3));
+	}
+
+	#[test]
+	fn add_and_subtract_flags() {
+		// 0x7fffffff + 1 overflows into the sign bit
+		let processor = run("mov r0, #1\nlsl r1, r0, #31\nsub r1, #1\nadd r2, r1, #1");
+		assert_eq!(processor.get_register(2), 0x8000_0000);
+		assert!(processor.get_flag(StatusFlag::Negative) && processor.get_flag(StatusFlag::Overflow));
+		assert!(!processor.get_flag(StatusFlag::Carry) && !processor.get_flag(StatusFlag::Zero));
+
+		// Equal values compare as zero with no borrow
+		let processor = run("mov r0, #5\ncmp r0, #5");
+		assert!(processor.get_flag(StatusFlag::Zero) && processor.get_flag(StatusFlag::Carry));
+
+		// 64 bit add with the carry from the low word
+		let processor = run("mov r0, #0\nmvn r0, r0\nmov r1, #1\nmov r2, #0\nmov r3, #0\nadd r0, r0, r1\nadc r2, r3");
+		assert_eq!((processor.get_register(0), processor.get_register(2)), (0, 1));
+		assert!(!processor.get_flag(StatusFlag::Carry));
+
+		// 0 - 1 borrows, SBC then takes the borrow away
+		let processor = run("mov r0, #0\nsub r0, #1\nmov r1, #10\nmov r2, #3\nsbc r1, r2");
+		assert_eq!(processor.get_register(1), 6);
+
+		let processor = run("mov r0, #3\nneg r1, r0");
+		assert_eq!(processor.get_register(1), (-3i32) as u32);
+		assert!(processor.get_flag(StatusFlag::Negative) && !processor.get_flag(StatusFlag::Carry));
+	}
+
+	#[test]
+	fn shift_carry() {
+		// LSL #0 keeps the carry, LSR #0 is a shift by 32
+		let processor = run("mov r0, #5\ncmp r0, #5\nmov r1, #1\nlsl r2, r1, #0");
+		assert!(processor.get_flag(StatusFlag::Carry) && processor.get_register(2) == 1);
+		let processor = run("mov r0, #1\nlsl r0, r0, #31\nlsr r1, r0, #0");
+		assert!(processor.get_flag(StatusFlag::Carry) && processor.get_flag(StatusFlag::Zero));
+		let processor = run("mov r0, #1\nlsl r0, r0, #31\nasr r1, r0, #0");
+		assert_eq!(processor.get_register(1), 0xffff_ffff);
+		assert!(processor.get_flag(StatusFlag::Carry) && processor.get_flag(StatusFlag::Negative));
+
+		// Register shifts by 0 leave the carry alone, by 32 and more clear the value
+		let processor = run("mov r0, #3\nmov r1, #0\nmov r2, #2\nlsr r0, r2\nmov r3, #3\nlsr r3, r1");
+		assert!(processor.get_flag(StatusFlag::Carry) && processor.get_register(3) == 3);
+		let processor = run("mov r0, #1\nmov r1, #32\nlsl r0, r1");
+		assert!(processor.get_flag(StatusFlag::Carry) && processor.get_flag(StatusFlag::Zero));
+		let processor = run("mov r0, #1\nmov r1, #33\nlsl r0, r1");
+		assert!(!processor.get_flag(StatusFlag::Carry));
+		let processor = run("mov r0, #3\nmov r1, #1\nror r0, r1");
+		assert_eq!(processor.get_register(0), 0x8000_0001);
+		assert!(processor.get_flag(StatusFlag::Carry) && processor.get_flag(StatusFlag::Negative));
+	}
+
+	#[test]
+	fn conditional_branch() {
+		// mov r0, #0; beq +0; mov r1, #1; mov r2, #2
+		let mut processor: VirtualProcessor = Default::default();
+		processor.set_rom(vec![0x00, 0x20, 0x00, 0xd0, 0x01, 0x21, 0x02, 0x22]).unwrap();
+		processor.run(10).unwrap();
+		assert_eq!((processor.get_register(1), processor.get_register(2)), (0, 2));
+		// bne falls through
+		processor.set_rom(vec![0x00, 0x20, 0x00, 0xd1, 0x01, 0x21, 0x02, 0x22]).unwrap();
+		processor.reset();
+		processor.run(10).unwrap();
+		assert_eq!((processor.get_register(1), processor.get_register(2)), (1, 2));
+	}
+
+	#[test]
+	fn rom_images() {
+		let mut complier: Complier = Default::default();
+		complier.compile_from_str("\tmov r0, #7\n\tadd r0, #5\n\tswi 2\n").unwrap();
+		for format in [OutputFormat::Binary, OutputFormat::IntelHex, OutputFormat::Srec, OutputFormat::HexDump] {
+			let mut processor: VirtualProcessor = Default::default();
+			processor.set_rom(complier.get_output(format)).unwrap();
+			processor.run(10).unwrap();
+			assert_eq!((processor.get_register(0), processor.get_interrupt()), (12, Some(2)), "{}", format);
+		}
+
+		let mut processor: VirtualProcessor = Default::default();
+		let past_rom = output::to_intel_hex(&[0x00, 0xdf], 0xffff);
+		assert_eq!(processor.set_rom(past_rom.into_bytes()), Err("the image ends at 00010001, past the end of rom at 00010000".to_string()));
+		processor.set_rom(b":0000000000\n:01010000AA54\n:00000001FF\n".to_vec()).unwrap();
+		assert_eq!(processor.read_memory(0x100, 1), Ok(0xaa));
+
+		// mov r0, #0x3a; mov r1, #0x30 starts like Intel HEX but is code
+		processor.set_rom(vec![0x3a, 0x20, 0x30, 0x21]).unwrap();
+		processor.reset();
+		processor.run(10).unwrap();
+		assert_eq!((processor.get_register(0), processor.get_register(1)), (0x3a, 0x30));
+		assert_eq!(processor.set_rom_as(vec![0x3a, 0x20, 0x30, 0x21], OutputFormat::IntelHex), Err("line 1: a record is written in hex digits".to_string()));
+	}
+
+	#[test]
+	fn elf_executables() {
+		let mut complier: Complier = Default::default();
+		let source = "skip:\tswi 9\n_start:\tldr r1, =value\n\tldr r0, [r1, #0]\n\tswi 2\n.data\nvalue:\t.word 42\n";
+		complier.compile_from_str(source).unwrap();
+		let mut processor: VirtualProcessor = Default::default();
+		processor.set_rom(complier.get_output(OutputFormat::Elf)).unwrap();
+		assert_eq!(processor.get_register(15), 2);
+		processor.run(10).unwrap();
+		assert_eq!((processor.get_register(0), processor.get_interrupt()), (42, Some(2)));
+
+		complier.set_relocatable(true);
+		complier.compile_from_str(source).unwrap();
+		assert_eq!(processor.load_elf(&complier.get_output(OutputFormat::Elf)), Err("a relocatable object has to be linked before it can run".to_string()));
+	}
+
+	#[test]
+	fn branch_and_link_to_labels() {
+		let processor = run("\tmov r0, #0\n\tmov r1, #5\nloop:\tbl add_two\n\tsub r1, #1\n\tbne loop\n\tswi 1\nadd_two:\tadd r0, #2\n\tbx h6");
+		assert_eq!(processor.get_register(0), 10);
+		assert_eq!(processor.get_interrupt(), Some(1));
+	}
+
+	#[test]
+	fn initialized_data_is_copied_to_ram() {
+		let mut processor = run("\tldr r1, [pc, #8]\n\tldr r0, [r1, #0]\n\tadd r0, #1\n\tstr r0, [r1, #4]\n\tswi 0\n.align 2\n.word counter\n.data\ncounter: .word 41\n.bss\ncopy: .space 4");
+		assert_eq!(processor.get_register(0), 42);
+		assert_eq!(&processor.get_ram()[0..8], &[41, 0, 0, 0, 42, 0, 0, 0]);
+		processor.reset();
+		assert_eq!(&processor.get_ram()[0..8], &[41, 0, 0, 0, 0, 0, 0, 0]);
+	}
+
+	#[test]
+	fn condition_codes() {
+		let mut processor: VirtualProcessor = Default::default();
+		processor.set_flag(StatusFlag::Zero, true);
+		assert!(processor.condition_passed(0) && !processor.condition_passed(1));
+		assert!(processor.condition_passed(9) && !processor.condition_passed(8));
+		processor.set_status(0);
+		processor.set_flag(StatusFlag::Negative, true);
+		assert!(processor.condition_passed(11) && processor.condition_passed(13) && !processor.condition_passed(10));
+		processor.set_flag(StatusFlag::Overflow, true);
+		assert!(processor.condition_passed(10) && processor.condition_passed(12));
+	}
+}
@@ -1,21 +1,369 @@
 
 
+use crate::{compile::{disassemble, elf::{self, ElfFile}, output::{self, OutputFormat}, Section}, definitions::{device::DeviceDefinition, language::LanguageDefinition}, error::KgemuError};
+
+mod execute;
+
+/// Stack pointer register
+pub const SP: usize = 13;
+/// Link register, holds the return address after a long branch with link
+pub const LR: usize = 14;
+/// Program counter register
+pub const PC: usize = 15;
+
+/// Condition flags, each is the bit it is kept in in the program status register
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatusFlag {
+	Negative = 31,
+	Zero = 30,
+	Carry = 29,
+	Overflow = 28,
+}
+
 pub struct VirtualProcessor {
 	pub name: String,
 	pub clock_speed: i32,
-	//pub language: LanguageDefinition,
+	pub language: LanguageDefinition,
 	pub num_registers: i32,
+	/// Size of each register in bytes
 	pub registers_size: i32,
 	registers: Vec<Vec<u8>>,
+	/// Program status register, holds the condition flags
+	status: u32,
 	pub ram_size: i32,
+	/// Address of the first byte of ram
+	pub ram_start: u32,
 	ram_data: Vec<u8>,
 	pub rom_size: i32,
+	/// Address of the first byte of rom, the code starts running here
+	pub rom_start: u32,
 	rom_data: Vec<u8>,
+	/// Initial data copied from rom to ram at reset, as (rom address, ram address, size)
+	copy_table: Vec<(u32, u32, u32)>,
+	/// Address to start running at instead of rom_start, from an ELF file
+	entry: Option<u32>,
+	halted: bool,
+	steps: u64,
+	interrupt: Option<u32>,
+}
 
+impl Default for VirtualProcessor {
+	fn default() -> Self {
+		VirtualProcessor::new(Default::default())
+	}
 }
 
 impl VirtualProcessor {
-	pub fn set_rom(&mut self, data: Vec<u8>) {
-		self.rom_data = data;
+	/// A processor with 64KiB of rom and ram running code written in language
+	pub fn new(language: LanguageDefinition) -> Self {
+		let mut processor = VirtualProcessor {
+			name: language.processor_def.name.clone(),
+			clock_speed: 16_000_000,
+			num_registers: language.processor_def.num_register,
+			registers_size: 4,
+			language,
+			registers: Vec::new(),
+			status: 0,
+			ram_size: 0x10000,
+			ram_start: 0x2000_0000,
+			ram_data: Vec::new(),
+			rom_size: 0x10000,
+			rom_start: 0,
+			rom_data: Vec::new(),
+			copy_table: Vec::new(),
+			entry: None,
+			halted: false,
+			steps: 0,
+			interrupt: None,
+		};
+		processor.reset();
+		processor
+	}
+
+	/// Load a rom image in any format compile::output can write, worked out from its contents.
+	/// Raw binary starts at rom_start, the other formats say where their bytes go and have to be inside rom.
+	/// Nothing is copied to ram at reset, an image does not say which bytes are initial data, except an ELF file which is loaded with load_elf
+	pub fn set_rom(&mut self, data: Vec<u8>) -> Result<(), String> {
+		if elf::is_elf(&data) {
+			return self.load_elf(&data);
+		}
+		let image = output::read(&data, self.rom_start, self.rom_size as u32)?;
+		self.place_rom(image)
+	}
+
+	/// Load a rom image known to be in format, like set_rom
+	pub fn set_rom_as(&mut self, data: Vec<u8>, format: OutputFormat) -> Result<(), String> {
+		if format == OutputFormat::Elf {
+			return self.load_elf(&data);
+		}
+		let image = output::read_as(format, &data, self.rom_start, self.rom_size as u32)?;
+		self.place_rom(image)
+	}
+
+	/// Put an image that starts at start in rom
+	fn place_rom(&mut self, (start, bytes): (u32, Vec<u8>)) -> Result<(), String> {
+		let offset = start.checked_sub(self.rom_start)
+			.ok_or(format!("the image starts at {:08x}, before rom at {:08x}", start, self.rom_start))? as usize;
+		if offset + bytes.len() > self.rom_size as usize {
+			return Err(format!("the image ends at {:08x}, past the end of rom at {:08x}", start as usize + bytes.len(), self.rom_start as usize + self.rom_size as usize));
+		}
+		self.rom_data = vec![0; offset];
+		self.rom_data.extend(bytes);
+		self.copy_table.clear();
+		self.entry = None;
+		Ok(())
+	}
+
+	/// Load an ELF executable, storing each section in rom where its program header says and copying ram sections at reset,
+	/// then reset to start running at its entry point
+	pub fn load_elf(&mut self, data: &[u8]) -> Result<(), String> {
+		let file = ElfFile::parse(data)?;
+		if file.relocatable {
+			return Err("a relocatable object has to be linked before it can run".to_string());
+		}
+		let sections: Vec<Section> = file.sections.into_iter().map(|s| s.section).collect();
+		for section in &sections {
+			let Some(load_address) = section.load_address else { continue };
+			let end = load_address as u64 + section.data.len() as u64;
+			if load_address < self.rom_start || end > self.rom_start as u64 + self.rom_size as u64 {
+				return Err(format!("{} is stored at {:08x} to {:08x}, outside of rom", section.name, load_address, end));
+			}
+		}
+		self.load_sections(&sections);
+		// Thumb code is entered with bit 0 of the address set
+		self.entry = Some(file.entry & !1);
+		self.reset();
+		Ok(())
+	}
+
+	/// Load a rom image from a file in the format its extension names, or worked out from its contents when it names none, then reset
+	pub fn load_rom_file(&mut self, file_name: &str) -> Result<(), KgemuError> {
+		let data = std::fs::read(file_name).map_err(|error| KgemuError::Io { file_name: file_name.to_string(), error })?;
+		let loaded = match file_name.rsplit_once('.').and_then(|e| e.1.parse::<OutputFormat>().ok()) {
+			Some(format) => self.set_rom_as(data, format),
+			None => self.set_rom(data),
+		};
+		loaded.map_err(|message| KgemuError::Image { file_name: file_name.to_string(), message })?;
+		self.reset();
+		Ok(())
+	}
+
+	/// Use the memory map of a device, then reset
+	pub fn set_device(&mut self, device: &DeviceDefinition) {
+		self.rom_start = device.rom_start;
+		self.rom_size = device.rom_size as i32;
+		self.ram_start = device.ram_start;
+		self.ram_size = device.ram_size as i32;
+		self.reset();
+	}
+
+	/// Load every section stored in rom and remember which ones are copied to ram at reset, then reset
+	pub fn load_sections(&mut self, sections: &[Section]) {
+		self.rom_data.clear();
+		self.copy_table.clear();
+		self.entry = None;
+		for section in sections {
+			let Some(load_address) = section.load_address else { continue };
+			let start = load_address.wrapping_sub(self.rom_start) as usize;
+			let end = start + section.data.len();
+			if self.rom_data.len() < end {
+				self.rom_data.resize(end, 0);
+			}
+			self.rom_data[start..end].copy_from_slice(&section.data);
+			if load_address != section.address {
+				self.copy_table.push((load_address, section.address, section.size));
+			}
+		}
+		self.reset();
+	}
+
+	/// Clear registers and ram, copy initial data to ram, point the stack at the top of ram and start again from rom_start or the ELF entry point
+	pub fn reset(&mut self) {
+		self.registers = vec![vec![0; self.registers_size as usize]; self.num_registers as usize];
+		self.ram_data = vec![0; self.ram_size as usize];
+		for &(load_address, address, size) in &self.copy_table {
+			let from = load_address.wrapping_sub(self.rom_start) as usize;
+			let to = address.wrapping_sub(self.ram_start) as usize;
+			let size = (size as usize).min(self.ram_data.len().saturating_sub(to)).min(self.rom_data.len().saturating_sub(from));
+			self.ram_data[to..to + size].copy_from_slice(&self.rom_data[from..from + size]);
+		}
+		self.set_register(SP, self.ram_start.wrapping_add(self.ram_size as u32));
+		self.set_register(PC, self.entry.unwrap_or(self.rom_start));
+		self.status = 0;
+		self.halted = false;
+		self.steps = 0;
+		self.interrupt = None;
+	}
+
+	/// Fetch, decode and execute the instruction at PC.
+	/// Running past the end of the loaded rom halts the processor.
+	pub fn step(&mut self) -> Result<(), String> {
+		if self.halted {
+			return Ok(());
+		}
+		let address = self.get_register(PC);
+		let offset = match address.checked_sub(self.rom_start) {
+			Some(offset) if (offset as usize) < self.rom_data.len() => offset as usize,
+			_ => {
+				self.halted = true;
+				return Ok(());
+			}
+		};
+
+		let end = self.rom_data.len().min(offset + 4);
+		let instruction = {
+			let decoded = disassemble::decode(&self.language, &self.rom_data[offset..end])
+				.ok_or(format!("{:08x}: unknown instruction", address))?;
+			execute::Instruction {
+				op_code: decoded.op_code.to_string(),
+				format: decoded.format.id,
+				size: decoded.format.size() as u32,
+				scale: decoded.command.scale,
+				fields: decoded.fields,
+			}
+		};
+
+		self.set_register(PC, address.wrapping_add(instruction.size));
+		self.execute(&instruction, address)
+			.map_err(|e| format!("{:08x}: {}: {}", address, instruction.op_code, e))?;
+		self.steps += 1;
+		Ok(())
+	}
+
+	/// Step until the processor halts or max_steps instructions have run, returns the number run
+	pub fn run(&mut self, max_steps: u64) -> Result<u64, String> {
+		let start = self.steps;
+		while !self.halted && self.steps - start < max_steps {
+			self.step()?;
+		}
+		Ok(self.steps - start)
+	}
+
+	pub fn is_halted(&self) -> bool {
+		self.halted
+	}
+
+	/// Instructions executed since reset
+	pub fn get_steps(&self) -> u64 {
+		self.steps
+	}
+
+	/// Comment value of the software interrupt that halted the processor
+	pub fn get_interrupt(&self) -> Option<u32> {
+		self.interrupt
+	}
+
+	pub fn get_register(&self, register: usize) -> u32 {
+		let bytes = &self.registers[register];
+		bytes.iter().rev().fold(0, |value, b| value << 8 | *b as u32)
+	}
+
+	pub fn set_register(&mut self, register: usize, value: u32) {
+		for (i, b) in self.registers[register].iter_mut().enumerate() {
+			*b = (value >> (i * 8)) as u8;
+		}
+	}
+
+	pub fn get_status(&self) -> u32 {
+		self.status
+	}
+
+	pub fn set_status(&mut self, status: u32) {
+		self.status = status;
+	}
+
+	pub fn get_flag(&self, flag: StatusFlag) -> bool {
+		self.status & (1 << flag as u32) != 0
 	}
-}
\ No newline at end of file
+
+	pub fn set_flag(&mut self, flag: StatusFlag, set: bool) {
+		if set {
+			self.status |= 1 << flag as u32;
+		}
+		else {
+			self.status &= !(1 << flag as u32);
+		}
+	}
+
+	/// Check a condition code against the flags, codes follow the ARM order EQ, NE, CS, CC ... LE, AL
+	pub fn condition_passed(&self, condition: u32) -> bool {
+		let n = self.get_flag(StatusFlag::Negative);
+		let z = self.get_flag(StatusFlag::Zero);
+		let c = self.get_flag(StatusFlag::Carry);
+		let v = self.get_flag(StatusFlag::Overflow);
+		match condition {
+			0 => z,
+			1 => !z,
+			2 => c,
+			3 => !c,
+			4 => n,
+			5 => !n,
+			6 => v,
+			7 => !v,
+			8 => c && !z,
+			9 => !c || z,
+			10 => n == v,
+			11 => n != v,
+			12 => !z && n == v,
+			13 => z || n != v,
+			_ => true,
+		}
+	}
+
+	pub fn get_ram(&self) -> &Vec<u8> {
+		&self.ram_data
+	}
+
+	/// Read size bytes from rom or ram
+	pub fn read_memory(&self, address: u32, size: usize) -> Result<u32, String> {
+		if !(address as usize).is_multiple_of(size) {
+			return Err(format!("unaligned {} byte read at {:08x}", size, address));
+		}
+		let offset = address.wrapping_sub(self.rom_start) as u64;
+		let data = if address >= self.rom_start && offset + (size as u64) <= self.rom_size as u64 {
+			let start = (offset as usize).min(self.rom_data.len());
+			let end = (offset as usize + size).min(self.rom_data.len());
+			let mut bytes = self.rom_data[start..end].to_vec();
+			bytes.resize(size, 0);
+			bytes
+		}
+		else {
+			self.ram_range(address, size)
+				.map(|r| self.ram_data[r].to_vec())
+				.ok_or(format!("read of unmapped address {:08x}", address))?
+		};
+		Ok(self.value_from_bytes(&data))
+	}
+
+	/// Write the low size bytes of value to ram
+	pub fn write_memory(&mut self, address: u32, size: usize, value: u32) -> Result<(), String> {
+		if !(address as usize).is_multiple_of(size) {
+			return Err(format!("unaligned {} byte write at {:08x}", size, address));
+		}
+		let range = self.ram_range(address, size)
+			.ok_or(format!("write to {:08x} which is not in ram", address))?;
+		let little_endian = self.language.processor_def.little_endian;
+		for (i, b) in self.ram_data[range].iter_mut().enumerate() {
+			let shift = if little_endian { i } else { size - 1 - i };
+			*b = (value >> (shift * 8)) as u8;
+		}
+		Ok(())
+	}
+
+	fn ram_range(&self, address: u32, size: usize) -> Option<std::ops::Range<usize>> {
+		let offset = address.checked_sub(self.ram_start)? as usize;
+		if offset + size > self.ram_data.len() {
+			return None;
+		}
+		Some(offset..offset + size)
+	}
+
+	fn value_from_bytes(&self, bytes: &[u8]) -> u32 {
+		if self.language.processor_def.little_endian {
+			bytes.iter().rev().fold(0, |value, b| value << 8 | *b as u32)
+		}
+		else {
+			bytes.iter().fold(0, |value, b| value << 8 | *b as u32)
+		}
+	}
+}